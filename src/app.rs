@@ -1,10 +1,23 @@
-use std::{net::SocketAddr, thread};
+use std::{
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    sync::Arc,
+    thread,
+};
 
 use anyhow::Result;
 use chrono::Duration;
 use once_cell::sync::OnceCell;
 use prometheus::{Encoder, Registry, TextEncoder};
+use serde_json::{json, Value as JsonValue};
 
+use crate::catalog::Catalog;
+use crate::disk_space::{self, Level};
+use crate::janitor;
+use crate::message_handler::handle_http_request;
+use crate::recovery;
+use crate::switchboard::StreamId;
+use crate::upload_queue::UploadQueue;
 use crate::{conf::Config, recorder::recorder};
 use crate::{message_handler::JanusSender, recorder::RecorderHandlesCreator};
 use crate::{metrics::Metrics, switchboard::LockedSwitchboard as Switchboard};
@@ -26,6 +39,8 @@ pub struct App {
     pub recorders_creator: RecorderHandlesCreator,
     pub janus_sender: JanusSender,
     pub metrics: Metrics,
+    pub upload_queue: UploadQueue,
+    pub catalog: Arc<Catalog>,
 }
 
 impl App {
@@ -34,18 +49,70 @@ impl App {
             svc_error::extension::sentry::init(sentry_config);
             info!("Sentry initialized");
         }
-        let (recorder, handles_creator) =
-            recorder(config.recordings.clone(), config.metrics.clone());
+        let catalog = Arc::new(Catalog::new(Path::new(&config.recordings.directory))?);
+
+        let (recorders, handles_creator) = recorder(
+            config.recordings.clone(),
+            config.metrics.clone(),
+            catalog.clone(),
+        );
         let metrics_registry = Registry::new();
         let metrics = Metrics::new(&metrics_registry)?;
-        async_std::task::spawn(start_metrics_collector(
-            metrics_registry,
-            config.metrics.bind_addr,
-        ));
+        let http_state = HttpState {
+            registry: metrics_registry,
+            api_token: config.http_api.as_ref().map(|api| api.token.clone()),
+        };
+        async_std::task::spawn(start_http_server(http_state, config.metrics.bind_addr));
+
+        // Nothing is being recorded yet so every unfinished segment is left by a crash.
+        let recordings_dir = Path::new(&config.recordings.directory);
+
+        if recordings_dir.exists() {
+            let encryption = config.recordings.encryption.as_ref();
+
+            match recovery::recover_all(recordings_dir, encryption) {
+                Ok(stats) if stats.segments > 0 => warn!(
+                    "Recovered {} unfinished segments after a crash: {} frames, {} bytes truncated",
+                    stats.segments, stats.frames, stats.truncated_bytes
+                ),
+                Ok(_) => (),
+                Err(err) => err!("Failed to recover unfinished recordings: {:#}", err),
+            }
+        }
 
-        let app = App::new(config, handles_creator, metrics)?;
+        let upload_queue = UploadQueue::new(
+            config.upload.queue.clone(),
+            Path::new(&config.recordings.directory),
+            catalog.clone(),
+        )?;
+
+        let app = App::new(config, handles_creator, metrics, upload_queue, catalog)?;
         APP.set(app).expect("Already initialized");
-        thread::spawn(|| recorder.start());
+        for recorder in recorders {
+            thread::spawn(|| recorder.start());
+        }
+
+        if let Some(app) = APP.get() {
+            app.upload_queue.start();
+
+            if app.config.recordings.retention.is_enabled() {
+                janitor::start(
+                    app.config.recordings.retention.clone(),
+                    PathBuf::from(&app.config.recordings.directory),
+                    app.catalog.clone(),
+                    move |stream_id| app.is_record_active(stream_id),
+                );
+            }
+
+            if app.config.recordings.disk_space.is_enabled() {
+                disk_space::watch(
+                    app.config.recordings.disk_space.clone(),
+                    PathBuf::from(&app.config.recordings.directory),
+                    app.recorders_creator.disk_space().clone(),
+                    move |level, free_space| handle_disk_space_change(app, level, free_space),
+                );
+            }
+        }
 
         thread::spawn(|| loop {
             if let Ok(app) = app!() {
@@ -74,6 +141,8 @@ impl App {
         config: Config,
         recorders_creator: RecorderHandlesCreator,
         metrics: Metrics,
+        upload_queue: UploadQueue,
+        catalog: Arc<Catalog>,
     ) -> Result<Self> {
         Ok(Self {
             config,
@@ -81,18 +150,86 @@ impl App {
             recorders_creator,
             janus_sender: JanusSender::new(),
             metrics,
+            upload_queue,
+            catalog,
         })
     }
+
+    /// Whether the stream is still being recorded or its record is being uploaded.
+    /// Errors are considered as active to be on the safe side.
+    pub fn is_record_active(&self, stream_id: StreamId) -> bool {
+        let is_recording = self
+            .switchboard
+            .with_read_lock(|switchboard| match switchboard.publisher_of(stream_id) {
+                Some(publisher) => Ok(switchboard.state(publisher)?.recorder().is_some()),
+                None => Ok(false),
+            })
+            .unwrap_or(true);
+
+        let is_uploading = self
+            .upload_queue
+            .find_unfinished(stream_id)
+            .map(|job| job.is_some())
+            .unwrap_or(true);
+
+        is_recording || is_uploading
+    }
 }
 
-async fn start_metrics_collector(
+fn handle_disk_space_change(app: &App, level: Level, free_space: u64) {
+    match level {
+        Level::Normal => info!("Disk space is back to normal: {} bytes free", free_space),
+        Level::Low => warn!("Disk space is low: {} bytes free", free_space),
+        Level::Critical => {
+            err!(
+                "Disk space is critically low: {} bytes free; stopping recordings",
+                free_space
+            );
+
+            if let Err(err) = app.recorders_creator.stop_all() {
+                err!("Failed to stop recordings: {:?}", err);
+            }
+        }
+    }
+
+    // Let publishers of recorded streams know that their recordings are at risk.
+    let publishers = app
+        .switchboard
+        .with_read_lock(|switchboard| {
+            Ok(switchboard
+                .publishers()
+                .filter(|(_, publisher)| {
+                    matches!(switchboard.state(*publisher), Ok(state) if state.recorder().is_some())
+                })
+                .collect::<Vec<_>>())
+        })
+        .unwrap_or_default();
+
+    let event = json!({
+        "event": "recording.disk_space",
+        "level": level,
+        "free_space": free_space,
+    });
+
+    for (stream_id, publisher) in publishers {
+        if let Err(err) = app.janus_sender.notify(publisher, &event) {
+            warn!("Failed to notify about disk space: {:?}", err; {"rtc_id": stream_id});
+        }
+    }
+}
+
+#[derive(Clone)]
+struct HttpState {
     registry: Registry,
-    bind_addr: SocketAddr,
-) -> async_std::io::Result<()> {
-    let mut app = tide::with_state(registry);
+    api_token: Option<String>,
+}
+
+async fn start_http_server(state: HttpState, bind_addr: SocketAddr) -> async_std::io::Result<()> {
+    let is_api_enabled = state.api_token.is_some();
+    let mut app = tide::with_state(state);
     app.at("/metrics")
-        .get(|req: tide::Request<Registry>| async move {
-            let registry = req.state();
+        .get(|req: tide::Request<HttpState>| async move {
+            let registry = &req.state().registry;
             let mut buffer = vec![];
             let encoder = TextEncoder::new();
             let metric_families = registry.gather();
@@ -108,5 +245,44 @@ async fn start_metrics_collector(
                 }
             }
         });
+
+    if is_api_enabled {
+        app.at("/api/v1/:method").post(handle_api_request);
+    }
+
     app.listen(bind_addr).await
 }
+
+async fn handle_api_request(mut req: tide::Request<HttpState>) -> tide::Result {
+    let is_authorized = match (&req.state().api_token, req.header("Authorization")) {
+        (Some(token), Some(header)) => match header.last().as_str().strip_prefix("Bearer ") {
+            Some(value) => constant_time_eq(value.as_bytes(), token.as_bytes()),
+            None => false,
+        },
+        _ => false,
+    };
+
+    if !is_authorized {
+        return Ok(tide::Response::new(401));
+    }
+
+    let method = req.param("method")?.to_owned();
+    let params = req.body_json::<JsonValue>().await?;
+
+    match handle_http_request(&method, params).await {
+        Ok((status, payload)) => {
+            let mut response = tide::Response::new(status.as_u16());
+            response.set_body(tide::Body::from_json(&payload)?);
+            Ok(response)
+        }
+        Err(err) => {
+            err!("HTTP request handling error: {}", err; {"method": method});
+            Ok(tide::Response::new(500))
+        }
+    }
+}
+
+// Compares all the bytes regardless of where they differ to not leak the token by timing.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
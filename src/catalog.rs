@@ -0,0 +1,291 @@
+use std::collections::HashMap;
+use std::fs::{self, OpenOptions};
+use std::io::{ErrorKind, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, MutexGuard};
+
+use anyhow::{format_err, Context, Result};
+use chrono::Utc;
+
+use crate::janitor;
+use crate::switchboard::StreamId;
+use crate::utils;
+
+const CATALOG_FILENAME: &str = "catalog.jsonl";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RecordingState {
+    Recording,
+    Stopped,
+    Uploading,
+    Uploaded,
+    Failed,
+    Deleted,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct CatalogEntry {
+    pub id: StreamId,
+    pub state: RecordingState,
+    /// Unix timestamps in milliseconds.
+    pub created_at: i64,
+    pub updated_at: i64,
+    /// Set once the record has been uploaded so deleted records which have been
+    /// uploaded can be told apart from ones which haven't.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub uploaded_at: Option<i64>,
+    /// The last upload error of a failed record.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// States of all the records in the recordings directory.
+///
+/// Every change is appended to a JSON lines file as a full entry so the last line
+/// of a stream wins when the file is replayed on start. The file is compacted then.
+#[derive(Debug)]
+pub struct Catalog {
+    recordings_dir: PathBuf,
+    entries: Mutex<HashMap<StreamId, CatalogEntry>>,
+}
+
+impl Catalog {
+    /// Loads the catalog and reconciles it with record directories: records which
+    /// are missing in the catalog get added, interrupted recordings become stopped
+    /// and records removed bypassing the catalog become deleted.
+    pub fn new(recordings_dir: &Path) -> Result<Self> {
+        fs::create_dir_all(recordings_dir).context("Failed to create recordings dir")?;
+
+        let catalog = Self {
+            recordings_dir: recordings_dir.to_owned(),
+            entries: Mutex::new(HashMap::new()),
+        };
+
+        catalog.load()?;
+        Ok(catalog)
+    }
+
+    pub fn set_state(&self, stream_id: StreamId, state: RecordingState) {
+        self.update(stream_id, state, None);
+    }
+
+    pub fn set_failed(&self, stream_id: StreamId, error: String) {
+        self.update(stream_id, RecordingState::Failed, Some(error));
+    }
+
+    pub fn get(&self, stream_id: StreamId) -> Result<Option<CatalogEntry>> {
+        Ok(self.lock_entries()?.get(&stream_id).cloned())
+    }
+
+    /// Returns entries in the `state` or all of them if it's not specified
+    /// in order of creation.
+    pub fn list(&self, state: Option<RecordingState>) -> Result<Vec<CatalogEntry>> {
+        let mut entries = self
+            .lock_entries()?
+            .values()
+            .filter(|entry| state.is_none() || state == Some(entry.state))
+            .cloned()
+            .collect::<Vec<_>>();
+
+        entries.sort_by_key(|entry| (entry.created_at, entry.id));
+        Ok(entries)
+    }
+
+    fn update(&self, stream_id: StreamId, state: RecordingState, error: Option<String>) {
+        let mut entries = match self.lock_entries() {
+            Ok(entries) => entries,
+            Err(err) => {
+                err!("Failed to update catalog: {}", err; {"rtc_id": stream_id});
+                return;
+            }
+        };
+
+        let now = Utc::now().timestamp_millis();
+
+        let entry = entries
+            .entry(stream_id)
+            .or_insert_with(|| CatalogEntry::new(stream_id, state, now));
+
+        entry.state = state;
+        entry.updated_at = now;
+        entry.error = error;
+
+        if state == RecordingState::Uploaded {
+            entry.uploaded_at = Some(now);
+
+            // A record deleted right after the upload goes straight to deleted.
+            if !self.record_dir(stream_id).exists() {
+                entry.state = RecordingState::Deleted;
+            }
+        }
+
+        if let Err(err) = self.append(entry) {
+            err!("{:#}", err; {"rtc_id": stream_id});
+        }
+    }
+
+    fn load(&self) -> Result<()> {
+        let mut entries = self.lock_entries()?;
+        let path = self.path();
+
+        let data = match fs::read_to_string(&path) {
+            Ok(data) => data,
+            Err(err) if err.kind() == ErrorKind::NotFound => String::new(),
+            Err(err) => return Err(err).context("Failed to read catalog"),
+        };
+
+        for line in data.lines().filter(|line| !line.is_empty()) {
+            // The last line may be partially written because of a crash.
+            match serde_json::from_str::<CatalogEntry>(line) {
+                Ok(entry) => {
+                    entries.insert(entry.id, entry);
+                }
+                Err(err) => warn!("Skipping malformed catalog entry: {}", err),
+            }
+        }
+
+        let now = Utc::now().timestamp_millis();
+
+        for entry in entries.values_mut() {
+            let state = match entry.state {
+                RecordingState::Deleted => continue,
+                _ if !self.record_dir(entry.id).exists() => RecordingState::Deleted,
+                RecordingState::Recording => RecordingState::Stopped,
+                _ => continue,
+            };
+
+            entry.state = state;
+            entry.updated_at = now;
+        }
+
+        for (stream_id, path) in utils::record_dirs(&self.recordings_dir)? {
+            entries.entry(stream_id).or_insert_with(|| {
+                let created_at = fs::metadata(&path)
+                    .and_then(|metadata| metadata.modified())
+                    .map(|time| chrono::DateTime::<Utc>::from(time).timestamp_millis())
+                    .unwrap_or(now);
+
+                let mut entry = CatalogEntry::new(stream_id, RecordingState::Stopped, created_at);
+
+                if janitor::is_uploaded(&path) {
+                    entry.state = RecordingState::Uploaded;
+                    entry.uploaded_at = Some(created_at);
+                }
+
+                entry
+            });
+        }
+
+        self.compact(&entries)
+    }
+
+    fn append(&self, entry: &CatalogEntry) -> Result<()> {
+        let mut line = serde_json::to_vec(entry).context("Failed to serialize catalog entry")?;
+        line.push(b'\n');
+
+        OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(self.path())
+            .and_then(|mut file| file.write_all(&line))
+            .context("Failed to append to catalog")
+    }
+
+    fn compact(&self, entries: &HashMap<StreamId, CatalogEntry>) -> Result<()> {
+        let mut data = vec![];
+
+        for entry in entries.values() {
+            serde_json::to_writer(&mut data, entry).context("Failed to serialize catalog entry")?;
+            data.push(b'\n');
+        }
+
+        utils::atomic_write(&self.path(), &data).context("Failed to save catalog")
+    }
+
+    fn path(&self) -> PathBuf {
+        self.recordings_dir.join(CATALOG_FILENAME)
+    }
+
+    fn record_dir(&self, stream_id: StreamId) -> PathBuf {
+        self.recordings_dir.join(stream_id.to_string())
+    }
+
+    fn lock_entries(&self) -> Result<MutexGuard<'_, HashMap<StreamId, CatalogEntry>>> {
+        self.entries
+            .lock()
+            .map_err(|_| format_err!("Catalog lock is poisoned"))
+    }
+}
+
+impl CatalogEntry {
+    fn new(id: StreamId, state: RecordingState, created_at: i64) -> Self {
+        Self {
+            id,
+            state,
+            created_at,
+            updated_at: created_at,
+            uploaded_at: None,
+            error: None,
+        }
+    }
+}
+
+///////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use uuid::Uuid;
+
+    use crate::utils::TempDir;
+
+    use super::*;
+
+    #[test]
+    fn replay_and_reconcile() -> Result<()> {
+        let dir = TempDir::new("catalog_test")?;
+        let (recording, uploaded, unknown, removed) = (
+            Uuid::new_v4(),
+            Uuid::new_v4(),
+            Uuid::new_v4(),
+            Uuid::new_v4(),
+        );
+
+        for stream_id in &[recording, uploaded, unknown, removed] {
+            fs::create_dir_all(dir.join(stream_id.to_string()))?;
+        }
+
+        let catalog = Catalog::new(&dir)?;
+        assert_eq!(catalog.list(Some(RecordingState::Stopped))?.len(), 4);
+
+        catalog.set_state(recording, RecordingState::Recording);
+        catalog.set_state(uploaded, RecordingState::Uploading);
+        catalog.set_state(uploaded, RecordingState::Uploaded);
+        catalog.set_failed(removed, String::from("Upload failed"));
+        let entry = catalog.get(removed)?.expect("Missing entry");
+        assert_eq!(entry.error.as_deref(), Some("Upload failed"));
+
+        fs::remove_dir(dir.join(removed.to_string()))?;
+        // A partially written line is skipped.
+        OpenOptions::new()
+            .append(true)
+            .open(catalog.path())?
+            .write_all(br#"{"id":"#)?;
+
+        let catalog = Catalog::new(&dir)?;
+        let state = |stream_id| catalog.get(stream_id).map(|entry| entry.map(|e| e.state));
+        assert_eq!(state(recording)?, Some(RecordingState::Stopped));
+        assert_eq!(state(uploaded)?, Some(RecordingState::Uploaded));
+        assert_eq!(state(unknown)?, Some(RecordingState::Stopped));
+        assert_eq!(state(removed)?, Some(RecordingState::Deleted));
+        assert!(catalog.get(uploaded)?.and_then(|e| e.uploaded_at).is_some());
+
+        // Uploaded and deleted on upload.
+        fs::remove_dir(dir.join(unknown.to_string()))?;
+        catalog.set_state(unknown, RecordingState::Uploaded);
+        assert_eq!(state(unknown)?, Some(RecordingState::Deleted));
+        assert_eq!(catalog.list(Some(RecordingState::Deleted))?.len(), 2);
+        assert_eq!(catalog.list(None)?.len(), 4);
+        Ok(())
+    }
+}
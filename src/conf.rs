@@ -1,10 +1,14 @@
-use std::{net::SocketAddr, path::Path, time::Duration};
+use std::{collections::HashMap, fmt, net::SocketAddr, path::Path, time::Duration};
 
-use anyhow::Result;
+use anyhow::{bail, format_err, Context, Result};
+use serde_json::Value as JsonValue;
 
-use crate::recorder;
+use crate::postprocess::EncoderConfig;
+use crate::storage::{BackendConfig, StorageConfig};
+use crate::{ingest, recorder, upload_queue};
 
 const CONFIG_FILE_NAME: &str = "janus.plugin.conference.toml";
+const MIN_API_TOKEN_LENGTH: usize = 16;
 
 #[derive(Clone, Deserialize, Debug)]
 pub struct Config {
@@ -14,6 +18,8 @@ pub struct Config {
     pub sentry: Option<svc_error::extension::sentry::Config>,
     pub upload: UploadConfig,
     pub metrics: Metrics,
+    pub ingest: Option<ingest::Config>,
+    pub http_api: Option<HttpApi>,
 }
 
 impl Config {
@@ -31,7 +37,12 @@ impl Config {
         let mut config = parser.try_into::<Config>()?;
 
         config.recordings.check()?;
-        config.upload.check()?;
+        config.upload.load_backends()?;
+        config.upload.check_encoders()?;
+
+        if let Some(http_api) = &config.http_api {
+            http_api.check()?;
+        }
 
         Ok(config)
     }
@@ -51,6 +62,31 @@ pub struct Metrics {
     pub bind_addr: SocketAddr,
 }
 
+#[derive(Clone, Deserialize)]
+pub struct HttpApi {
+    pub token: String,
+}
+
+impl HttpApi {
+    fn check(&self) -> Result<()> {
+        if self.token.len() < MIN_API_TOKEN_LENGTH {
+            bail!(
+                "http_api.token must be at least {} characters long",
+                MIN_API_TOKEN_LENGTH
+            );
+        }
+
+        Ok(())
+    }
+}
+
+// The token must not leak to logs with the rest of the config.
+impl fmt::Debug for HttpApi {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("HttpApi").field("token", &"***").finish()
+    }
+}
+
 #[derive(Clone, Deserialize, Debug)]
 pub struct Constraint {
     pub writer: WriterConstraint,
@@ -63,28 +99,124 @@ pub struct WriterConstraint {
     pub audio_bitrate: u32,
 }
 
-#[derive(Clone, Deserialize, Debug)]
-struct UploadBackendConfig {
-    access_key_id: String,
-    secret_access_key: String,
-    endpoint: String,
-    region: String,
+/// S3 backend settings.
+#[derive(Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct UploadBackendConfig {
+    pub access_key_id: String,
+    pub secret_access_key: String,
+    pub endpoint: String,
+    pub region: String,
+}
+
+impl fmt::Debug for UploadBackendConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("UploadBackendConfig")
+            .field("access_key_id", &self.access_key_id)
+            .field("secret_access_key", &"***")
+            .field("endpoint", &self.endpoint)
+            .field("region", &self.region)
+            .finish()
+    }
+}
+
+impl UploadBackendConfig {
+    // Settings missing in the config file are taken from `APP_UPLOADING_<BACKEND>_*`
+    // environment variables.
+    fn load_env(&mut self, backend: &str) -> Result<()> {
+        let prefix = format!("APP_UPLOADING_{}", backend.to_uppercase());
+        let env = config::Environment::with_prefix(&prefix).separator("__");
+
+        let mut parser = config::Config::default();
+        parser.set_default("access_key_id", self.access_key_id.to_owned())?;
+        parser.set_default("secret_access_key", self.secret_access_key.to_owned())?;
+        parser.set_default("endpoint", self.endpoint.to_owned())?;
+        parser.set_default("region", self.region.to_owned())?;
+        parser.merge(env)?;
+        *self = parser.try_into::<UploadBackendConfig>()?;
+
+        let settings = [
+            ("access_key_id", &self.access_key_id),
+            ("secret_access_key", &self.secret_access_key),
+            ("endpoint", &self.endpoint),
+            ("region", &self.region),
+        ];
+
+        for (name, value) in settings.iter() {
+            if value.is_empty() {
+                bail!("Missing {}", name);
+            }
+        }
+
+        Ok(())
+    }
 }
 
 #[derive(Clone, Deserialize, Debug)]
 pub struct UploadConfig {
-    pub backends: Vec<String>,
+    /// Either a table of backend configs by name or a list of S3 backend names
+    /// configured entirely through environment variables.
+    backends: JsonValue,
+    #[serde(default)]
+    pub queue: upload_queue::Config,
+    /// External encoders by name for output profiles which need transcoding.
+    #[serde(default)]
+    pub encoders: HashMap<String, EncoderConfig>,
+    #[serde(skip)]
+    backend_configs: HashMap<String, BackendConfig>,
 }
 
 impl UploadConfig {
-    fn check(&self) -> Result<()> {
-        for backend in &self.backends {
-            let prefix = format!("APP_UPLOADING_{}", backend.to_uppercase());
-            let env = config::Environment::with_prefix(&prefix).separator("__");
+    pub fn backend(&self, name: &str) -> Option<&BackendConfig> {
+        self.backend_configs.get(name)
+    }
+
+    fn check_encoders(&self) -> Result<()> {
+        for (name, config) in &self.encoders {
+            config
+                .check()
+                .with_context(|| format!("Invalid encoder '{}'", name))?;
+        }
+
+        Ok(())
+    }
 
-            let mut parser = config::Config::default();
-            parser.merge(env)?;
-            parser.try_into::<UploadBackendConfig>()?;
+    fn load_backends(&mut self) -> Result<()> {
+        let configs = match self.backends {
+            JsonValue::Array(ref names) => names
+                .iter()
+                .map(|name| {
+                    let name = name
+                        .as_str()
+                        .ok_or_else(|| format_err!("Upload backend name must be a string"))?;
+
+                    Ok((name.to_owned(), BackendConfig::s3(Default::default())))
+                })
+                .collect::<Result<Vec<_>>>()?,
+            JsonValue::Object(ref configs) => configs
+                .iter()
+                .map(|(name, config)| {
+                    let config = serde_json::from_value::<BackendConfig>(config.to_owned())
+                        .with_context(|| format!("Invalid upload backend '{}'", name))?;
+
+                    Ok((name.to_owned(), config))
+                })
+                .collect::<Result<Vec<_>>>()?,
+            _ => bail!("Upload backends must be either a list or a table"),
+        };
+
+        for (name, mut config) in configs {
+            if let StorageConfig::S3(ref mut s3_config) = config.storage {
+                s3_config
+                    .load_env(&name)
+                    .with_context(|| format!("Invalid upload backend '{}'", name))?;
+            }
+
+            config
+                .check()
+                .with_context(|| format!("Invalid upload backend '{}'", name))?;
+
+            self.backend_configs.insert(name, config);
         }
 
         Ok(())
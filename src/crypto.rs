@@ -0,0 +1,652 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufReader, BufWriter, ErrorKind, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+use aes_gcm::aead::generic_array::GenericArray;
+use aes_gcm::aead::{AeadInPlace, NewAead};
+use aes_gcm::Aes256Gcm;
+use anyhow::{bail, Context, Result};
+use rand::{rngs::OsRng, RngCore};
+
+use crate::utils;
+
+///////////////////////////////////////////////////////////////////////////////
+
+const MAGIC: &[u8] = b"JCENC001";
+const DATA_KEY_FILENAME: &str = "data_key.json";
+const DECRYPTED_DIR: &str = ".decrypted";
+const KEY_SIZE: usize = 32;
+const NONCE_SIZE: usize = 12;
+const NONCE_PREFIX_SIZE: usize = 8;
+const HEADER_SIZE: usize = MAGIC.len() + NONCE_PREFIX_SIZE;
+// Associated data of the last chunk which tells it from the others.
+const LAST_CHUNK_AAD: &[u8] = b"last";
+
+/// Encryption of recordings at rest.
+///
+/// Each record has its own random data key stored in the records directory wrapped
+/// with a master key. Old master keys are to be kept in `keys` as long as there're
+/// records with data keys wrapped by them.
+#[derive(Clone, Deserialize)]
+pub struct Config {
+    /// ID of the master key to wrap data keys of new records.
+    pub key_id: String,
+    /// Base64 encoded 256 bit master keys by their IDs.
+    pub keys: HashMap<String, String>,
+}
+
+impl fmt::Debug for Config {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Config")
+            .field("key_id", &self.key_id)
+            .field("keys", &self.keys.keys().collect::<Vec<_>>())
+            .finish()
+    }
+}
+
+impl Config {
+    pub fn check(&self) -> Result<()> {
+        if !self.keys.contains_key(&self.key_id) {
+            bail!("Encryption: missing master key '{}'", self.key_id);
+        }
+
+        for key_id in self.keys.keys() {
+            self.master_key(key_id)?;
+        }
+
+        Ok(())
+    }
+
+    fn master_key(&self, key_id: &str) -> Result<Aes256Gcm> {
+        let key = self
+            .keys
+            .get(key_id)
+            .with_context(|| format!("Unknown master key '{}'", key_id))?;
+
+        let key = base64::decode(key)
+            .with_context(|| format!("Master key '{}' is not valid base64", key_id))?;
+
+        if key.len() != KEY_SIZE {
+            bail!("Master key '{}' must be {} bytes long", key_id, KEY_SIZE);
+        }
+
+        Ok(Aes256Gcm::new(GenericArray::from_slice(&key)))
+    }
+}
+
+/// Data key of a record. Gets zeroed on drop.
+pub struct DataKey {
+    key_id: String,
+    key: [u8; KEY_SIZE],
+}
+
+impl DataKey {
+    /// ID of the master key which wraps the data key.
+    pub fn key_id(&self) -> &str {
+        &self.key_id
+    }
+
+    fn cipher(&self) -> Aes256Gcm {
+        Aes256Gcm::new(GenericArray::from_slice(&self.key))
+    }
+}
+
+impl Drop for DataKey {
+    fn drop(&mut self) {
+        for byte in self.key.iter_mut() {
+            unsafe { std::ptr::write_volatile(byte, 0) };
+        }
+    }
+}
+
+impl fmt::Debug for DataKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "DataKey({})", self.key_id)
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct WrappedKey {
+    key_id: String,
+    nonce: String,
+    key: String,
+}
+
+/// Reads the data key of the record or creates a new one wrapped with the current master key.
+pub fn data_key(dir: &Path, config: &Config) -> Result<DataKey> {
+    if let Some(data_key) = read_data_key(dir, config)? {
+        return Ok(data_key);
+    }
+
+    let mut data_key = DataKey {
+        key_id: config.key_id.to_owned(),
+        key: [0; KEY_SIZE],
+    };
+
+    OsRng.fill_bytes(&mut data_key.key);
+    let mut nonce = [0; NONCE_SIZE];
+    OsRng.fill_bytes(&mut nonce);
+    let mut key = data_key.key.to_vec();
+
+    // The key ID is authenticated so a wrapped key can't be attributed to another master key.
+    config
+        .master_key(&config.key_id)?
+        .encrypt_in_place(
+            GenericArray::from_slice(&nonce),
+            config.key_id.as_bytes(),
+            &mut key,
+        )
+        .map_err(|_| anyhow!("Failed to wrap data key"))?;
+
+    let wrapped_key = WrappedKey {
+        key_id: config.key_id.to_owned(),
+        nonce: base64::encode(&nonce),
+        key: base64::encode(&key),
+    };
+
+    let data = serde_json::to_vec(&wrapped_key)?;
+
+    utils::atomic_write(&dir.join(DATA_KEY_FILENAME), &data).context("Failed to write data key")?;
+
+    Ok(data_key)
+}
+
+/// Reads and unwraps the data key of the record. `None` means the record isn't encrypted.
+pub fn read_data_key(dir: &Path, config: &Config) -> Result<Option<DataKey>> {
+    let wrapped_key = match fs::read(dir.join(DATA_KEY_FILENAME)) {
+        Ok(data) => serde_json::from_slice::<WrappedKey>(&data).context("Invalid data key")?,
+        Err(err) if err.kind() == ErrorKind::NotFound => return Ok(None),
+        Err(err) => return Err(err).context("Failed to read data key"),
+    };
+
+    let nonce = base64::decode(&wrapped_key.nonce).context("Invalid data key nonce")?;
+    let mut key = base64::decode(&wrapped_key.key).context("Invalid data key")?;
+
+    if nonce.len() != NONCE_SIZE {
+        bail!("Invalid data key nonce");
+    }
+
+    config
+        .master_key(&wrapped_key.key_id)?
+        .decrypt_in_place(
+            GenericArray::from_slice(&nonce),
+            wrapped_key.key_id.as_bytes(),
+            &mut key,
+        )
+        .map_err(|_| anyhow!("Failed to unwrap data key"))?;
+
+    if key.len() != KEY_SIZE {
+        bail!("Invalid data key size");
+    }
+
+    let mut data_key = DataKey {
+        key_id: wrapped_key.key_id,
+        key: [0; KEY_SIZE],
+    };
+
+    data_key.key.copy_from_slice(&key);
+    key.iter_mut().for_each(|byte| *byte = 0);
+    Ok(Some(data_key))
+}
+
+/// Deletes the wrapped data key making encrypted files of the record unreadable.
+///
+/// The key file gets overwritten before deletion but that's best effort only: journaling and
+/// copy-on-write filesystems or SSDs may keep the old blocks. What actually protects the data
+/// is that the key is wrapped with a master key so a leftover copy is useless without it.
+pub fn destroy_data_key(dir: &Path) -> Result<()> {
+    let path = dir.join(DATA_KEY_FILENAME);
+
+    let mut file = match OpenOptions::new().write(true).open(&path) {
+        Ok(file) => file,
+        Err(err) if err.kind() == ErrorKind::NotFound => return Ok(()),
+        Err(err) => return Err(err).context("Failed to open data key"),
+    };
+
+    let size = file.metadata()?.len() as usize;
+
+    file.write_all(&vec![0; size])
+        .and_then(|()| file.sync_all())
+        .and_then(|()| fs::remove_file(&path))
+        .context("Failed to destroy data key")
+}
+
+///////////////////////////////////////////////////////////////////////////////
+
+/// Encrypts each write as a separate AES-256-GCM chunk.
+///
+/// The layout is the following: `JCENC001` magic, 8 bytes random nonce prefix, then chunks each of
+/// which is u32 BE length and the ciphertext with the tag. The nonce of a chunk is the prefix
+/// followed by u32 BE chunk number so chunks can't be reordered. `finish` writes an empty last
+/// chunk authenticated with `last` as associated data so a file cut at a chunk boundary doesn't
+/// pass for a complete one. A file left unfinished by a crash gets sealed on recovery.
+pub struct EncryptedWriter<W: Write> {
+    writer: W,
+    cipher: Aes256Gcm,
+    nonce_prefix: [u8; NONCE_PREFIX_SIZE],
+    counter: u32,
+    is_finished: bool,
+    buf: Vec<u8>,
+}
+
+impl EncryptedWriter<BufWriter<File>> {
+    pub fn create(path: &Path, key: &DataKey) -> io::Result<Self> {
+        Self::new(BufWriter::new(File::create(path)?), key)
+    }
+}
+
+impl<W: Write> EncryptedWriter<W> {
+    pub fn new(mut writer: W, key: &DataKey) -> io::Result<Self> {
+        let mut nonce_prefix = [0; NONCE_PREFIX_SIZE];
+        OsRng.fill_bytes(&mut nonce_prefix);
+        writer.write_all(MAGIC)?;
+        writer.write_all(&nonce_prefix)?;
+
+        Ok(Self {
+            writer,
+            cipher: key.cipher(),
+            nonce_prefix,
+            counter: 0,
+            is_finished: false,
+            buf: vec![],
+        })
+    }
+
+    /// Writes the last chunk and flushes. Nothing can be written afterwards.
+    pub fn finish(&mut self) -> io::Result<()> {
+        if !self.is_finished {
+            self.write_chunk(&[], LAST_CHUNK_AAD)?;
+            self.is_finished = true;
+        }
+
+        self.writer.flush()
+    }
+
+    fn write_chunk(&mut self, data: &[u8], aad: &[u8]) -> io::Result<()> {
+        if self.is_finished {
+            return Err(io::Error::new(ErrorKind::Other, "Writer is finished"));
+        }
+
+        seal_chunk(
+            &mut self.writer,
+            &self.cipher,
+            nonce(&self.nonce_prefix, self.counter),
+            data,
+            aad,
+            &mut self.buf,
+        )?;
+
+        self.counter = self
+            .counter
+            .checked_add(1)
+            .ok_or_else(|| io::Error::new(ErrorKind::Other, "Too many chunks"))?;
+
+        Ok(())
+    }
+}
+
+impl<W: Write> Write for EncryptedWriter<W> {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        if data.is_empty() {
+            return Ok(0);
+        }
+
+        self.write_chunk(data, b"")?;
+        Ok(data.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+// Encrypts `data` in `buf` and writes it as a chunk.
+fn seal_chunk<W: Write>(
+    writer: &mut W,
+    cipher: &Aes256Gcm,
+    nonce: [u8; NONCE_SIZE],
+    data: &[u8],
+    aad: &[u8],
+    buf: &mut Vec<u8>,
+) -> io::Result<()> {
+    buf.clear();
+    buf.extend_from_slice(data);
+
+    cipher
+        .encrypt_in_place(GenericArray::from_slice(&nonce), aad, buf)
+        .map_err(|_| io::Error::new(ErrorKind::Other, "Failed to encrypt chunk"))?;
+
+    writer.write_all(&(buf.len() as u32).to_be_bytes())?;
+    writer.write_all(buf)
+}
+
+fn nonce(prefix: &[u8; NONCE_PREFIX_SIZE], counter: u32) -> [u8; NONCE_SIZE] {
+    let mut nonce = [0; NONCE_SIZE];
+    nonce[..NONCE_PREFIX_SIZE].copy_from_slice(prefix);
+    nonce[NONCE_PREFIX_SIZE..].copy_from_slice(&counter.to_be_bytes());
+    nonce
+}
+
+/// Decrypts chunks written by `EncryptedWriter` on the fly.
+///
+/// Fails with `InvalidData` on a tampered chunk and if the file ends without the last chunk
+/// since then it has been truncated.
+pub struct DecryptingReader<R: Read> {
+    reader: R,
+    cipher: Aes256Gcm,
+    nonce_prefix: [u8; NONCE_PREFIX_SIZE],
+    counter: u32,
+    // The chunk is read ahead to know whether the current one must be the last.
+    next: Option<Vec<u8>>,
+    buf: Vec<u8>,
+    position: usize,
+}
+
+impl<R: Read> DecryptingReader<R> {
+    pub fn new(mut reader: R, key: &DataKey) -> io::Result<Self> {
+        let mut header = [0; HEADER_SIZE];
+        reader.read_exact(&mut header)?;
+
+        if &header[..MAGIC.len()] != MAGIC {
+            return Err(invalid_data("Not an encrypted file"));
+        }
+
+        let mut nonce_prefix = [0; NONCE_PREFIX_SIZE];
+        nonce_prefix.copy_from_slice(&header[MAGIC.len()..]);
+
+        let mut decrypting_reader = Self {
+            reader,
+            cipher: key.cipher(),
+            nonce_prefix,
+            counter: 0,
+            next: None,
+            buf: vec![],
+            position: 0,
+        };
+
+        decrypting_reader.next = decrypting_reader.read_next_chunk()?;
+
+        if decrypting_reader.next.is_none() {
+            return Err(invalid_data("Missing last chunk"));
+        }
+
+        Ok(decrypting_reader)
+    }
+
+    fn read_next_chunk(&mut self) -> io::Result<Option<Vec<u8>>> {
+        let mut chunk = vec![];
+
+        match read_chunk(&mut self.reader, &mut chunk)? {
+            Some(read) if read < chunk.len() => Err(invalid_data("Truncated chunk")),
+            Some(_) => Ok(Some(chunk)),
+            None => Ok(None),
+        }
+    }
+
+    // Decrypts the next chunk to the buffer. Returns `false` after the last chunk.
+    fn fill_buf(&mut self) -> io::Result<bool> {
+        let mut chunk = match self.next.take() {
+            Some(chunk) => chunk,
+            None => return Ok(false),
+        };
+
+        self.next = self.read_next_chunk()?;
+        let is_last = self.next.is_none();
+
+        let aad = match is_last {
+            true => LAST_CHUNK_AAD,
+            false => b"",
+        };
+
+        self.cipher
+            .decrypt_in_place(
+                GenericArray::from_slice(&nonce(&self.nonce_prefix, self.counter)),
+                aad,
+                &mut chunk,
+            )
+            .map_err(|_| match is_last {
+                true => invalid_data(&format!(
+                    "Failed to decrypt chunk {} as the last one, the file may be truncated",
+                    self.counter
+                )),
+                false => invalid_data(&format!("Failed to decrypt chunk {}", self.counter)),
+            })?;
+
+        self.counter = self.counter.wrapping_add(1);
+        self.buf = chunk;
+        self.position = 0;
+        Ok(true)
+    }
+}
+
+impl<R: Read> Read for DecryptingReader<R> {
+    fn read(&mut self, data: &mut [u8]) -> io::Result<usize> {
+        while self.position == self.buf.len() {
+            if !self.fill_buf()? {
+                return Ok(0);
+            }
+        }
+
+        let count = data.len().min(self.buf.len() - self.position);
+        data[..count].copy_from_slice(&self.buf[self.position..self.position + count]);
+        self.position += count;
+        Ok(count)
+    }
+}
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(ErrorKind::InvalidData, message.to_owned())
+}
+
+// Reads the next chunk to `buf`. Returns the number of bytes actually read
+// which is less than the buffer size for a truncated chunk or `None` on EOF.
+fn read_chunk<R: Read>(reader: &mut R, buf: &mut Vec<u8>) -> io::Result<Option<usize>> {
+    let mut length = [0; 4];
+    let mut read = 0;
+
+    while read < length.len() {
+        match reader.read(&mut length[read..])? {
+            0 if read == 0 => return Ok(None),
+            0 => return Ok(Some(0)),
+            count => read += count,
+        }
+    }
+
+    buf.resize(u32::from_be_bytes(length) as usize, 0);
+    let mut read = 0;
+
+    while read < buf.len() {
+        match reader.read(&mut buf[read..])? {
+            0 => break,
+            count => read += count,
+        }
+    }
+
+    Ok(Some(read))
+}
+
+/// Opens the file for reading decrypting it on the fly if it's encrypted
+/// so its plain contents never get to the disk.
+pub fn open(path: &Path, key: Option<&DataKey>) -> io::Result<Box<dyn Read>> {
+    let reader = BufReader::new(File::open(path)?);
+
+    if !is_encrypted(path)? {
+        return Ok(Box::new(reader));
+    }
+
+    match key {
+        Some(key) => Ok(Box::new(DecryptingReader::new(reader, key)?)),
+        None => Err(io::Error::new(
+            ErrorKind::Other,
+            "File is encrypted but there's no data key",
+        )),
+    }
+}
+
+pub fn is_encrypted(path: &Path) -> io::Result<bool> {
+    let mut magic = [0; 8];
+
+    match File::open(path)?.read_exact(&mut magic) {
+        Ok(()) => Ok(magic == MAGIC),
+        Err(err) if err.kind() == ErrorKind::UnexpectedEof => Ok(false),
+        Err(err) => Err(err),
+    }
+}
+
+/// Seals a file left unfinished by a crash: cuts off a partially written or corrupted tail
+/// and writes the last chunk after the remaining ones. Returns the number of chunks except
+/// the last one and the number of truncated bytes.
+pub fn repair(path: &Path, key: &DataKey) -> io::Result<(u64, u64)> {
+    let size = fs::metadata(path)?.len();
+    let mut reader = BufReader::new(File::open(path)?);
+    let mut header = [0; HEADER_SIZE];
+    reader.read_exact(&mut header)?;
+
+    if &header[..MAGIC.len()] != MAGIC {
+        return Err(invalid_data("Not an encrypted file"));
+    }
+
+    let mut nonce_prefix = [0; NONCE_PREFIX_SIZE];
+    nonce_prefix.copy_from_slice(&header[MAGIC.len()..]);
+    let cipher = key.cipher();
+    let mut valid_size = HEADER_SIZE as u64;
+    let mut chunks = 0;
+    let mut is_finished = false;
+    let mut buf = vec![];
+
+    // Chunks are authenticated so a tail of zeros or garbage left by the filesystem gets cut too.
+    while let Some(read) = read_chunk(&mut reader, &mut buf)? {
+        if read < buf.len() {
+            break;
+        }
+
+        let nonce = nonce(&nonce_prefix, chunks);
+        let chunk_size = 4 + read as u64;
+        let mut plain = buf.clone();
+
+        if cipher
+            .decrypt_in_place(GenericArray::from_slice(&nonce), b"", &mut plain)
+            .is_ok()
+        {
+            valid_size += chunk_size;
+            chunks += 1;
+        } else if cipher
+            .decrypt_in_place(GenericArray::from_slice(&nonce), LAST_CHUNK_AAD, &mut buf)
+            .is_ok()
+        {
+            valid_size += chunk_size;
+            is_finished = true;
+            break;
+        } else {
+            break;
+        }
+    }
+
+    drop(reader);
+    let mut file = OpenOptions::new().write(true).open(path)?;
+
+    if valid_size < size {
+        file.set_len(valid_size)?;
+    }
+
+    if !is_finished {
+        file.seek(SeekFrom::Start(valid_size))?;
+        let nonce = nonce(&nonce_prefix, chunks);
+        seal_chunk(&mut file, &cipher, nonce, &[], LAST_CHUNK_AAD, &mut buf)?;
+        file.sync_all()?;
+    }
+
+    Ok((chunks as u64, size - valid_size))
+}
+
+/// Removes plain .mjr files left by older versions which decrypted records to the disk for upload.
+pub fn remove_decrypted(dir: &Path) -> Result<()> {
+    utils::remove_dir(&dir.join(DECRYPTED_DIR)).context("Failed to remove decrypted files")
+}
+
+///////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use crate::utils::TempDir;
+
+    use super::*;
+
+    fn decrypt(data: &[u8], key: &DataKey) -> io::Result<Vec<u8>> {
+        let mut plain = vec![];
+        DecryptingReader::new(data, key)?.read_to_end(&mut plain)?;
+        Ok(plain)
+    }
+
+    #[test]
+    fn encrypt_and_decrypt() -> Result<()> {
+        let dir = TempDir::new("crypto_test")?;
+
+        let mut keys = HashMap::new();
+        keys.insert(String::from("old"), base64::encode(&[1; KEY_SIZE]));
+        keys.insert(String::from("new"), base64::encode(&[2; KEY_SIZE]));
+
+        let mut config = Config {
+            key_id: String::from("old"),
+            keys,
+        };
+
+        config.check()?;
+        let key = data_key(&dir, &config)?;
+        assert_eq!(key.key_id(), "old");
+
+        // The data key stays the same after master key rotation.
+        config.key_id = String::from("new");
+        let same_key = data_key(&dir, &config)?;
+        assert_eq!(same_key.key_id(), "old");
+        assert_eq!(same_key.key, key.key);
+
+        let path = dir.join("1000.audio.mjr");
+        let mut writer = EncryptedWriter::create(&path, &key)?;
+        writer.write_all(b"MJR00002")?;
+        writer.write_all(b"frame")?;
+        writer.finish()?;
+        drop(writer);
+
+        assert!(is_encrypted(&path)?);
+        let mut data = fs::read(&path)?;
+        let plain = decrypt(&data, &key)?;
+        assert_eq!(plain, b"MJR00002frame");
+
+        // Tampering is detected.
+        let last = data.len() - 1;
+        data[last] ^= 1;
+        assert!(decrypt(&data, &key).is_err());
+        data[last] ^= 1;
+
+        // So is a file cut at a chunk boundary.
+        let last_chunk_size = 4 + 16;
+        let cut = &data[..data.len() - last_chunk_size];
+        assert!(decrypt(cut, &key).is_err());
+
+        // An unfinished file with a partial chunk gets sealed.
+        fs::write(&path, cut)?;
+        OpenOptions::new()
+            .append(true)
+            .open(&path)?
+            .write_all(&[0, 0, 0, 9, 1])?;
+        assert_eq!(repair(&path, &key)?, (2, 5));
+        assert_eq!(fs::read(&path)?.len(), data.len());
+        assert_eq!(decrypt(&fs::read(&path)?, &key)?, plain);
+
+        // A finished file is left as is.
+        assert_eq!(repair(&path, &key)?, (2, 0));
+        assert_eq!(fs::read(&path)?.len(), data.len());
+
+        let mut opened = vec![];
+        open(&path, Some(&key))?.read_to_end(&mut opened)?;
+        assert_eq!(opened, plain);
+        assert!(open(&path, None).is_err());
+
+        destroy_data_key(&dir)?;
+        assert!(read_data_key(&dir, &config)?.is_none());
+        Ok(())
+    }
+}
@@ -0,0 +1,164 @@
+use std::ffi::CString;
+use std::io;
+use std::mem::MaybeUninit;
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use crate::metrics::Metrics;
+
+///////////////////////////////////////////////////////////////////////////////
+
+/// Free space thresholds of the recordings volume in bytes.
+#[derive(Clone, Deserialize, Debug)]
+#[serde(default)]
+pub struct Config {
+    #[serde(with = "humantime_serde")]
+    pub check_interval: Duration,
+    /// Below this recordings go on but warnings are emitted.
+    pub soft_threshold: Option<u64>,
+    /// Below this recordings get stopped and new ones are refused.
+    pub hard_threshold: Option<u64>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            check_interval: Duration::from_secs(10),
+            soft_threshold: None,
+            hard_threshold: None,
+        }
+    }
+}
+
+impl Config {
+    pub fn is_enabled(&self) -> bool {
+        self.soft_threshold.is_some() || self.hard_threshold.is_some()
+    }
+
+    fn level(&self, free_space: u64) -> Level {
+        let is_below = |threshold: Option<u64>| matches!(threshold, Some(t) if free_space < t);
+
+        if is_below(self.hard_threshold) {
+            Level::Critical
+        } else if is_below(self.soft_threshold) {
+            Level::Low
+        } else {
+            Level::Normal
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Level {
+    Normal = 0,
+    Low = 1,
+    Critical = 2,
+}
+
+impl From<u8> for Level {
+    fn from(value: u8) -> Self {
+        match value {
+            0 => Self::Normal,
+            1 => Self::Low,
+            _ => Self::Critical,
+        }
+    }
+}
+
+/// Last known free space level shared between the watcher and recorder handles.
+#[derive(Clone, Debug)]
+pub struct DiskSpace {
+    level: Arc<AtomicU8>,
+}
+
+impl DiskSpace {
+    pub fn new() -> Self {
+        Self {
+            level: Arc::new(AtomicU8::new(Level::Normal as u8)),
+        }
+    }
+
+    pub fn level(&self) -> Level {
+        Level::from(self.level.load(Ordering::Relaxed))
+    }
+
+    pub fn is_critical(&self) -> bool {
+        self.level() == Level::Critical
+    }
+
+    fn set_level(&self, level: Level) -> Level {
+        Level::from(self.level.swap(level as u8, Ordering::Relaxed))
+    }
+}
+
+/// Spawns a thread which checks free space of `dir` every `check_interval`
+/// and calls `on_change` with the new level and free space in bytes when the level changes.
+pub fn watch<F>(config: Config, dir: PathBuf, disk_space: DiskSpace, on_change: F)
+where
+    F: Fn(Level, u64) + Send + 'static,
+{
+    info!(
+        "Starting disk space watcher: soft threshold = {:?}, hard threshold = {:?}",
+        config.soft_threshold, config.hard_threshold
+    );
+
+    thread::spawn(move || loop {
+        match free_space(&dir) {
+            Ok(free_space) => {
+                let level = config.level(free_space);
+                Metrics::observe_disk_space(free_space, level);
+
+                if disk_space.set_level(level) != level {
+                    on_change(level, free_space);
+                }
+            }
+            Err(err) => err!("Failed to get free space of {}: {}", dir.display(), err),
+        }
+
+        thread::sleep(config.check_interval);
+    });
+}
+
+/// Returns the number of bytes available to unprivileged users on the volume of `path`.
+pub fn free_space(path: &Path) -> io::Result<u64> {
+    let path = CString::new(path.as_os_str().as_bytes())?;
+    let mut stat = MaybeUninit::<libc::statvfs>::uninit();
+
+    if unsafe { libc::statvfs(path.as_ptr(), stat.as_mut_ptr()) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    let stat = unsafe { stat.assume_init() };
+
+    // Field types differ across platforms.
+    #[allow(clippy::unnecessary_cast)]
+    Ok(stat.f_bavail as u64 * stat.f_frsize as u64)
+}
+
+///////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn detect_level() -> io::Result<()> {
+        let config = Config {
+            soft_threshold: Some(1000),
+            hard_threshold: Some(100),
+            ..Config::default()
+        };
+
+        assert_eq!(config.level(1000), Level::Normal);
+        assert_eq!(config.level(999), Level::Low);
+        assert_eq!(config.level(99), Level::Critical);
+        assert_eq!(Config::default().level(0), Level::Normal);
+        assert!(free_space(&std::env::temp_dir())? > 0);
+        Ok(())
+    }
+}
@@ -0,0 +1,245 @@
+use std::fmt::Write;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, UdpSocket};
+use std::sync::Mutex;
+
+use anyhow::{bail, format_err, Context, Result};
+use uuid::Uuid;
+
+use crate::switchboard::StreamId;
+
+///////////////////////////////////////////////////////////////////////////////
+
+pub type ForwarderId = Uuid;
+
+pub const DEFAULT_AUDIO_PAYLOAD_TYPE: u8 = 111;
+pub const DEFAULT_VIDEO_PAYLOAD_TYPE: u8 = 96;
+
+const RTP_HEADER_SIZE: usize = 12;
+
+/// Destination of a single media kind of an RTP forwarder.
+#[derive(Debug, Clone)]
+pub struct ForwarderTarget {
+    port: u16,
+    rtcp_port: Option<u16>,
+    ssrc: Option<u32>,
+    payload_type: u8,
+}
+
+impl ForwarderTarget {
+    pub fn new(port: u16, payload_type: u8) -> Self {
+        Self {
+            port,
+            rtcp_port: None,
+            ssrc: None,
+            payload_type,
+        }
+    }
+
+    pub fn set_rtcp_port(self, rtcp_port: Option<u16>) -> Self {
+        Self { rtcp_port, ..self }
+    }
+
+    pub fn set_ssrc(self, ssrc: Option<u32>) -> Self {
+        Self { ssrc, ..self }
+    }
+}
+
+/// Sends RTP (and optionally RTCP) of a stream to plain UDP destinations
+/// the same way as videoroom's `rtp_forward` does.
+///
+/// Payload type is always rewritten to the configured one so the generated SDP
+/// describes the forwarded session correctly. SSRC is rewritten only if it's specified.
+/// RTCP is forwarded as is.
+#[derive(Debug)]
+pub struct RtpForwarder {
+    id: ForwarderId,
+    stream_id: StreamId,
+    host: IpAddr,
+    audio: Option<ForwarderTarget>,
+    video: Option<ForwarderTarget>,
+    socket: UdpSocket,
+    buffer: Mutex<Vec<u8>>,
+}
+
+impl RtpForwarder {
+    pub fn new(
+        stream_id: StreamId,
+        host: IpAddr,
+        audio: Option<ForwarderTarget>,
+        video: Option<ForwarderTarget>,
+    ) -> Result<Self> {
+        if audio.is_none() && video.is_none() {
+            bail!("Neither audio nor video port specified");
+        }
+
+        let bind_addr = match host {
+            IpAddr::V4(_) => SocketAddr::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), 0),
+            IpAddr::V6(_) => SocketAddr::new(IpAddr::V6(Ipv6Addr::UNSPECIFIED), 0),
+        };
+
+        let socket = UdpSocket::bind(bind_addr).context("Failed to bind forwarder socket")?;
+
+        socket
+            .set_nonblocking(true)
+            .context("Failed to make forwarder socket non-blocking")?;
+
+        Ok(Self {
+            id: Uuid::new_v4(),
+            stream_id,
+            host,
+            audio,
+            video,
+            socket,
+            buffer: Mutex::new(Vec::new()),
+        })
+    }
+
+    pub fn id(&self) -> ForwarderId {
+        self.id
+    }
+
+    pub fn forward_rtp(&self, packet: &[u8], is_video: bool) -> Result<()> {
+        let target = match self.target(is_video) {
+            Some(target) => target,
+            None => return Ok(()),
+        };
+
+        if packet.len() < RTP_HEADER_SIZE {
+            bail!("RTP packet is too short: {} bytes", packet.len());
+        }
+
+        let mut buffer = self
+            .buffer
+            .lock()
+            .map_err(|err| format_err!("Failed to acquire forwarder buffer mutex: {}", err))?;
+
+        buffer.clear();
+        buffer.extend_from_slice(packet);
+        rewrite_rtp_header(&mut buffer, target.payload_type, target.ssrc);
+
+        let addr = SocketAddr::new(self.host, target.port);
+        self.socket
+            .send_to(&buffer, addr)
+            .with_context(|| format!("Failed to forward RTP packet to {}", addr))?;
+
+        Ok(())
+    }
+
+    pub fn forward_rtcp(&self, packet: &[u8], is_video: bool) -> Result<()> {
+        let rtcp_port = match self.target(is_video).and_then(|target| target.rtcp_port) {
+            Some(rtcp_port) => rtcp_port,
+            None => return Ok(()),
+        };
+
+        let addr = SocketAddr::new(self.host, rtcp_port);
+        self.socket
+            .send_to(packet, addr)
+            .with_context(|| format!("Failed to forward RTCP packet to {}", addr))?;
+
+        Ok(())
+    }
+
+    /// Generates an SDP describing the forwarded session which is
+    /// suitable to pass to ffmpeg or gstreamer as an input.
+    pub fn sdp(&self) -> String {
+        let addr_type = match self.host {
+            IpAddr::V4(_) => "IP4",
+            IpAddr::V6(_) => "IP6",
+        };
+
+        let mut sdp = String::new();
+        let _ = writeln!(sdp, "v=0");
+        let _ = writeln!(sdp, "o=- 0 0 IN {} {}", addr_type, self.host);
+        let _ = writeln!(sdp, "s={}", self.stream_id);
+        let _ = writeln!(sdp, "c=IN {} {}", addr_type, self.host);
+        let _ = writeln!(sdp, "t=0 0");
+
+        if let Some(ref audio) = self.audio {
+            let _ = writeln!(sdp, "m=audio {} RTP/AVP {}", audio.port, audio.payload_type);
+            let _ = writeln!(sdp, "a=rtpmap:{} opus/48000/2", audio.payload_type);
+
+            if let Some(rtcp_port) = audio.rtcp_port {
+                let _ = writeln!(sdp, "a=rtcp:{}", rtcp_port);
+            }
+        }
+
+        if let Some(ref video) = self.video {
+            let _ = writeln!(sdp, "m=video {} RTP/AVP {}", video.port, video.payload_type);
+            let _ = writeln!(sdp, "a=rtpmap:{} VP8/90000", video.payload_type);
+
+            if let Some(rtcp_port) = video.rtcp_port {
+                let _ = writeln!(sdp, "a=rtcp:{}", rtcp_port);
+            }
+        }
+
+        sdp
+    }
+
+    fn target(&self, is_video: bool) -> Option<&ForwarderTarget> {
+        match is_video {
+            true => self.video.as_ref(),
+            false => self.audio.as_ref(),
+        }
+    }
+}
+
+pub fn rewrite_rtp_header(packet: &mut [u8], payload_type: u8, ssrc: Option<u32>) {
+    // Keep the marker bit and replace the payload type.
+    packet[1] = (packet[1] & 0x80) | (payload_type & 0x7f);
+
+    if let Some(ssrc) = ssrc {
+        packet[8..12].copy_from_slice(&ssrc.to_be_bytes());
+    }
+}
+
+///////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rewrite_header() {
+        let mut packet = [
+            0x80, 0xe0, 0x00, 0x01, 0x00, 0x00, 0x00, 0x10, 0x11, 0x22, 0x33, 0x44, 0xff,
+        ];
+
+        rewrite_rtp_header(&mut packet, 100, None);
+        assert_eq!(packet[1], 0x80 | 100);
+        assert_eq!(&packet[8..12], &[0x11, 0x22, 0x33, 0x44]);
+
+        rewrite_rtp_header(&mut packet, 96, Some(0xdeadbeef));
+        assert_eq!(packet[1], 0x80 | 96);
+        assert_eq!(&packet[8..12], &[0xde, 0xad, 0xbe, 0xef]);
+        assert_eq!(packet[12], 0xff);
+    }
+
+    #[test]
+    fn generate_sdp() -> Result<()> {
+        let stream_id = Uuid::nil();
+
+        let forwarder = RtpForwarder::new(
+            stream_id,
+            "127.0.0.1".parse()?,
+            Some(ForwarderTarget::new(5002, DEFAULT_AUDIO_PAYLOAD_TYPE).set_rtcp_port(Some(5003))),
+            Some(ForwarderTarget::new(5004, DEFAULT_VIDEO_PAYLOAD_TYPE)),
+        )?;
+
+        let expected_sdp = format!(
+            "v=0\n\
+             o=- 0 0 IN IP4 127.0.0.1\n\
+             s={}\n\
+             c=IN IP4 127.0.0.1\n\
+             t=0 0\n\
+             m=audio 5002 RTP/AVP 111\n\
+             a=rtpmap:111 opus/48000/2\n\
+             a=rtcp:5003\n\
+             m=video 5004 RTP/AVP 96\n\
+             a=rtpmap:96 VP8/90000\n",
+            stream_id
+        );
+
+        assert_eq!(forwarder.sdp(), expected_sdp);
+        Ok(())
+    }
+}
@@ -0,0 +1,268 @@
+use std::io::{self, ErrorKind};
+use std::net::{IpAddr, SocketAddr, UdpSocket};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+
+use crate::forwarder::rewrite_rtp_header;
+use crate::janus_rtp::new_rtp_packet;
+use crate::switchboard::SessionId;
+
+///////////////////////////////////////////////////////////////////////////////
+
+#[derive(Clone, Deserialize, Debug)]
+pub struct Config {
+    pub bind_ip: IpAddr,
+}
+
+// Janus generates handle ids which fit into JavaScript's safe integer
+// so synthetic publishers ids never clash with them.
+static NEXT_SESSION_ID: AtomicU64 = AtomicU64::new(1 << 63);
+
+const RECV_BUFFER_SIZE: usize = 65536;
+const RECV_TIMEOUT: Duration = Duration::from_secs(1);
+const RTP_HEADER_SIZE: usize = 12;
+
+/// Receives plain RTP from a non-WebRTC source like OBS or ffmpeg
+/// and pushes it to the regular publisher packet path on behalf of a synthetic session.
+///
+/// Audio and video are expected on separate UDP ports which are allocated by the OS.
+/// Each port accepts packets only from the first address sending RTP to it and only from
+/// `source` IP if it's specified so nobody else can inject media into the stream.
+/// Payload types are rewritten to the ones readers expect.
+/// Receiving threads stop shortly after the ingest is dropped.
+#[derive(Debug)]
+pub struct RtpIngest {
+    session_id: SessionId,
+    audio_port: u16,
+    video_port: u16,
+    is_stopped: Arc<AtomicBool>,
+}
+
+impl RtpIngest {
+    pub fn start(
+        config: &Config,
+        source: Option<IpAddr>,
+        audio_payload_type: u8,
+        video_payload_type: u8,
+    ) -> Result<Self> {
+        let session_id = SessionId::new(NEXT_SESSION_ID.fetch_add(1, Ordering::Relaxed));
+        let is_stopped = Arc::new(AtomicBool::new(false));
+
+        let audio_socket = bind(config.bind_ip)?;
+        let audio_port = audio_socket.local_addr()?.port();
+        let video_socket = bind(config.bind_ip)?;
+        let video_port = video_socket.local_addr()?.port();
+
+        for (socket, is_video, payload_type) in [
+            (audio_socket, false, audio_payload_type),
+            (video_socket, true, video_payload_type),
+        ] {
+            let thread_is_stopped = is_stopped.clone();
+
+            let spawn_result = thread::Builder::new()
+                .name(format!("rtp-ingest-{}", session_id))
+                .spawn(move || {
+                    receive_loop(
+                        Receiver::new(socket, source),
+                        session_id,
+                        is_video,
+                        payload_type,
+                        thread_is_stopped,
+                    )
+                });
+
+            if let Err(err) = spawn_result {
+                // Stop the thread which may have been already spawned.
+                is_stopped.store(true, Ordering::Relaxed);
+                return Err(err).context("Failed to spawn RTP ingest thread");
+            }
+        }
+
+        info!(
+            "RTP ingest started; audio port = {}, video port = {}", audio_port, video_port;
+            {"handle_id": session_id}
+        );
+
+        Ok(Self {
+            session_id,
+            audio_port,
+            video_port,
+            is_stopped,
+        })
+    }
+
+    pub fn session_id(&self) -> SessionId {
+        self.session_id
+    }
+
+    pub fn audio_port(&self) -> u16 {
+        self.audio_port
+    }
+
+    pub fn video_port(&self) -> u16 {
+        self.video_port
+    }
+}
+
+impl Drop for RtpIngest {
+    fn drop(&mut self) {
+        info!("RTP ingest stopped"; {"handle_id": self.session_id});
+        self.is_stopped.store(true, Ordering::Relaxed);
+    }
+}
+
+fn bind(ip: IpAddr) -> Result<UdpSocket> {
+    let socket = UdpSocket::bind(SocketAddr::new(ip, 0)).context("Failed to bind ingest socket")?;
+
+    socket
+        .set_read_timeout(Some(RECV_TIMEOUT))
+        .context("Failed to set ingest socket read timeout")?;
+
+    Ok(socket)
+}
+
+fn receive_loop(
+    mut receiver: Receiver,
+    session_id: SessionId,
+    is_video: bool,
+    payload_type: u8,
+    is_stopped: Arc<AtomicBool>,
+) {
+    let mut buffer = vec![0; RECV_BUFFER_SIZE];
+
+    while !is_stopped.load(Ordering::Relaxed) {
+        let len = match receiver.recv(&mut buffer) {
+            Ok(Some(len)) => len,
+            Ok(None) => continue,
+            Err(err) => {
+                err!("Failed to receive ingest packet: {}", err; {"handle_id": session_id});
+                continue;
+            }
+        };
+
+        // Check the stop flag once again since the stream could have been removed
+        // while we've been waiting for the packet.
+        if is_stopped.load(Ordering::Relaxed) {
+            continue;
+        }
+
+        let packet_buffer = &mut buffer[..len];
+        rewrite_rtp_header(packet_buffer, payload_type, None);
+        let mut packet = new_rtp_packet(packet_buffer, is_video);
+
+        if let Err(err) = crate::handle_publisher_rtp(session_id, &mut packet) {
+            huge!("Failed to handle ingest packet: {}", err; {"handle_id": session_id});
+        }
+    }
+}
+
+/// Receives RTP packets latching to the first sender.
+struct Receiver {
+    socket: UdpSocket,
+    source: Option<IpAddr>,
+    sender: Option<SocketAddr>,
+}
+
+impl Receiver {
+    fn new(socket: UdpSocket, source: Option<IpAddr>) -> Self {
+        Self {
+            socket,
+            source,
+            sender: None,
+        }
+    }
+
+    /// Returns `None` on timeout or if the packet is ignored.
+    fn recv(&mut self, buffer: &mut [u8]) -> io::Result<Option<usize>> {
+        let (len, addr) = match self.socket.recv_from(buffer) {
+            Ok(result) => result,
+            Err(err) if matches!(err.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {
+                return Ok(None)
+            }
+            Err(err) => return Err(err),
+        };
+
+        if !is_rtp(&buffer[..len]) {
+            return Ok(None);
+        }
+
+        match self.sender {
+            Some(sender) if sender != addr => return Ok(None),
+            Some(_) => (),
+            None if matches!(self.source, Some(source) if source != addr.ip()) => return Ok(None),
+            None => {
+                // The OS drops packets from other addresses since then.
+                self.socket.connect(addr)?;
+                self.sender = Some(addr);
+            }
+        }
+
+        Ok(Some(len))
+    }
+}
+
+fn is_rtp(packet: &[u8]) -> bool {
+    // Version 2 and not an RTCP packet type multiplexed on the same port.
+    packet.len() >= RTP_HEADER_SIZE
+        && packet[0] >> 6 == 2
+        && !(72..=76).contains(&(packet[1] & 0x7f))
+}
+
+///////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn detect_rtp() {
+        let mut packet = [0x80, 0x60, 0, 1, 0, 0, 0, 0, 0, 0, 0, 1];
+        assert!(is_rtp(&packet));
+
+        // RTCP sender report.
+        packet[1] = 200;
+        assert!(!is_rtp(&packet));
+
+        // Wrong version.
+        packet[0] = 0x40;
+        packet[1] = 0x60;
+        assert!(!is_rtp(&packet));
+
+        assert!(!is_rtp(&packet[..4]));
+    }
+
+    #[test]
+    fn latch_to_first_sender() -> Result<()> {
+        let ip = IpAddr::from([127, 0, 0, 1]);
+        let socket = bind(ip)?;
+        socket.set_read_timeout(Some(Duration::from_millis(100)))?;
+        let addr = socket.local_addr()?;
+        let mut receiver = Receiver::new(socket, Some(ip));
+        let mut buffer = vec![0; RECV_BUFFER_SIZE];
+        let packet = [0x80, 0x60, 0, 1, 0, 0, 0, 0, 0, 0, 0, 1];
+
+        let sender = UdpSocket::bind(SocketAddr::new(ip, 0))?;
+        sender.send_to(&packet, addr)?;
+        assert_eq!(receiver.recv(&mut buffer)?, Some(packet.len()));
+
+        // Packets from another address are ignored.
+        let intruder = UdpSocket::bind(SocketAddr::new(ip, 0))?;
+        intruder.send_to(&packet, addr)?;
+        assert_eq!(receiver.recv(&mut buffer)?, None);
+
+        sender.send_to(&packet, addr)?;
+        assert_eq!(receiver.recv(&mut buffer)?, Some(packet.len()));
+
+        // Packets from other IPs than the source one are ignored before latching.
+        let socket = bind(ip)?;
+        let addr = socket.local_addr()?;
+        let mut receiver = Receiver::new(socket, Some(IpAddr::from([10, 0, 0, 1])));
+        sender.send_to(&packet, addr)?;
+        assert_eq!(receiver.recv(&mut buffer)?, None);
+        Ok(())
+    }
+}
@@ -0,0 +1,225 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+use anyhow::{Context, Result};
+
+use crate::catalog::{Catalog, RecordingState};
+use crate::crypto;
+use crate::metrics::Metrics;
+use crate::switchboard::StreamId;
+use crate::utils;
+
+// Written to a record which is uploaded but not deleted.
+const UPLOADED_MARKER: &str = ".uploaded";
+
+/// Removes old records from the recordings directory.
+#[derive(Clone, Deserialize, Debug)]
+#[serde(default)]
+pub struct Config {
+    /// How often records are checked.
+    #[serde(with = "humantime_serde")]
+    pub interval: Duration,
+    /// How long uploaded records are kept. Forever if not specified.
+    #[serde(with = "humantime_serde")]
+    pub uploaded_ttl: Option<Duration>,
+    /// How long records which have never been uploaded are kept since the last write.
+    /// Forever if not specified.
+    #[serde(with = "humantime_serde")]
+    pub not_uploaded_ttl: Option<Duration>,
+    /// Only log records which would be removed.
+    pub dry_run: bool,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            interval: Duration::from_secs(60 * 60),
+            uploaded_ttl: None,
+            not_uploaded_ttl: None,
+            dry_run: false,
+        }
+    }
+}
+
+impl Config {
+    pub fn is_enabled(&self) -> bool {
+        self.uploaded_ttl.is_some() || self.not_uploaded_ttl.is_some()
+    }
+}
+
+/// Marks the record to be removed after `uploaded_ttl`.
+pub fn mark_uploaded(dir: &Path) -> Result<()> {
+    fs::write(dir.join(UPLOADED_MARKER), b"").context("Failed to mark record as uploaded")
+}
+
+pub fn is_uploaded(dir: &Path) -> bool {
+    dir.join(UPLOADED_MARKER).exists()
+}
+
+/// Spawns a thread which checks records every `interval`.
+/// Records of streams for which `is_active` returns `true` are skipped.
+/// Removed records are marked as deleted in the `catalog`.
+pub fn start<F>(config: Config, recordings_dir: PathBuf, catalog: Arc<Catalog>, is_active: F)
+where
+    F: Fn(StreamId) -> bool + Send + 'static,
+{
+    info!(
+        "Starting records janitor: uploaded TTL = {:?}, not uploaded TTL = {:?}, dry run = {}",
+        config.uploaded_ttl, config.not_uploaded_ttl, config.dry_run
+    );
+
+    thread::spawn(move || loop {
+        thread::sleep(config.interval);
+
+        match sweep(&config, &recordings_dir, &is_active) {
+            Ok(_) if config.dry_run => (),
+            Ok(removed) => {
+                for stream_id in removed {
+                    catalog.set_state(stream_id, RecordingState::Deleted);
+                }
+            }
+            Err(err) => err!("Failed to clean up records: {:#}", err),
+        }
+    });
+}
+
+/// Removes expired records. Returns IDs of removed streams or ones which would be
+/// removed in dry run mode. A record failed to be removed doesn't stop the others.
+fn sweep<F>(config: &Config, recordings_dir: &Path, is_active: F) -> Result<Vec<StreamId>>
+where
+    F: Fn(StreamId) -> bool,
+{
+    let mut removed = vec![];
+
+    for (stream_id, path) in utils::record_dirs(recordings_dir)? {
+        if is_active(stream_id) {
+            continue;
+        }
+
+        match sweep_record(config, &path, stream_id) {
+            Ok(true) => removed.push(stream_id),
+            Ok(false) => (),
+            Err(err) => err!("Failed to clean up record: {:#}", err; {"rtc_id": stream_id}),
+        }
+    }
+
+    Ok(removed)
+}
+
+/// Removes the record if it's expired. Returns whether it has been removed
+/// or would be in dry run mode.
+fn sweep_record(config: &Config, path: &Path, stream_id: StreamId) -> Result<bool> {
+    let marker_path = path.join(UPLOADED_MARKER);
+
+    let (ttl, modified_at) = match fs::metadata(&marker_path) {
+        Ok(metadata) => (config.uploaded_ttl, metadata.modified()?),
+        Err(_) => (config.not_uploaded_ttl, last_modified(path)?),
+    };
+
+    let age = SystemTime::now()
+        .duration_since(modified_at)
+        .unwrap_or_default();
+
+    match ttl {
+        Some(ttl) if age >= ttl => (),
+        _ => return Ok(false),
+    }
+
+    let size = dir_size(path)?;
+
+    if config.dry_run {
+        info!(
+            "Record would be removed: age = {:?}, size = {} bytes", age, size;
+            {"rtc_id": stream_id}
+        );
+    } else {
+        crypto::destroy_data_key(path)?;
+
+        fs::remove_dir_all(path).with_context(|| format!("Failed to remove {}", path.display()))?;
+
+        info!("Record removed: age = {:?}, size = {} bytes", age, size; {"rtc_id": stream_id});
+        Metrics::observe_record_removal(size);
+    }
+
+    Ok(true)
+}
+
+/// Returns the latest modification time of the dir and its files.
+fn last_modified(dir: &Path) -> Result<SystemTime> {
+    let mut modified_at = fs::metadata(dir)?.modified()?;
+
+    for entry in fs::read_dir(dir)? {
+        modified_at = modified_at.max(entry?.metadata()?.modified()?);
+    }
+
+    Ok(modified_at)
+}
+
+fn dir_size(dir: &Path) -> Result<u64> {
+    let mut size = 0;
+
+    for entry in fs::read_dir(dir)? {
+        size += entry?.metadata()?.len();
+    }
+
+    Ok(size)
+}
+
+///////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use uuid::Uuid;
+
+    use crate::utils::TempDir;
+
+    use super::*;
+
+    #[test]
+    fn remove_expired_records() -> Result<()> {
+        let dir = TempDir::new("janitor_test")?;
+        let (uploaded, not_uploaded, active) = (Uuid::new_v4(), Uuid::new_v4(), Uuid::new_v4());
+
+        for stream_id in &[uploaded, not_uploaded, active] {
+            fs::create_dir_all(dir.join(stream_id.to_string()))?;
+            fs::write(dir.join(format!("{}/1000.audio.mjr", stream_id)), b"abc")?;
+        }
+
+        fs::create_dir(dir.join(".upload_jobs"))?;
+        mark_uploaded(&dir.join(uploaded.to_string()))?;
+
+        let mut config = Config {
+            not_uploaded_ttl: Some(Duration::from_secs(0)),
+            dry_run: true,
+            ..Config::default()
+        };
+
+        let is_active = |stream_id| stream_id == active;
+
+        // Nothing gets removed in dry run.
+        assert_eq!(sweep(&config, &dir, is_active)?, vec![not_uploaded]);
+        assert!(dir.join(not_uploaded.to_string()).exists());
+
+        // A record which fails to be removed doesn't stop the others.
+        let broken = Uuid::new_v4();
+        fs::create_dir_all(dir.join(format!("{}/data_key.json", broken)))?;
+
+        config.dry_run = false;
+        assert_eq!(sweep(&config, &dir, is_active)?, vec![not_uploaded]);
+        assert!(!dir.join(not_uploaded.to_string()).exists());
+        assert!(dir.join(broken.to_string()).exists());
+
+        // Uploaded records are kept while their TTL isn't expired.
+        config.uploaded_ttl = Some(Duration::from_secs(60));
+        assert!(sweep(&config, &dir, is_active)?.is_empty());
+
+        config.uploaded_ttl = Some(Duration::from_secs(0));
+        assert_eq!(sweep(&config, &dir, is_active)?, vec![uploaded]);
+        assert!(dir.join(active.to_string()).exists());
+        assert!(dir.join(".upload_jobs").exists());
+        Ok(())
+    }
+}
@@ -17,16 +17,18 @@ pub enum Codec {
     H264,
     G711,
     VP9,
+    Text,
 }
 
 impl Codec {
-    fn as_str(self) -> &'static str {
+    pub fn as_str(self) -> &'static str {
         match self {
             Self::VP8 => "vp8",
             Self::Opus => "opus",
             Self::H264 => "h264",
             Self::G711 => "g711",
             Self::VP9 => "vp9",
+            Self::Text => "text",
         }
     }
 }
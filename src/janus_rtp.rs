@@ -45,6 +45,22 @@ impl JanusRtpSwitchingContext {
     }
 }
 
+/// Builds a plugin RTP packet for the buffer the same way as Janus core does for incoming packets.
+/// Used to push RTP received not through a PeerConnection into the regular packet path.
+pub fn new_rtp_packet(buffer: &mut [u8], is_video: bool) -> PluginRtpPacket {
+    let mut uninit_packet = MaybeUninit::<PluginRtpPacket>::uninit();
+
+    let mut packet = unsafe {
+        janus_plugin_rtp_reset(uninit_packet.as_mut_ptr());
+        uninit_packet.assume_init()
+    };
+
+    packet.video = is_video as _;
+    packet.buffer = buffer.as_mut_ptr() as *mut _;
+    packet.length = buffer.len() as _;
+    packet
+}
+
 pub struct JanusRtpHeader(janus_rtp_header);
 
 impl JanusRtpHeader {
@@ -127,6 +143,8 @@ struct janus_rtp_switching_context {
 
 #[cfg(not(test))]
 extern "C" {
+    fn janus_plugin_rtp_reset(packet: *mut PluginRtpPacket);
+
     fn janus_rtp_switching_context_reset(context: *mut janus_rtp_switching_context);
 
     fn janus_rtp_header_update(
@@ -139,6 +157,10 @@ extern "C" {
 
 ////////////////////////////////////////////////////////////////////////////////
 
+#[cfg(test)]
+#[no_mangle]
+unsafe extern "C" fn janus_plugin_rtp_reset(_packet: *mut PluginRtpPacket) {}
+
 #[cfg(test)]
 #[no_mangle]
 unsafe extern "C" fn janus_rtp_switching_context_reset(_context: *mut janus_rtp_switching_context) {
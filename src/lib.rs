@@ -28,22 +28,42 @@ mod utils;
 #[macro_use]
 mod app;
 mod bidirectional_multimap;
+mod catalog;
 mod conf;
+mod crypto;
+mod disk_space;
+mod forwarder;
+mod ingest;
+mod janitor;
 mod janus_callbacks;
 mod janus_recorder;
 mod janus_rtp;
 mod jsep;
+mod manifest;
 mod message_handler;
 mod metrics;
+mod mjr;
+mod ogg;
+mod packet_pool;
+mod postprocess;
 mod recorder;
+mod recovery;
+mod rtp;
+mod s3;
 mod serde;
+mod storage;
 mod switchboard;
 #[cfg(test)]
 mod test_stubs;
+mod timeline;
+mod upload_queue;
+mod vp8;
+mod webm;
 
 use app::App;
 use conf::Config;
 use janus_rtp::JanusRtpHeader;
+use recorder::DataFrame;
 use switchboard::{SessionId, Switchboard};
 
 use crate::{
@@ -191,13 +211,18 @@ extern "C" fn incoming_rtp(handle: *mut PluginSession, packet: *mut PluginRtpPac
 }
 
 fn incoming_rtp_impl(handle: *mut PluginSession, packet: *mut PluginRtpPacket) -> Result<()> {
+    let session_id = session_id(handle)?;
+    handle_publisher_rtp(session_id, unsafe { &mut *packet })
+}
+
+/// Handles an RTP packet from the publisher: relays it to subscribers, forwarders and the recorder.
+/// Used for both WebRTC publishers and plain RTP ingests.
+fn handle_publisher_rtp(session_id: SessionId, mut packet: &mut PluginRtpPacket) -> Result<()> {
     let app = app!()?;
-    let mut packet = unsafe { &mut *packet };
     let is_video = matches!(packet.video, 1);
     let header = JanusRtpHeader::extract(packet);
 
     // Touch last packet timestamp to drop timeout.
-    let session_id = session_id(handle)?;
     app.switchboard.with_read_lock(|switchboard| {
         let state = switchboard.state(session_id)?;
         state.touch_last_rtp_packet_timestamp();
@@ -221,8 +246,9 @@ fn incoming_rtp_impl(handle: *mut PluginSession, packet: *mut PluginRtpPacket) -
         // Send incremental initial or regular REMB to the publisher if needed to control bitrate.
         // Do it only for video because Windows and Linux don't make a difference for media types
         // and apply audio limitation to video while only MacOS does.
+        // Plain RTP ingests have no feedback channel so skip them.
         let remb_interval = chrono::Duration::seconds(5);
-        if is_video {
+        if is_video && !switchboard.is_ingest(session_id) {
             let target_bitrate = writer_config.video_remb();
             let initial_rembs_left = INITIAL_REMBS - state.initial_rembs_counter();
 
@@ -261,6 +287,24 @@ fn incoming_rtp_impl(handle: *mut PluginSession, packet: *mut PluginRtpPacket) -
             }
         }
 
+        // Send packet to RTP forwarders.
+        let forwarders = switchboard.forwarders(stream_id);
+
+        if !forwarders.is_empty() {
+            let buf = unsafe {
+                std::slice::from_raw_parts(packet.buffer as *const u8, packet.length as usize)
+            };
+
+            for forwarder in forwarders {
+                if let Err(err) = forwarder.forward_rtp(buf, is_video) {
+                    huge!(
+                        "Failed to forward an RTP packet: {}", err;
+                        {"rtc_id": stream_id, "forwarder_id": forwarder.id()}
+                    );
+                }
+            }
+        }
+
         // Push packet to the recorder.
         if let Some(recorder) = state.recorder() {
             let buf = unsafe {
@@ -296,6 +340,21 @@ fn incoming_rtcp_impl(handle: *mut PluginSession, packet: *mut PluginRtcpPacket)
                 }
             }
             _ => {
+                if let Some(stream_id) = switchboard.published_by(session_id) {
+                    let buf = unsafe {
+                        slice::from_raw_parts(packet.buffer as *const u8, packet.length as usize)
+                    };
+
+                    for forwarder in switchboard.forwarders(stream_id) {
+                        if let Err(err) = forwarder.forward_rtcp(buf, packet.video == 1) {
+                            huge!(
+                                "Failed to forward an RTCP packet: {}", err;
+                                {"rtc_id": stream_id, "forwarder_id": forwarder.id()}
+                            );
+                        }
+                    }
+                }
+
                 for subscriber in switchboard.subscribers_to(session_id) {
                     let subscriber_session =
                         switchboard.session(*subscriber)?.lock().map_err(|err| {
@@ -315,8 +374,44 @@ fn incoming_rtcp_impl(handle: *mut PluginSession, packet: *mut PluginRtcpPacket)
     })
 }
 
-extern "C" fn incoming_data(_handle: *mut PluginSession, _packet: *mut PluginDataPacket) {
-    // Dropping incoming data.
+extern "C" fn incoming_data(handle: *mut PluginSession, packet: *mut PluginDataPacket) {
+    report_error(incoming_data_impl(handle, packet));
+}
+
+/// Writes text data channel messages of a publisher to the data track of the recording.
+/// Other data is dropped.
+fn incoming_data_impl(handle: *mut PluginSession, packet: *mut PluginDataPacket) -> Result<()> {
+    let session_id = session_id(handle)?;
+    let packet = unsafe { &*packet };
+
+    if packet.binary != 0 || packet.buffer.is_null() {
+        return Ok(());
+    }
+
+    let buf = unsafe { slice::from_raw_parts(packet.buffer as *const u8, packet.length as usize) };
+    let data = std::str::from_utf8(buf).context("Invalid UTF-8 in data channel message")?;
+
+    let label = match packet.label.is_null() {
+        true => "",
+        false => unsafe { CStr::from_ptr(packet.label) }
+            .to_str()
+            .unwrap_or_default(),
+    };
+
+    app!()?.switchboard.with_read_lock(|switchboard| {
+        let stream_id = match switchboard.published_by(session_id) {
+            Some(stream_id) => stream_id,
+            None => return Ok(()),
+        };
+
+        if let Some(recorder) = switchboard.state(session_id)?.recorder() {
+            recorder
+                .record_data(&DataFrame::DataChannel { label, data })
+                .with_context(|| format!("Failed to record data of stream {}", stream_id))?;
+        }
+
+        Ok(())
+    })
 }
 
 extern "C" fn data_ready(_handle: *mut PluginSession) {
@@ -424,6 +519,10 @@ fn send_pli(publisher: SessionId, switchboard: &Switchboard) {
 }
 
 fn send_pli_impl(publisher: SessionId, switchboard: &Switchboard) -> Result<()> {
+    if switchboard.is_ingest(publisher) {
+        return Ok(());
+    }
+
     let session = switchboard
         .session(publisher)?
         .lock()
@@ -446,6 +545,10 @@ fn send_fir(publisher: SessionId, switchboard: &Switchboard) {
 }
 
 fn send_fir_impl(publisher: SessionId, switchboard: &Switchboard) -> Result<()> {
+    if switchboard.is_ingest(publisher) {
+        return Ok(());
+    }
+
     let session = switchboard
         .session(publisher)?
         .lock()
@@ -465,6 +568,17 @@ fn send_fir_impl(publisher: SessionId, switchboard: &Switchboard) -> Result<()>
     Ok(())
 }
 
+/// Asks the publisher for a video keyframe on behalf of the recorder. Sent in background
+/// so the recorder thread never waits for the switchboard lock.
+pub(crate) fn request_keyframe(publisher: SessionId) {
+    async_std::task::spawn_blocking(move || {
+        report_error(app!().and_then(|app| {
+            app.switchboard
+                .with_read_lock(|switchboard| send_fir_impl(publisher, &switchboard))
+        }));
+    });
+}
+
 fn send_remb(publisher: SessionId, bitrate: u32) {
     verb!("Sending REMB bitrate = {}", bitrate; {"handle_id": publisher});
     report_error(send_remb_impl(publisher, bitrate));
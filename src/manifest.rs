@@ -0,0 +1,159 @@
+use std::fs::{self, File};
+use std::io::{self, ErrorKind};
+use std::path::Path;
+
+use anyhow::{Context, Result};
+use sha2::{Digest, Sha256};
+
+use crate::switchboard::{AgentId, SessionId};
+use crate::utils;
+
+///////////////////////////////////////////////////////////////////////////////
+
+const MANIFEST_FILENAME: &str = "manifest.json";
+
+/// Description of the record's segments maintained by the recorder.
+/// Stored in the records directory of the stream. Times are unix timestamps in milliseconds.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Manifest {
+    pub segments: Vec<SegmentEntry>,
+    /// ID of the master key wrapping the data key if the record is encrypted.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub key_id: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SegmentEntry {
+    /// Also the prefix of the segment's filenames.
+    pub started_at: i64,
+    /// Missing while the segment is being recorded or if the recorder has crashed.
+    pub stopped_at: Option<i64>,
+    /// Publisher of the stream.
+    pub agent_id: AgentId,
+    pub handle_id: SessionId,
+    pub files: Vec<FileEntry>,
+    /// Set when the segment has been repaired after a crash.
+    /// Its `stopped_at` is the time of the last write then.
+    #[serde(default)]
+    pub recovered: bool,
+    /// Milliseconds from the segment start to the first video keyframe. Video before it
+    /// is not recorded. Missing if there's no video or no keyframe has come.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub keyframe_wait: Option<u64>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FileEntry {
+    pub filename: String,
+    pub codec: String,
+    pub packets: u64,
+    /// Size in bytes and checksum are filled when the segment gets closed.
+    pub size: Option<u64>,
+    pub sha256: Option<String>,
+}
+
+impl Manifest {
+    pub fn segment_mut(&mut self, started_at: i64) -> Option<&mut SegmentEntry> {
+        self.segments
+            .iter_mut()
+            .rev()
+            .find(|segment| segment.started_at == started_at)
+    }
+
+    /// Leaves only segments with the given prefixes.
+    pub fn retain_prefixes<'a, I>(&mut self, prefixes: I)
+    where
+        I: IntoIterator<Item = &'a str>,
+    {
+        let prefixes = prefixes.into_iter().collect::<Vec<_>>();
+
+        self.segments
+            .retain(|segment| prefixes.contains(&segment.started_at.to_string().as_str()));
+    }
+}
+
+/// Reads the manifest of the records directory.
+/// Missing manifest means there were no segments.
+pub fn read(dir: &Path) -> Result<Manifest> {
+    match fs::read(dir.join(MANIFEST_FILENAME)) {
+        Ok(data) => serde_json::from_slice(&data).context("Failed to parse manifest"),
+        Err(err) if err.kind() == ErrorKind::NotFound => Ok(Manifest::default()),
+        Err(err) => Err(err).context("Failed to read manifest"),
+    }
+}
+
+pub fn write(dir: &Path, manifest: &Manifest) -> Result<()> {
+    let data = serde_json::to_vec_pretty(manifest).context("Failed to serialize manifest")?;
+
+    utils::atomic_write(&dir.join(MANIFEST_FILENAME), &data).context("Failed to write manifest")
+}
+
+/// Returns the size and hex encoded SHA-256 of the file.
+pub fn digest(path: &Path) -> io::Result<(u64, String)> {
+    let mut hasher = Sha256::new();
+    let size = io::copy(&mut File::open(path)?, &mut hasher)?;
+    let hash = hasher
+        .finalize()
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect();
+
+    Ok((size, hash))
+}
+
+///////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use crate::utils::TempDir;
+
+    use super::*;
+
+    #[test]
+    fn write_and_read() -> Result<()> {
+        let dir = TempDir::new("manifest_test")?;
+
+        assert_eq!(read(&dir)?, Manifest::default());
+
+        fs::write(dir.join("1000.audio.mjr"), b"abc")?;
+        let (size, sha256) = digest(&dir.join("1000.audio.mjr"))?;
+        assert_eq!(size, 3);
+
+        assert_eq!(
+            sha256,
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+
+        let segment = |started_at| SegmentEntry {
+            started_at,
+            stopped_at: None,
+            agent_id: String::from("web.john.example.org"),
+            handle_id: SessionId::new(123),
+            files: vec![FileEntry {
+                filename: format!("{}.audio.mjr", started_at),
+                codec: String::from("opus"),
+                packets: 0,
+                size: None,
+                sha256: None,
+            }],
+            recovered: false,
+            keyframe_wait: None,
+        };
+
+        let mut manifest = Manifest {
+            segments: vec![segment(1000), segment(2000)],
+            key_id: None,
+        };
+
+        let entry = manifest.segment_mut(1000).expect("Missing segment");
+        entry.stopped_at = Some(2000);
+        entry.files[0].size = Some(size);
+        entry.files[0].sha256 = Some(sha256);
+        write(&dir, &manifest)?;
+        assert_eq!(read(&dir)?, manifest);
+
+        manifest.retain_prefixes(vec!["2000"]);
+        assert_eq!(manifest.segments, vec![segment(2000)]);
+        Ok(())
+    }
+}
@@ -4,19 +4,22 @@ mod response;
 
 use std::convert::TryFrom;
 
-use anyhow::{format_err, Error};
+use anyhow::{bail, format_err, Error};
 use http::StatusCode;
 use janus::JanssonValue;
 use serde_json::Value as JsonValue;
 use svc_error::{extension::sentry, Error as SvcError};
+use uuid::Uuid;
 
 use self::response::Response;
+use crate::jsep::Jsep;
+use crate::message_handler::{HttpMethod, Method};
 use crate::switchboard::{SessionId, StreamId};
 use crate::utils;
-use crate::{jsep::Jsep, message_handler::Method};
 
 pub use self::operation::{MethodKind, Operation, Result as OperationResult};
 pub use self::request::Request;
+pub use self::response::Payload;
 
 pub struct PreparedRequest<O> {
     request: Request,
@@ -51,6 +54,27 @@ pub fn prepare_request(
     })
 }
 
+pub fn prepare_http_request(
+    method: &str,
+    params: JsonValue,
+) -> anyhow::Result<PreparedRequest<HttpMethod>> {
+    let transaction = Uuid::new_v4().to_string();
+    huge!("Start handling HTTP request"; {"method": method, "transaction": transaction});
+
+    let mut payload = match params {
+        JsonValue::Object(payload) => payload,
+        _ => bail!("Request body must be a JSON object"),
+    };
+
+    payload.insert(String::from("method"), JsonValue::from(method));
+    let method = serde_json::from_value::<HttpMethod>(JsonValue::Object(payload))?;
+
+    Ok(PreparedRequest {
+        request: Request::http(&transaction),
+        operation: method,
+    })
+}
+
 pub async fn handle_request<O: Operation>(request: PreparedRequest<O>) -> Response {
     let result = async {
         let jsep_answer = request
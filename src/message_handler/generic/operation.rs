@@ -20,9 +20,19 @@ pub trait Operation: fmt::Debug + Send + Sync {
 pub enum MethodKind {
     AgentLeave,
     ReaderConfigUpdate,
+    RecordingDelete,
+    RecordingEvent,
+    RecordingList,
+    RecordingMark,
+    RecordingPause,
+    RecordingResume,
     StreamCreate,
+    StreamForwardStart,
+    StreamForwardStop,
+    StreamIngestCreate,
     StreamRead,
     StreamUpload,
+    UploadStatus,
     WriterConfigUpdate,
 }
 
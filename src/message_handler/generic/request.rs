@@ -18,6 +18,12 @@ impl Request {
         }
     }
 
+    /// Requests coming through the HTTP API aren't bound to any Janus handle.
+    /// Janus never generates zero handle id so it's used as a placeholder.
+    pub fn http(transaction: &str) -> Self {
+        Self::new(SessionId::new(0), transaction)
+    }
+
     pub fn set_jsep_offer(self, jsep_offer: JsonValue) -> Self {
         Self {
             jsep_offer: Some(jsep_offer),
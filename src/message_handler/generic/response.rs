@@ -69,6 +69,10 @@ impl Payload {
         }
     }
 
+    pub fn status(&self) -> StatusCode {
+        self.status
+    }
+
     pub fn set_response(self, response: JsonValue) -> Self {
         Self {
             response: Some(response),
@@ -2,19 +2,26 @@ mod generic;
 mod operations;
 
 use std::ffi::CString;
+use std::time::Instant;
 
 use anyhow::{format_err, Context, Result};
 use async_trait::async_trait;
+use http::StatusCode;
 use janus::JanssonValue;
+use serde_json::Value as JsonValue;
+use svc_error::Error as SvcError;
 
 use self::generic::Sender;
 use crate::janus_callbacks;
+use crate::metrics::Metrics;
 use crate::switchboard::SessionId;
+use crate::utils;
 
 pub use self::generic::{
-    handle_request, prepare_request, send_response, MethodKind, Operation, OperationResult,
-    PreparedRequest, Request,
+    handle_request, prepare_http_request, prepare_request, send_response, MethodKind, Operation,
+    OperationResult, PreparedRequest, Request,
 };
+pub use self::operations::stream_upload::run as run_upload;
 
 #[derive(Debug, Clone, Deserialize)]
 #[serde(tag = "method")]
@@ -23,12 +30,32 @@ pub enum Method {
     AgentLeave(operations::agent_leave::Request),
     #[serde(rename = "reader_config.update")]
     ReaderConfigUpdate(operations::reader_config_update::Request),
+    #[serde(rename = "recording.delete")]
+    RecordingDelete(operations::recording_delete::Request),
+    #[serde(rename = "recording.event")]
+    RecordingEvent(operations::recording_event::Request),
+    #[serde(rename = "recording.list")]
+    RecordingList(operations::recording_list::Request),
+    #[serde(rename = "recording.mark")]
+    RecordingMark(operations::recording_mark::Request),
+    #[serde(rename = "recording.pause")]
+    RecordingPause(operations::recording_pause::Request),
+    #[serde(rename = "recording.resume")]
+    RecordingResume(operations::recording_resume::Request),
     #[serde(rename = "stream.create")]
     StreamCreate(operations::stream_create::Request),
+    #[serde(rename = "stream.forward.start")]
+    StreamForwardStart(operations::stream_forward_start::Request),
+    #[serde(rename = "stream.forward.stop")]
+    StreamForwardStop(operations::stream_forward_stop::Request),
+    #[serde(rename = "stream.ingest.create")]
+    StreamIngestCreate(operations::stream_ingest_create::Request),
     #[serde(rename = "stream.read")]
     StreamRead(operations::stream_read::Request),
     #[serde(rename = "stream.upload")]
     StreamUpload(operations::stream_upload::Request),
+    #[serde(rename = "upload.status")]
+    UploadStatus(operations::upload_status::Request),
     #[serde(rename = "writer_config.update")]
     WriterConfigUpdate(operations::writer_config_update::Request),
 }
@@ -39,9 +66,19 @@ impl Operation for Method {
         match self {
             Method::AgentLeave(x) => x.call(request).await,
             Method::ReaderConfigUpdate(x) => x.call(request).await,
+            Method::RecordingDelete(x) => x.call(request).await,
+            Method::RecordingEvent(x) => x.call(request).await,
+            Method::RecordingList(x) => x.call(request).await,
+            Method::RecordingMark(x) => x.call(request).await,
+            Method::RecordingPause(x) => x.call(request).await,
+            Method::RecordingResume(x) => x.call(request).await,
             Method::StreamCreate(x) => x.call(request).await,
+            Method::StreamForwardStart(x) => x.call(request).await,
+            Method::StreamForwardStop(x) => x.call(request).await,
+            Method::StreamIngestCreate(x) => x.call(request).await,
             Method::StreamRead(x) => x.call(request).await,
             Method::StreamUpload(x) => x.call(request).await,
+            Method::UploadStatus(x) => x.call(request).await,
             Method::WriterConfigUpdate(x) => x.call(request).await,
         }
     }
@@ -50,9 +87,19 @@ impl Operation for Method {
         match self {
             Method::AgentLeave(x) => x.stream_id(),
             Method::ReaderConfigUpdate(x) => x.stream_id(),
+            Method::RecordingDelete(x) => x.stream_id(),
+            Method::RecordingEvent(x) => x.stream_id(),
+            Method::RecordingList(x) => x.stream_id(),
+            Method::RecordingMark(x) => x.stream_id(),
+            Method::RecordingPause(x) => x.stream_id(),
+            Method::RecordingResume(x) => x.stream_id(),
             Method::StreamCreate(x) => x.stream_id(),
+            Method::StreamForwardStart(x) => x.stream_id(),
+            Method::StreamForwardStop(x) => x.stream_id(),
+            Method::StreamIngestCreate(x) => x.stream_id(),
             Method::StreamRead(x) => x.stream_id(),
             Method::StreamUpload(x) => x.stream_id(),
+            Method::UploadStatus(x) => x.stream_id(),
             Method::WriterConfigUpdate(x) => x.stream_id(),
         }
     }
@@ -61,14 +108,131 @@ impl Operation for Method {
         match self {
             Method::AgentLeave(x) => x.method_kind(),
             Method::ReaderConfigUpdate(x) => x.method_kind(),
+            Method::RecordingDelete(x) => x.method_kind(),
+            Method::RecordingEvent(x) => x.method_kind(),
+            Method::RecordingList(x) => x.method_kind(),
+            Method::RecordingMark(x) => x.method_kind(),
+            Method::RecordingPause(x) => x.method_kind(),
+            Method::RecordingResume(x) => x.method_kind(),
             Method::StreamCreate(x) => x.method_kind(),
+            Method::StreamForwardStart(x) => x.method_kind(),
+            Method::StreamForwardStop(x) => x.method_kind(),
+            Method::StreamIngestCreate(x) => x.method_kind(),
             Method::StreamRead(x) => x.method_kind(),
             Method::StreamUpload(x) => x.method_kind(),
+            Method::UploadStatus(x) => x.method_kind(),
             Method::WriterConfigUpdate(x) => x.method_kind(),
         }
     }
 }
 
+/// Methods available through the HTTP API. They don't need a WebRTC handle so
+/// there's no need to create a Janus session just to call them.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "method")]
+pub enum HttpMethod {
+    #[serde(rename = "agent.leave")]
+    AgentLeave(operations::agent_leave::Request),
+    #[serde(rename = "reader_config.update")]
+    ReaderConfigUpdate(operations::reader_config_update::Request),
+    #[serde(rename = "recording.delete")]
+    RecordingDelete(operations::recording_delete::Request),
+    #[serde(rename = "recording.event")]
+    RecordingEvent(operations::recording_event::Request),
+    #[serde(rename = "recording.list")]
+    RecordingList(operations::recording_list::Request),
+    #[serde(rename = "recording.mark")]
+    RecordingMark(operations::recording_mark::Request),
+    #[serde(rename = "stream.upload")]
+    StreamUpload(operations::stream_upload::Request),
+    #[serde(rename = "upload.status")]
+    UploadStatus(operations::upload_status::Request),
+    #[serde(rename = "writer_config.update")]
+    WriterConfigUpdate(operations::writer_config_update::Request),
+}
+
+#[async_trait]
+impl Operation for HttpMethod {
+    async fn call(&self, request: &generic::Request) -> OperationResult {
+        match self {
+            HttpMethod::AgentLeave(x) => x.call(request).await,
+            HttpMethod::ReaderConfigUpdate(x) => x.call(request).await,
+            HttpMethod::RecordingDelete(x) => x.call(request).await,
+            HttpMethod::RecordingEvent(x) => x.call(request).await,
+            HttpMethod::RecordingList(x) => x.call(request).await,
+            HttpMethod::RecordingMark(x) => x.call(request).await,
+            HttpMethod::StreamUpload(x) => x.call(request).await,
+            HttpMethod::UploadStatus(x) => x.call(request).await,
+            HttpMethod::WriterConfigUpdate(x) => x.call(request).await,
+        }
+    }
+
+    fn stream_id(&self) -> Option<crate::switchboard::StreamId> {
+        match self {
+            HttpMethod::AgentLeave(x) => x.stream_id(),
+            HttpMethod::ReaderConfigUpdate(x) => x.stream_id(),
+            HttpMethod::RecordingDelete(x) => x.stream_id(),
+            HttpMethod::RecordingEvent(x) => x.stream_id(),
+            HttpMethod::RecordingList(x) => x.stream_id(),
+            HttpMethod::RecordingMark(x) => x.stream_id(),
+            HttpMethod::StreamUpload(x) => x.stream_id(),
+            HttpMethod::UploadStatus(x) => x.stream_id(),
+            HttpMethod::WriterConfigUpdate(x) => x.stream_id(),
+        }
+    }
+
+    fn method_kind(&self) -> Option<MethodKind> {
+        match self {
+            HttpMethod::AgentLeave(x) => x.method_kind(),
+            HttpMethod::ReaderConfigUpdate(x) => x.method_kind(),
+            HttpMethod::RecordingDelete(x) => x.method_kind(),
+            HttpMethod::RecordingEvent(x) => x.method_kind(),
+            HttpMethod::RecordingList(x) => x.method_kind(),
+            HttpMethod::RecordingMark(x) => x.method_kind(),
+            HttpMethod::StreamUpload(x) => x.method_kind(),
+            HttpMethod::UploadStatus(x) => x.method_kind(),
+            HttpMethod::WriterConfigUpdate(x) => x.method_kind(),
+        }
+    }
+}
+
+/// Handles a call of `method` with `params` coming through the HTTP API.
+/// Returns the HTTP status and the payload which is the same as for the Janus transport.
+pub async fn handle_http_request(
+    method: &str,
+    params: JsonValue,
+) -> Result<(StatusCode, JsonValue)> {
+    let now = Instant::now();
+
+    let request = match prepare_http_request(method, params) {
+        Ok(request) => request,
+        Err(err) => {
+            Metrics::observe_failed_request();
+            warn!("Failed to parse HTTP request: {}", err; {"method": method});
+
+            let error = SvcError::builder()
+                .kind("http_request_error", "Error parsing HTTP request")
+                .status(StatusCode::BAD_REQUEST)
+                .detail(&err.to_string())
+                .build();
+
+            let payload = generic::Payload::from(error);
+            return Ok((payload.status(), serde_json::to_value(&payload)?));
+        }
+    };
+
+    Metrics::observe_success_request();
+    let method_kind = request.method_kind();
+    let response = handle_request(request).await;
+
+    if let Some(method) = method_kind {
+        Metrics::observe_request(now, method)
+    }
+
+    let payload = response.payload();
+    Ok((payload.status(), serde_json::to_value(payload)?))
+}
+
 #[derive(Clone, Debug)]
 pub struct JanusSender;
 
@@ -76,6 +240,24 @@ impl JanusSender {
     pub fn new() -> Self {
         Self {}
     }
+
+    /// Pushes an event which is not a response to any request.
+    pub fn notify(&self, session_id: SessionId, event: &JsonValue) -> Result<()> {
+        let payload = utils::serde_to_jansson(event)?;
+
+        app!()?.switchboard.with_read_lock(move |switchboard| {
+            let session = switchboard.session(session_id)?.lock().map_err(|err| {
+                format_err!(
+                    "Failed to acquire mutex for session {}: {}",
+                    session_id,
+                    err
+                )
+            })?;
+
+            janus_callbacks::push_event(&*session, std::ptr::null_mut(), Some(payload), None)
+                .context("Failed to push event")
+        })
+    }
 }
 
 impl Sender for JanusSender {
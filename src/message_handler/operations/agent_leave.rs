@@ -3,8 +3,8 @@ use async_trait::async_trait;
 use http::StatusCode;
 use svc_error::Error as SvcError;
 
+use crate::message_handler::generic::MethodKind;
 use crate::switchboard::{AgentId, StreamId};
-use crate::{janus_callbacks, message_handler::generic::MethodKind};
 
 #[derive(Clone, Debug, Deserialize)]
 pub struct Request {
@@ -16,7 +16,7 @@ struct Response {}
 
 #[async_trait]
 impl super::Operation for Request {
-    async fn call(&self, request: &super::Request) -> super::OperationResult {
+    async fn call(&self, _request: &super::Request) -> super::OperationResult {
         verb!("Calling agent.leave operation"; {"agent_id": self.agent_id});
 
         let error = |status: StatusCode, err: Error| {
@@ -30,22 +30,22 @@ impl super::Operation for Request {
         app!()
             .map_err(|err| error(StatusCode::INTERNAL_SERVER_ERROR, err))?
             .switchboard
-            .with_read_lock(|switchboard| {
-                for session_id in switchboard.agent_sessions(&self.agent_id) {
-                    let session = switchboard.session(*session_id)?.lock().map_err(|err| {
-                        format_err!(
-                            "Failed to acquire session mutex for id = {}: {}",
-                            request.session_id(),
-                            err
-                        )
-                    })?;
-
+            .with_write_lock(|mut switchboard| {
+                for session_id in switchboard.agent_sessions(&self.agent_id).to_owned() {
                     info!(
                         "Agent left; finishing session";
                         {"agent_id": self.agent_id, "session_id": session_id}
                     );
 
-                    janus_callbacks::end_session(&session);
+                    // Disconnection ends Janus session asynchronously and removes RTP ingests
+                    // right away since there's no Janus session behind them.
+                    switchboard.disconnect(session_id).map_err(|err| {
+                        format_err!(
+                            "Failed to disconnect session for id = {}: {}",
+                            session_id,
+                            err
+                        )
+                    })?;
                 }
 
                 Ok(())
@@ -2,7 +2,17 @@ pub use super::{Operation, OperationResult, Request};
 
 pub mod agent_leave;
 pub mod reader_config_update;
+pub mod recording_delete;
+pub mod recording_event;
+pub mod recording_list;
+pub mod recording_mark;
+pub mod recording_pause;
+pub mod recording_resume;
 pub mod stream_create;
+pub mod stream_forward_start;
+pub mod stream_forward_stop;
+pub mod stream_ingest_create;
 pub mod stream_read;
 pub mod stream_upload;
+pub mod upload_status;
 pub mod writer_config_update;
@@ -0,0 +1,66 @@
+use anyhow::{format_err, Error};
+use async_trait::async_trait;
+use http::StatusCode;
+use svc_error::Error as SvcError;
+
+use crate::catalog::RecordingState;
+use crate::message_handler::generic::MethodKind;
+use crate::switchboard::StreamId;
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct Request {
+    id: StreamId,
+}
+
+#[derive(Serialize)]
+struct Response {}
+
+#[async_trait]
+impl super::Operation for Request {
+    async fn call(&self, _request: &super::Request) -> super::OperationResult {
+        verb!("Calling recording.delete operation"; {"rtc_id": self.id});
+
+        let error = |status: StatusCode, err: Error| {
+            SvcError::builder()
+                .kind("recording_delete_error", "Error deleting recording")
+                .status(status)
+                .detail(&err.to_string())
+                .build()
+        };
+
+        let app = app!().map_err(|err| error(StatusCode::INTERNAL_SERVER_ERROR, err))?;
+
+        let state = app
+            .catalog
+            .get(self.id)
+            .map_err(|err| error(StatusCode::INTERNAL_SERVER_ERROR, err))?
+            .map(|entry| entry.state);
+
+        if matches!(state, None | Some(RecordingState::Deleted)) {
+            let err = format_err!("Recording {} not found", self.id);
+            return Err(error(StatusCode::NOT_FOUND, err));
+        }
+
+        if app.is_record_active(self.id) {
+            let err = format_err!("Recording {} is in progress or being uploaded", self.id);
+            return Err(error(StatusCode::CONFLICT, err));
+        }
+
+        app.recorders_creator
+            .new_handle(self.id)
+            .remove_record()
+            .map_err(|err| error(StatusCode::INTERNAL_SERVER_ERROR, err))?;
+
+        app.catalog.set_state(self.id, RecordingState::Deleted);
+        info!("Recording deleted"; {"rtc_id": self.id});
+        Ok(Response {}.into())
+    }
+
+    fn stream_id(&self) -> Option<StreamId> {
+        None
+    }
+
+    fn method_kind(&self) -> Option<MethodKind> {
+        Some(MethodKind::RecordingDelete)
+    }
+}
@@ -0,0 +1,66 @@
+use anyhow::{format_err, Error};
+use async_trait::async_trait;
+use http::StatusCode;
+use serde_json::Value as JsonValue;
+use svc_error::Error as SvcError;
+
+use crate::message_handler::generic::MethodKind;
+use crate::recorder::DataFrame;
+use crate::switchboard::StreamId;
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct Request {
+    id: StreamId,
+    data: JsonValue,
+}
+
+#[derive(Serialize)]
+struct Response {}
+
+#[async_trait]
+impl super::Operation for Request {
+    async fn call(&self, _request: &super::Request) -> super::OperationResult {
+        verb!("Calling recording.event operation"; {"rtc_id": self.id});
+
+        let error = |status: StatusCode, err: Error| {
+            SvcError::builder()
+                .kind("recording_event_error", "Error recording event")
+                .status(status)
+                .detail(&err.to_string())
+                .build()
+        };
+
+        let app = app!().map_err(|err| error(StatusCode::INTERNAL_SERVER_ERROR, err))?;
+
+        if !app.config.recordings.record_data {
+            let err = format_err!("Data recording is disabled");
+            return Err(error(StatusCode::UNPROCESSABLE_ENTITY, err));
+        }
+
+        app.switchboard
+            .with_read_lock(|switchboard| {
+                let publisher = switchboard
+                    .publisher_of(self.id)
+                    .ok_or_else(|| format_err!("Stream {} not found", self.id))?;
+
+                let recorder = switchboard
+                    .state(publisher)?
+                    .recorder()
+                    .ok_or_else(|| format_err!("Stream {} is not being recorded", self.id))?;
+
+                Ok(recorder.record_data(&DataFrame::Api { data: &self.data }))
+            })
+            .map_err(|err| error(StatusCode::NOT_FOUND, err))?
+            .map_err(|err| error(StatusCode::INTERNAL_SERVER_ERROR, err))?;
+
+        Ok(Response {}.into())
+    }
+
+    fn stream_id(&self) -> Option<StreamId> {
+        None
+    }
+
+    fn method_kind(&self) -> Option<MethodKind> {
+        Some(MethodKind::RecordingEvent)
+    }
+}
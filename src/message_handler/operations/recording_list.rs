@@ -0,0 +1,47 @@
+use anyhow::Error;
+use async_trait::async_trait;
+use http::StatusCode;
+use svc_error::Error as SvcError;
+
+use crate::catalog::{CatalogEntry, RecordingState};
+use crate::message_handler::generic::MethodKind;
+use crate::switchboard::StreamId;
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct Request {
+    state: Option<RecordingState>,
+}
+
+#[derive(Serialize)]
+struct Response {
+    recordings: Vec<CatalogEntry>,
+}
+
+#[async_trait]
+impl super::Operation for Request {
+    async fn call(&self, _request: &super::Request) -> super::OperationResult {
+        verb!("Calling recording.list operation");
+
+        let error = |status: StatusCode, err: Error| {
+            SvcError::builder()
+                .kind("recording_list_error", "Error listing recordings")
+                .status(status)
+                .detail(&err.to_string())
+                .build()
+        };
+
+        let recordings = app!()
+            .and_then(|app| app.catalog.list(self.state))
+            .map_err(|err| error(StatusCode::INTERNAL_SERVER_ERROR, err))?;
+
+        Ok(Response { recordings }.into())
+    }
+
+    fn stream_id(&self) -> Option<StreamId> {
+        None
+    }
+
+    fn method_kind(&self) -> Option<MethodKind> {
+        Some(MethodKind::RecordingList)
+    }
+}
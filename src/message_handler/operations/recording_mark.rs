@@ -0,0 +1,67 @@
+use anyhow::{format_err, Error};
+use async_trait::async_trait;
+use http::StatusCode;
+use serde_json::Value as JsonValue;
+use svc_error::Error as SvcError;
+
+use crate::catalog::RecordingState;
+use crate::message_handler::generic::MethodKind;
+use crate::switchboard::StreamId;
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct Request {
+    id: StreamId,
+    label: String,
+    #[serde(default)]
+    payload: Option<JsonValue>,
+}
+
+#[derive(Serialize)]
+struct Response {
+    time: i64,
+}
+
+#[async_trait]
+impl super::Operation for Request {
+    async fn call(&self, _request: &super::Request) -> super::OperationResult {
+        verb!("Calling recording.mark operation"; {"rtc_id": self.id});
+
+        let error = |status: StatusCode, err: Error| {
+            SvcError::builder()
+                .kind("recording_mark_error", "Error marking recording")
+                .status(status)
+                .detail(&err.to_string())
+                .build()
+        };
+
+        let app = app!().map_err(|err| error(StatusCode::INTERNAL_SERVER_ERROR, err))?;
+
+        let state = app
+            .catalog
+            .get(self.id)
+            .map_err(|err| error(StatusCode::INTERNAL_SERVER_ERROR, err))?
+            .map(|entry| entry.state);
+
+        if state != Some(RecordingState::Recording) {
+            let err = format_err!("Stream {} is not being recorded", self.id);
+            return Err(error(StatusCode::NOT_FOUND, err));
+        }
+
+        let time = app
+            .recorders_creator
+            .new_handle(self.id)
+            .mark(self.label.clone(), self.payload.clone())
+            .await
+            .map_err(|err| error(StatusCode::NOT_FOUND, err))?;
+
+        Ok(Response { time }.into())
+    }
+
+    fn stream_id(&self) -> Option<StreamId> {
+        None
+    }
+
+    fn method_kind(&self) -> Option<MethodKind> {
+        Some(MethodKind::RecordingMark)
+    }
+}
@@ -0,0 +1,56 @@
+use anyhow::{format_err, Error};
+use async_trait::async_trait;
+use http::StatusCode;
+use svc_error::Error as SvcError;
+
+use crate::message_handler::generic::MethodKind;
+use crate::switchboard::StreamId;
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct Request {
+    id: StreamId,
+}
+
+#[derive(Serialize)]
+struct Response {}
+
+#[async_trait]
+impl super::Operation for Request {
+    async fn call(&self, _request: &super::Request) -> super::OperationResult {
+        verb!("Calling recording.pause operation"; {"rtc_id": self.id});
+
+        let error = |status: StatusCode, err: Error| {
+            SvcError::builder()
+                .kind("recording_pause_error", "Error pausing recording")
+                .status(status)
+                .detail(&err.to_string())
+                .build()
+        };
+
+        app!()
+            .map_err(|err| error(StatusCode::INTERNAL_SERVER_ERROR, err))?
+            .switchboard
+            .with_read_lock(|switchboard| {
+                let publisher = switchboard
+                    .publisher_of(self.id)
+                    .ok_or_else(|| format_err!("Stream {} not found", self.id))?;
+
+                switchboard
+                    .state(publisher)?
+                    .recorder()
+                    .ok_or_else(|| format_err!("Stream {} is not being recorded", self.id))?
+                    .pause_recording()
+            })
+            .map_err(|err| error(StatusCode::NOT_FOUND, err))?;
+
+        Ok(Response {}.into())
+    }
+
+    fn stream_id(&self) -> Option<StreamId> {
+        None
+    }
+
+    fn method_kind(&self) -> Option<MethodKind> {
+        Some(MethodKind::RecordingPause)
+    }
+}
@@ -5,6 +5,7 @@ use svc_error::Error as SvcError;
 
 use crate::{
     message_handler::generic::MethodKind,
+    recorder::RecordMode,
     switchboard::{AgentId, StreamId},
 };
 
@@ -12,6 +13,24 @@ use crate::{
 pub struct Request {
     id: StreamId,
     agent_id: AgentId,
+    record: Option<Record>,
+}
+
+/// Either a boolean flag or specific media kinds to record.
+#[derive(Clone, Copy, Debug, Deserialize)]
+#[serde(untagged)]
+enum Record {
+    Flag(bool),
+    Mode(RecordMode),
+}
+
+impl From<Record> for RecordMode {
+    fn from(record: Record) -> Self {
+        match record {
+            Record::Flag(flag) => flag.into(),
+            Record::Mode(mode) => mode,
+        }
+    }
 }
 
 #[derive(Serialize)]
@@ -22,23 +41,51 @@ impl super::Operation for Request {
     async fn call(&self, request: &super::Request) -> super::OperationResult {
         verb!("Calling stream.create operation"; {"rtc_id": self.id});
 
-        let internal_error = |err: Error| {
+        let error = |status: StatusCode, err: Error| {
             SvcError::builder()
                 .kind("stream_create_error", "Error creating a stream")
-                .status(StatusCode::INTERNAL_SERVER_ERROR)
+                .status(status)
                 .detail(&err.to_string())
                 .build()
         };
 
+        let internal_error = |err: Error| error(StatusCode::INTERNAL_SERVER_ERROR, err);
         let app = app!().map_err(internal_error)?;
 
+        // Recording is on by default unless it's disabled in config.
+        let record_mode = self
+            .record
+            .map(RecordMode::from)
+            .unwrap_or_else(|| app.config.recordings.enabled.into());
+
+        if record_mode.is_enabled() && !app.config.recordings.enabled {
+            let err = format_err!("Recording is disabled");
+            return Err(error(StatusCode::BAD_REQUEST, err));
+        }
+
+        if record_mode.is_enabled() && app.recorders_creator.disk_space().is_critical() {
+            let err = format_err!("Not enough disk space for recording");
+            return Err(error(StatusCode::INSUFFICIENT_STORAGE, err));
+        }
+
         app.switchboard.with_write_lock(|mut switchboard| {
             switchboard.create_stream(self.id, request.session_id(), self.agent_id.to_owned())?;
 
             let mut start_recording = || {
-                if app.config.recordings.enabled {
+                if record_mode.is_enabled() {
                     let recorder = app.recorders_creator.new_handle(self.id);
-                    recorder.start_recording()?;
+                    recorder.start_recording(
+                        record_mode,
+                        &self.agent_id,
+                        request.session_id(),
+                    )?;
+
+                    let writer_config = switchboard.writer_config(self.id);
+                    recorder.update_writer_config(
+                        writer_config.send_audio(),
+                        writer_config.send_video(),
+                    )?;
+
                     verb!("Attaching recorder"; {"handle_id": request.session_id()});
                     switchboard.state_mut(request.session_id())?.set_recorder(recorder);
                 }
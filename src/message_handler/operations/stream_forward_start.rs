@@ -0,0 +1,88 @@
+use std::net::IpAddr;
+
+use anyhow::Error;
+use async_trait::async_trait;
+use http::StatusCode;
+use svc_error::Error as SvcError;
+
+use crate::forwarder::{
+    ForwarderId, ForwarderTarget, RtpForwarder, DEFAULT_AUDIO_PAYLOAD_TYPE,
+    DEFAULT_VIDEO_PAYLOAD_TYPE,
+};
+use crate::{message_handler::generic::MethodKind, switchboard::StreamId};
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct Request {
+    id: StreamId,
+    host: IpAddr,
+    audio_port: Option<u16>,
+    audio_rtcp_port: Option<u16>,
+    audio_ssrc: Option<u32>,
+    audio_pt: Option<u8>,
+    video_port: Option<u16>,
+    video_rtcp_port: Option<u16>,
+    video_ssrc: Option<u32>,
+    video_pt: Option<u8>,
+}
+
+#[derive(Serialize)]
+struct Response {
+    id: StreamId,
+    forwarder_id: ForwarderId,
+    sdp: String,
+}
+
+#[async_trait]
+impl super::Operation for Request {
+    async fn call(&self, _request: &super::Request) -> super::OperationResult {
+        verb!("Calling stream.forward.start operation"; {"rtc_id": self.id});
+
+        let audio = self.audio_port.map(|port| {
+            ForwarderTarget::new(port, self.audio_pt.unwrap_or(DEFAULT_AUDIO_PAYLOAD_TYPE))
+                .set_rtcp_port(self.audio_rtcp_port)
+                .set_ssrc(self.audio_ssrc)
+        });
+
+        let video = self.video_port.map(|port| {
+            ForwarderTarget::new(port, self.video_pt.unwrap_or(DEFAULT_VIDEO_PAYLOAD_TYPE))
+                .set_rtcp_port(self.video_rtcp_port)
+                .set_ssrc(self.video_ssrc)
+        });
+
+        let forwarder = RtpForwarder::new(self.id, self.host, audio, video)
+            .map_err(|err| error(StatusCode::BAD_REQUEST, err))?;
+
+        let response = Response {
+            id: self.id,
+            forwarder_id: forwarder.id(),
+            sdp: forwarder.sdp(),
+        };
+
+        app!()
+            .map_err(|err| error(StatusCode::INTERNAL_SERVER_ERROR, err))?
+            .switchboard
+            .with_write_lock(|mut switchboard| switchboard.add_forwarder(self.id, forwarder))
+            .map_err(|err| error(StatusCode::NOT_FOUND, err))?;
+
+        Ok(response.into())
+    }
+
+    fn stream_id(&self) -> Option<StreamId> {
+        None
+    }
+
+    fn method_kind(&self) -> Option<MethodKind> {
+        Some(MethodKind::StreamForwardStart)
+    }
+}
+
+fn error(status: StatusCode, err: Error) -> SvcError {
+    SvcError::builder()
+        .kind(
+            "stream_forward_start_error",
+            "Error starting RTP forwarding",
+        )
+        .status(status)
+        .detail(&err.to_string())
+        .build()
+}
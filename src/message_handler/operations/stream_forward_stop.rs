@@ -0,0 +1,52 @@
+use anyhow::Error;
+use async_trait::async_trait;
+use http::StatusCode;
+use svc_error::Error as SvcError;
+
+use crate::forwarder::ForwarderId;
+use crate::{message_handler::generic::MethodKind, switchboard::StreamId};
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct Request {
+    id: StreamId,
+    forwarder_id: ForwarderId,
+}
+
+#[derive(Serialize)]
+struct Response {}
+
+#[async_trait]
+impl super::Operation for Request {
+    async fn call(&self, _request: &super::Request) -> super::OperationResult {
+        verb!(
+            "Calling stream.forward.stop operation";
+            {"rtc_id": self.id, "forwarder_id": self.forwarder_id}
+        );
+
+        let error = |status: StatusCode, err: Error| {
+            SvcError::builder()
+                .kind("stream_forward_stop_error", "Error stopping RTP forwarding")
+                .status(status)
+                .detail(&err.to_string())
+                .build()
+        };
+
+        app!()
+            .map_err(|err| error(StatusCode::INTERNAL_SERVER_ERROR, err))?
+            .switchboard
+            .with_write_lock(|mut switchboard| {
+                switchboard.remove_forwarder(self.id, self.forwarder_id)
+            })
+            .map_err(|err| error(StatusCode::NOT_FOUND, err))?;
+
+        Ok(Response {}.into())
+    }
+
+    fn stream_id(&self) -> Option<StreamId> {
+        None
+    }
+
+    fn method_kind(&self) -> Option<MethodKind> {
+        Some(MethodKind::StreamForwardStop)
+    }
+}
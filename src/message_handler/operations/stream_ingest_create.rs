@@ -0,0 +1,125 @@
+use std::net::IpAddr;
+
+use anyhow::{format_err, Error};
+use async_trait::async_trait;
+use http::StatusCode;
+use svc_error::Error as SvcError;
+
+use crate::forwarder::{DEFAULT_AUDIO_PAYLOAD_TYPE, DEFAULT_VIDEO_PAYLOAD_TYPE};
+use crate::ingest::RtpIngest;
+use crate::recorder::RecordMode;
+use crate::{
+    message_handler::generic::MethodKind,
+    switchboard::{AgentId, StreamId},
+};
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct Request {
+    id: StreamId,
+    agent_id: AgentId,
+    source: Option<IpAddr>,
+    audio_pt: Option<u8>,
+    video_pt: Option<u8>,
+}
+
+#[derive(Serialize)]
+struct Response {
+    id: StreamId,
+    audio_port: u16,
+    video_port: u16,
+}
+
+#[async_trait]
+impl super::Operation for Request {
+    async fn call(&self, _request: &super::Request) -> super::OperationResult {
+        verb!("Calling stream.ingest.create operation"; {"rtc_id": self.id});
+
+        let app = app!().map_err(internal_error)?;
+
+        let ingest_config = app.config.ingest.as_ref().ok_or_else(|| {
+            error(
+                StatusCode::BAD_REQUEST,
+                anyhow!("RTP ingest is not configured"),
+            )
+        })?;
+
+        if app.config.recordings.enabled && app.recorders_creator.disk_space().is_critical() {
+            let err = anyhow!("Not enough disk space for recording");
+            return Err(error(StatusCode::INSUFFICIENT_STORAGE, err));
+        }
+
+        let ingest = RtpIngest::start(
+            ingest_config,
+            self.source,
+            self.audio_pt.unwrap_or(DEFAULT_AUDIO_PAYLOAD_TYPE),
+            self.video_pt.unwrap_or(DEFAULT_VIDEO_PAYLOAD_TYPE),
+        )
+        .map_err(internal_error)?;
+
+        let publisher = ingest.session_id();
+
+        let response = Response {
+            id: self.id,
+            audio_port: ingest.audio_port(),
+            video_port: ingest.video_port(),
+        };
+
+        app.switchboard.with_write_lock(|mut switchboard| {
+            switchboard.create_ingest_stream(self.id, ingest, self.agent_id.to_owned())?;
+
+            let mut start_recording = || {
+                if app.config.recordings.enabled {
+                    let recorder = app.recorders_creator.new_handle(self.id);
+                    recorder.start_recording(RecordMode::All, &self.agent_id, publisher)?;
+
+                    let writer_config = switchboard.writer_config(self.id);
+                    recorder.update_writer_config(
+                        writer_config.send_audio(),
+                        writer_config.send_video(),
+                    )?;
+
+                    verb!("Attaching recorder"; {"handle_id": publisher});
+                    switchboard.state_mut(publisher)?.set_recorder(recorder);
+                }
+
+                Ok(())
+            };
+
+            start_recording().or_else(|err: Error| {
+                err!("Failed to start recording; stopping the ingest"; {"rtc_id": self.id});
+
+                switchboard
+                    .disconnect(publisher)
+                    .map_err(|disconnect_err| {
+                        format_err!(
+                            "Failed to disconnect ingest {}: {} while recovering from another error: {}",
+                            self.id, disconnect_err, err
+                        )
+                    })
+            })
+        })
+        .map_err(internal_error)?;
+
+        Ok(response.into())
+    }
+
+    fn stream_id(&self) -> Option<StreamId> {
+        None
+    }
+
+    fn method_kind(&self) -> Option<MethodKind> {
+        Some(MethodKind::StreamIngestCreate)
+    }
+}
+
+fn error(status: StatusCode, err: Error) -> SvcError {
+    SvcError::builder()
+        .kind("stream_ingest_create_error", "Error creating an RTP ingest")
+        .status(status)
+        .detail(&err.to_string())
+        .build()
+}
+
+fn internal_error(err: Error) -> SvcError {
+    error(StatusCode::INTERNAL_SERVER_ERROR, err)
+}
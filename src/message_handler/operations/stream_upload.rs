@@ -1,48 +1,83 @@
-use std::fs::File;
-use std::io::{BufRead, BufReader};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
 
-use anyhow::{bail, format_err, Context, Error, Result};
-use async_std::process::Command;
+use anyhow::{bail, Context, Error, Result};
 use async_trait::async_trait;
 use http::StatusCode;
+use serde_json::Value as JsonValue;
 use svc_error::Error as SvcError;
-
+use uuid::Uuid;
+
+use crate::conf::UploadConfig;
+use crate::crypto::{self, DataKey};
+use crate::janitor;
+use crate::manifest::{self, Manifest};
+use crate::message_handler::generic::MethodKind;
+use crate::mjr::{self, MjrSummary, Segment};
+use crate::postprocess::{self, Container, EncoderConfig};
+use crate::recorder::RecorderHandle;
+use crate::storage::{self, BackendConfig, ObjectMetadata};
 use crate::switchboard::StreamId;
-use crate::{message_handler::generic::MethodKind, recorder::RecorderHandle};
+use crate::timeline;
+use crate::upload_queue::{Clip, EnqueueError, JobState, OutputProfile, UploadTask};
+use crate::utils::{self, ScratchDir};
 
 #[derive(Clone, Debug, Deserialize)]
 pub struct Request {
     id: StreamId,
-    backend: String,
+    backend: Backends,
     bucket: String,
     object: String,
+    #[serde(default)]
+    clips: Vec<Clip>,
+    #[serde(default)]
+    outputs: Vec<OutputProfile>,
+    #[serde(default)]
+    partial: bool,
 }
 
-#[derive(Serialize)]
-struct Response {
-    id: StreamId,
-    started_at: u64,
-    time: Vec<(u64, u64)>,
-    mjr_dumps_uris: Vec<String>,
-}
+impl Request {
+    fn validate(&self, config: &UploadConfig) -> Result<()> {
+        let backends = self.backend.names();
 
-#[async_trait]
-impl super::Operation for Request {
-    async fn call(&self, _request: &super::Request) -> super::OperationResult {
-        verb!("Calling stream.upload operation"; {"rtc_id": self.id});
+        if backends.is_empty() {
+            bail!("No backends specified");
+        }
+
+        if let Some(name) = backends.iter().find(|name| config.backend(name).is_none()) {
+            bail!("Unknown backend '{}'", name);
+        }
 
-        {
-            let app = app!().map_err(internal_error)?;
+        for (index, clip) in self.clips.iter().enumerate() {
+            if clip.start >= clip.end {
+                bail!("Clip {} ends before it starts", index + 1);
+            }
 
-            if !app.config.upload.backends.contains(&self.backend) {
-                let err = anyhow!("Unknown backend '{}'", self.backend);
-                err!("{}", err; {"rtc_id": self.id});
-                return Err(error(StatusCode::BAD_REQUEST, err));
+            if clip.object.is_some() && !self.outputs.is_empty() {
+                bail!("Clip {} object can't be combined with outputs", index + 1);
             }
         }
 
-        app!()
-            .map_err(internal_error)?
+        for output in &self.outputs {
+            match output.encoder {
+                Some(ref encoder) if !config.encoders.contains_key(encoder) => {
+                    bail!("Unknown encoder '{}'", encoder)
+                }
+                None if output.container == Container::Mp4 => {
+                    bail!("MP4 output requires an encoder")
+                }
+                _ => (),
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Stops the stream and disconnects everyone if it's still ongoing.
+    fn stop_stream(&self) -> Result<()> {
+        app!()?
             .switchboard
             .with_write_lock(|mut switchboard| {
                 // The stream still may be ongoing and we must stop it gracefully.
@@ -72,35 +107,156 @@ impl super::Operation for Request {
 
                 Ok(())
             })
+    }
+}
+
+/// A single backend or several ones to upload the same record to.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(untagged)]
+enum Backends {
+    One(String),
+    Many(Vec<String>),
+}
+
+impl Backends {
+    fn names(&self) -> Vec<String> {
+        match self {
+            Self::One(name) => vec![name.to_owned()],
+            Self::Many(names) => names.to_owned(),
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct Response {
+    id: StreamId,
+    job_id: Uuid,
+    state: JobState,
+}
+
+/// Result of a done upload job.
+#[derive(Serialize)]
+struct UploadResult {
+    id: StreamId,
+    started_at: u64,
+    time: Vec<(u64, u64)>,
+    pauses: Vec<(u64, u64)>,
+    audio_muted: Vec<(u64, u64)>,
+    video_muted: Vec<(u64, u64)>,
+    markers: Vec<MarkerResult>,
+    uris: Vec<String>,
+    outputs: Vec<OutputResult>,
+    clips: Vec<ClipResult>,
+    mjr_dumps_uris: Vec<String>,
+    /// Uploaded segments only.
+    manifest: Manifest,
+}
+
+#[derive(Serialize)]
+struct ClipResult {
+    object: String,
+    /// Times of the first and the last frames relative to `started_at`.
+    start: u64,
+    end: u64,
+    uris: Vec<String>,
+    outputs: Vec<OutputResult>,
+}
+
+#[derive(Serialize)]
+struct MarkerResult {
+    /// Relative to `started_at`.
+    time: u64,
+    label: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    payload: Option<JsonValue>,
+}
+
+#[derive(Serialize)]
+struct OutputResult {
+    object: String,
+    container: Container,
+    uris: Vec<String>,
+}
+
+#[async_trait]
+impl super::Operation for Request {
+    async fn call(&self, _request: &super::Request) -> super::OperationResult {
+        verb!("Calling stream.upload operation"; {"rtc_id": self.id});
+
+        let upload_config = &app!().map_err(internal_error)?.config.upload;
+
+        if let Err(err) = self.validate(upload_config) {
+            err!("{}", err; {"rtc_id": self.id});
+            return Err(error(StatusCode::BAD_REQUEST, err));
+        }
+
+        let unfinished_job = app!()
+            .map_err(internal_error)?
+            .upload_queue
+            .find_unfinished(self.id)
             .map_err(internal_error)?;
+
+        // Partial and final uploads of the same record must not run at the same time.
+        if let Some(job) = unfinished_job.filter(|job| job.task.partial != self.partial) {
+            let err = anyhow!("Upload job {} of the stream is unfinished", job.id);
+            err!("{}", err; {"rtc_id": self.id});
+            return Err(error(StatusCode::CONFLICT, err));
+        }
+
         let recorder = app!()
             .map_err(internal_error)?
             .recorders_creator
             .new_handle(self.id);
-        recorder.wait_stop().await.map_err(internal_error)?;
+
+        let until = match self.partial {
+            true => {
+                // Finalize the current segment to upload everything recorded up to now.
+                async_std::future::timeout(ROTATION_TIMEOUT, recorder.rotate())
+                    .await
+                    .map_err(|_| internal_error(anyhow!("Timed out rotating the recording")))?
+                    .map_err(internal_error)?
+            }
+            false => {
+                self.stop_stream().map_err(internal_error)?;
+
+                recorder.wait_stop().await.map_err(internal_error)?;
+                None
+            }
+        };
 
         recorder
             .check_existence()
             .map_err(|err| error(StatusCode::NOT_FOUND, err))?;
 
-        match upload_record(self).await.map_err(internal_error)? {
-            UploadStatus::AlreadyRunning => {
-                Ok(serde_json::json!({"id": self.id, "state": "already_running"}).into())
-            }
-            UploadStatus::Done => {
-                let (started_at, segments) = parse_segments(&recorder).map_err(internal_error)?;
-                let dumps = get_dump_uris(&recorder).map_err(internal_error)?;
-                recorder.delete_record().map_err(internal_error)?;
-
-                Ok(Response {
-                    id: self.id,
-                    started_at,
-                    time: segments,
-                    mjr_dumps_uris: dumps,
+        let task = UploadTask {
+            id: self.id,
+            backends: self.backend.names(),
+            bucket: self.bucket.to_owned(),
+            object: self.object.to_owned(),
+            clips: self.clips.to_owned(),
+            outputs: self.outputs.to_owned(),
+            partial: self.partial,
+            until,
+        };
+
+        let job = app!()
+            .map_err(internal_error)?
+            .upload_queue
+            .enqueue(task)
+            .map_err(|err| match err {
+                EnqueueError::Conflict(_) => {
+                    err!("{}", err; {"rtc_id": self.id});
+                    error(StatusCode::CONFLICT, err.into())
                 }
-                .into())
-            }
+                EnqueueError::InternalError(err) => internal_error(err),
+            })?;
+
+        Ok(Response {
+            id: self.id,
+            job_id: job.id,
+            state: job.state,
         }
+        .into())
     }
 
     fn stream_id(&self) -> Option<StreamId> {
@@ -129,108 +285,527 @@ fn internal_error(err: Error) -> SvcError {
 
 ///////////////////////////////////////////////////////////////////////////////
 
-pub enum UploadStatus {
-    AlreadyRunning,
-    Done,
+// Partial upload fails if the current segment doesn't get closed in time.
+const ROTATION_TIMEOUT: Duration = Duration::from_secs(10);
+
+// Prefixes of segments uploaded by partial uploads.
+const UPLOADED_SEGMENTS_FILENAME: &str = "uploaded_segments.json";
+
+// Muxed recording which gets uploaded as the requested object.
+const OUTPUT_FILENAME: &str = "full.webm";
+
+/// Muxed file to upload.
+struct Output {
+    filename: String,
+    object: String,
+    container: Container,
+}
+
+/// The whole record or a clip with its outputs.
+struct Part {
+    /// Absolute times of the first and the last frames of a clip.
+    bounds: Option<(u64, u64)>,
+    outputs: Vec<Output>,
 }
 
-const LOCKFILE_EARLY_EXIT_STATUS: i32 = 251;
+/// Muxes and uploads the record of an upload job and deletes it afterwards.
+/// Returns the payload of `upload.status` result.
+pub async fn run(task: &UploadTask) -> Result<JsonValue> {
+    let app = app!()?;
+
+    let backends = task
+        .backends
+        .iter()
+        .map(|name| {
+            app.config
+                .upload
+                .backend(name)
+                .map(|config| (name.as_str(), config))
+                .ok_or_else(|| anyhow!("Unknown backend '{}'", name))
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    let recorder = app.recorders_creator.new_handle(task.id);
+    recorder.check_existence()?;
+    let dir = recorder.get_records_dir();
+    // Removed on return whether the upload succeeds or not so plain outputs
+    // of an encrypted record don't stay on the disk.
+    let outputs_dir = ScratchDir::create(dir.join(postprocess::OUTPUTS_DIR))
+        .context("Failed to create outputs dir")?;
+    let (segments, parts) = prepare_record(task, dir.clone(), outputs_dir.to_path_buf()).await?;
+    let outputs = parts
+        .iter()
+        .flat_map(|part| &part.outputs)
+        .collect::<Vec<_>>();
+    let mut output_uris = vec![vec![]; outputs.len()];
+    let mut dump_uris = vec![];
+
+    for (name, config) in backends {
+        let (uris, dumps) = upload_record(task, name, config, &dir, &outputs_dir, &outputs).await?;
+
+        for (output_uris, uri) in output_uris.iter_mut().zip(uris) {
+            output_uris.push(uri);
+        }
+
+        dump_uris.extend(dumps);
+    }
+
+    let (started_at, time) = parse_segments(&segments)?;
+    let events = timeline::read(&dir)?;
+    let mut manifest = manifest::read(&dir)?;
+    manifest.retain_prefixes(segments.iter().map(Segment::prefix));
+
+    match task.partial {
+        true => clean_up_partial_upload(&recorder, &dir, &segments)?,
+        false => {
+            recorder.delete_record()?;
+
+            // Deletion is disabled so the record is left for the janitor.
+            if dir.exists() {
+                janitor::mark_uploaded(&dir)?;
+            }
+        }
+    }
 
-async fn upload_record(request: &Request) -> Result<UploadStatus> {
-    info!("Preparing & uploading record"; {"rtc_id": request.id});
+    let mut output_uris = output_uris.into_iter();
+    let mut outputs = vec![];
+    let mut clips = vec![];
+
+    for part in parts {
+        let part_outputs = part
+            .outputs
+            .into_iter()
+            .map(|output| OutputResult {
+                object: output.object,
+                container: output.container,
+                uris: output_uris.next().unwrap_or_default(),
+            })
+            .collect::<Vec<_>>();
+
+        match part.bounds {
+            None => outputs = part_outputs,
+            Some((start, end)) => {
+                let (object, uris) = part_outputs
+                    .first()
+                    .map(|output| (output.object.to_owned(), output.uris.to_owned()))
+                    .unwrap_or_default();
+
+                clips.push(ClipResult {
+                    object,
+                    start: start.saturating_sub(started_at),
+                    end: end.saturating_sub(started_at),
+                    uris,
+                    outputs: part_outputs,
+                });
+            }
+        }
+    }
 
-    let mut script_path = std::env::current_exe()
-        .context("Failed to get current executable path")?
-        .parent()
-        .ok_or_else(|| format_err!("Missing current executable dir"))?
-        .to_path_buf();
+    let result = UploadResult {
+        id: task.id,
+        started_at,
+        time,
+        pauses: relative_intervals(timeline::pauses(&events), started_at),
+        audio_muted: relative_intervals(timeline::muted(&events, false), started_at),
+        video_muted: relative_intervals(timeline::muted(&events, true), started_at),
+        markers: relative_markers(&events, started_at, task.until),
+        uris: outputs
+            .first()
+            .map(|output| output.uris.to_owned())
+            .unwrap_or_default(),
+        outputs,
+        clips,
+        mjr_dumps_uris: dump_uris,
+        manifest,
+    };
 
-    script_path.push("upload_record.sh");
-    let mut command = Command::new(&script_path);
-    let stream_id = request.id.to_string();
+    Ok(serde_json::to_value(result)?)
+}
 
-    command.args(&[
-        &stream_id,
-        &request.backend,
-        &request.bucket,
-        &request.object,
-    ]);
+/// Muxes the whole record or each of the task's clips and produces their outputs in `outputs_dir`.
+async fn prepare_record(
+    task: &UploadTask,
+    dir: PathBuf,
+    outputs_dir: PathBuf,
+) -> Result<(Vec<Segment>, Vec<Part>)> {
+    info!("Preparing record"; {"rtc_id": task.id});
+    let app = app!()?;
+    let encoders = &app.config.upload.encoders;
+    let encryption = app.config.recordings.encryption.clone();
+    let task = task.to_owned();
+
+    async_std::task::spawn_blocking(move || {
+        let key = match encryption {
+            Some(ref config) => crypto::read_data_key(&dir, config)?,
+            None => None,
+        };
 
-    huge!("Running stream upload shell command: {:?}", command);
+        let record = Record {
+            dir: &dir,
+            key: key.as_ref(),
+            outputs_dir: &outputs_dir,
+        };
 
-    command
-        .status()
-        .await
-        .map_err(|err| format_err!("Failed to run upload_record.sh, return code = '{}'", err))
-        .and_then(|status| {
-            if status.success() {
-                info!(
-                    "Record successfully uploaded to {}/{}", request.bucket, request.object;
-                    {"rtc_id": request.id}
-                );
+        produce_parts(&task, encoders, record)
+    })
+    .await
+}
 
-                Ok(UploadStatus::Done)
-            } else {
-                match status.code() {
-                    Some(LOCKFILE_EARLY_EXIT_STATUS) => Ok(UploadStatus::AlreadyRunning),
-                    _ => Err(format_err!("Failed to prepare & upload record: {}", status)),
+/// The record to produce outputs from. Its .mjr files are decrypted with `key` while being read
+/// if they're encrypted. Outputs are written to `outputs_dir`.
+#[derive(Clone, Copy)]
+struct Record<'a> {
+    dir: &'a Path,
+    key: Option<&'a DataKey>,
+    outputs_dir: &'a Path,
+}
+
+fn produce_parts(
+    task: &UploadTask,
+    encoders: &HashMap<String, EncoderConfig>,
+    record: Record<'_>,
+) -> Result<(Vec<Segment>, Vec<Part>)> {
+    let uploaded_segments = read_uploaded_segments(record.dir)?;
+
+    let segments = mjr::read_segments(record.dir, record.key, |prefix| {
+        let is_complete = match (task.until, prefix.parse::<i64>()) {
+            (None, _) => true,
+            (Some(until), Ok(started_at)) => started_at < until,
+            (Some(_), Err(_)) => false,
+        };
+
+        is_complete && !uploaded_segments.iter().any(|segment| segment == prefix)
+    })?;
+
+    if segments.is_empty() {
+        bail!("No segments to upload");
+    }
+
+    if task.clips.is_empty() {
+        let webm = String::from(OUTPUT_FILENAME);
+        let output = record.outputs_dir.join(&webm);
+        postprocess::mux(
+            record.dir,
+            record.key,
+            &segments,
+            &output,
+            None,
+            Container::Webm,
+        )?;
+
+        let part = Part {
+            bounds: None,
+            outputs: produce_outputs(task, encoders, record, &segments, &webm, None, None)?,
+        };
+
+        return Ok((segments, vec![part]));
+    }
+
+    let started_at = segments.first().map_or(0, Segment::started_at);
+    let keyframes = postprocess::keyframes(record.dir, record.key, &segments)?;
+    let mut parts = Vec::with_capacity(task.clips.len());
+
+    for (index, clip) in task.clips.iter().enumerate() {
+        let number = index + 1;
+
+        let range = match clip.absolute {
+            true => (clip.start, clip.end),
+            false => (started_at + clip.start, started_at + clip.end),
+        };
+
+        let range = Some(postprocess::align_to_keyframe(&keyframes, range));
+        let webm = format!("clip_{}.webm", number);
+
+        let bounds = postprocess::mux(
+            record.dir,
+            record.key,
+            &segments,
+            &record.outputs_dir.join(&webm),
+            range,
+            Container::Webm,
+        )?
+        .ok_or_else(|| anyhow!("Clip {} has no frames", number))?;
+
+        let clip = Some((number, clip));
+
+        parts.push(Part {
+            bounds: Some(bounds),
+            outputs: produce_outputs(task, encoders, record, &segments, &webm, range, clip)?,
+        });
+    }
+
+    Ok((segments, parts))
+}
+
+/// Produces output files of the task's profiles from the record or its clip
+/// muxed into `webm` file. The file itself is the only output if there're no profiles.
+fn produce_outputs(
+    task: &UploadTask,
+    encoders: &HashMap<String, EncoderConfig>,
+    record: Record<'_>,
+    segments: &[Segment],
+    webm: &str,
+    range: Option<(u64, u64)>,
+    clip: Option<(usize, &Clip)>,
+) -> Result<Vec<Output>> {
+    if task.outputs.is_empty() {
+        let object = match clip {
+            None => task.object.to_owned(),
+            Some((number, clip)) => clip
+                .object
+                .to_owned()
+                .unwrap_or_else(|| clip_object(&task.object, number)),
+        };
+
+        let output = Output {
+            filename: webm.to_owned(),
+            object,
+            container: Container::Webm,
+        };
+
+        return Ok(vec![output]);
+    }
+
+    let dir = record.outputs_dir;
+    let stem = webm.trim_end_matches(".webm");
+
+    task.outputs
+        .iter()
+        .enumerate()
+        .map(|(index, profile)| {
+            let filename = format!("{}_{}.{}", stem, index + 1, profile.container.extension());
+
+            let filename = match (profile.container, &profile.encoder) {
+                (Container::Webm, None) => webm.to_owned(),
+                (container, None) => {
+                    let output = dir.join(&filename);
+                    postprocess::mux(record.dir, record.key, segments, &output, range, container)?;
+                    filename
                 }
-            }
+                (_, Some(encoder)) => {
+                    let config = encoders
+                        .get(encoder)
+                        .ok_or_else(|| anyhow!("Unknown encoder '{}'", encoder))?;
+
+                    postprocess::transcode(config, &dir.join(webm), &dir.join(&filename))
+                        .with_context(|| format!("Failed to transcode with {}", encoder))?;
+
+                    filename
+                }
+            };
+
+            let object = match clip {
+                None => profile.object.to_owned(),
+                Some((number, _)) => clip_object(&profile.object, number),
+            };
+
+            Ok(Output {
+                filename,
+                object,
+                container: profile.container,
+            })
         })
+        .collect()
 }
 
-fn get_dump_uris(recorder: &RecorderHandle) -> Result<Vec<String>> {
-    let mut path = recorder.get_records_dir();
-    path.push("dumps.txt");
-    Ok(BufReader::new(File::open(path)?)
-        .lines()
-        .collect::<Result<Vec<_>, _>>()?)
+fn read_uploaded_segments(dir: &Path) -> Result<Vec<String>> {
+    match fs::read(dir.join(UPLOADED_SEGMENTS_FILENAME)) {
+        Ok(data) => serde_json::from_slice(&data).context("Failed to parse uploaded segments"),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(vec![]),
+        Err(err) => Err(err).context("Failed to read uploaded segments"),
+    }
 }
 
-fn parse_segments(recorder: &RecorderHandle) -> Result<(u64, Vec<(u64, u64)>)> {
-    let mut path = recorder.get_records_dir();
-    path.push("segments.csv");
+/// Remembers uploaded segments to skip them next time and deletes their files.
+fn clean_up_partial_upload(
+    recorder: &RecorderHandle,
+    dir: &Path,
+    segments: &[Segment],
+) -> Result<()> {
+    let mut uploaded_segments = read_uploaded_segments(dir)?;
+    uploaded_segments.extend(segments.iter().map(|segment| segment.prefix().to_owned()));
 
-    let file = File::open(&path)?;
-    let mut segments = vec![];
+    let data = serde_json::to_vec(&uploaded_segments)?;
 
-    for read_result in BufReader::new(file).lines() {
-        let line = match read_result {
-            Ok(line) => line,
-            Err(err) => bail!(err),
-        };
+    utils::atomic_write(&dir.join(UPLOADED_SEGMENTS_FILENAME), &data)
+        .context("Failed to save uploaded segments")?;
 
-        // "123456789,123.45" => (123456789, 123.45)
-        match line.splitn(2, ',').collect::<Vec<&str>>().as_slice() {
-            [started_at, duration] => {
-                let parsed_started_at = started_at
-                    .parse::<u64>()
-                    .context("Failed to parse started_at")?;
+    for segment in segments {
+        recorder.delete_segment(segment.prefix())?;
+    }
 
-                let parsed_duration = duration
-                    .parse::<f32>()
-                    .context("Failed to parse duration")?;
+    Ok(())
+}
 
-                segments.push((parsed_started_at, parsed_duration))
-            }
-            _ => bail!("Failed to split line: {}", line),
+/// Adds the clip number before the extension: `record.webm` => `record.1.webm`.
+fn clip_object(object: &str, number: usize) -> String {
+    match object.rfind('.') {
+        Some(index) if index > 0 && !object[index..].contains('/') => {
+            format!("{}.{}{}", &object[..index], number, &object[index..])
         }
+        _ => format!("{}.{}", object, number),
+    }
+}
+
+/// Uploads .mjr dumps from `dir` and the muxed outputs from `outputs_dir` to the backend.
+/// Returns URIs of the outputs and dumps.
+async fn upload_record(
+    task: &UploadTask,
+    backend_name: &str,
+    config: &BackendConfig,
+    dir: &Path,
+    outputs_dir: &Path,
+    outputs: &[&Output],
+) -> Result<(Vec<String>, Vec<String>)> {
+    info!("Uploading record to {}", backend_name; {"rtc_id": task.id});
+    let backend = config.build()?;
+    let id = task.id.to_string();
+
+    let mut dumps = fs::read_dir(dir)
+        .context("Failed to read records dir")?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| matches!(path.extension(), Some(ext) if ext == "mjr"))
+        .collect::<Vec<_>>();
+
+    dumps.sort();
+
+    let dump_metadata = ObjectMetadata {
+        content_type: None,
+        cache_control: Some("no-cache"),
+    };
+
+    let mut dump_uris = Vec::with_capacity(dumps.len());
+
+    for path in dumps {
+        let filename = path
+            .file_name()
+            .and_then(|filename| filename.to_str())
+            .context("Invalid dump filename")?;
+
+        let key = storage::render_key(
+            &config.dump_key_template,
+            &[
+                ("id", &id),
+                ("bucket", &task.bucket),
+                ("object", &task.object),
+                ("filename", filename),
+            ],
+        );
+
+        let uri = backend
+            .upload(&task.bucket, &key, &path, &dump_metadata)
+            .await
+            .with_context(|| format!("Failed to upload dump {}", filename))?;
+
+        dump_uris.push(uri);
+    }
+
+    let mut uris = Vec::with_capacity(outputs.len());
+
+    for output in outputs {
+        let metadata = ObjectMetadata {
+            content_type: Some(output.container.content_type()),
+            cache_control: Some("no-cache"),
+        };
+
+        let key = storage::render_key(
+            &config.key_template,
+            &[
+                ("id", &id),
+                ("bucket", &task.bucket),
+                ("object", &output.object),
+            ],
+        );
+
+        let uri = backend
+            .upload(
+                &task.bucket,
+                &key,
+                &outputs_dir.join(&output.filename),
+                &metadata,
+            )
+            .await
+            .with_context(|| format!("Failed to upload {}", output.filename))?;
+
+        info!("Record successfully uploaded to {}", uri; {"rtc_id": task.id});
+        uris.push(uri);
     }
 
+    Ok((uris, dump_uris))
+}
+
+fn parse_segments(segments: &[Segment]) -> Result<(u64, Vec<(u64, u64)>)> {
     let absolute_started_at = match segments.first() {
         None => bail!("No segments parsed"),
-        Some((started_at, _)) => started_at.to_owned(),
+        Some(segment) => segment.started_at(),
     };
 
-    // [(123456789, 123.45), (123470134, 456.78)] => [(0, 12345), (13345, 59023)]
+    for segment in segments {
+        for summary in segment.audio().iter().chain(segment.video().iter()) {
+            if summary.is_truncated() {
+                warn!(
+                    "Segment {} has a truncated trailing frame",
+                    segment.prefix()
+                );
+            }
+        }
+
+        let audio_frames = segment.audio().map_or(0, MjrSummary::frames);
+        let video_frames = segment.video().map_or(0, MjrSummary::frames);
+
+        verb!(
+            "Segment {}: duration = {} ms, skew = {:?} ms, audio frames = {}, video frames = {}",
+            segment.prefix(),
+            segment.duration(),
+            segment.skew(),
+            audio_frames,
+            video_frames
+        );
+    }
+
+    // Absolute start times and durations in ms => [(0, 12345), (13345, 59023)]
     let relative_segments = segments
-        .into_iter()
-        .map(|(started_at, duration_sec)| {
-            let relative_started_at = started_at - absolute_started_at;
-            let duration_ms = (duration_sec * 1000.0) as u64;
-            (relative_started_at, relative_started_at + duration_ms)
+        .iter()
+        .map(|segment| {
+            let relative_started_at = segment.started_at() - absolute_started_at;
+            (
+                relative_started_at,
+                relative_started_at + segment.duration(),
+            )
         })
         .collect();
 
     Ok((absolute_started_at, relative_segments))
 }
+
+/// Makes timeline intervals relative to `started_at` in the same way as segments.
+/// Intervals which ended earlier belong to previous partial uploads and are skipped.
+fn relative_intervals(intervals: Vec<(i64, i64)>, started_at: u64) -> Vec<(u64, u64)> {
+    intervals
+        .into_iter()
+        .filter(|(_, end)| *end as u64 >= started_at)
+        .map(|(start, end)| {
+            let start = (start as u64).saturating_sub(started_at);
+            let end = (end as u64).saturating_sub(started_at);
+            (start, end)
+        })
+        .collect()
+}
+
+/// Makes markers relative to `started_at`. Markers added before belong to previous
+/// partial uploads and ones added after `until` belong to the next ones.
+fn relative_markers(
+    events: &[timeline::TimelineEvent],
+    started_at: u64,
+    until: Option<i64>,
+) -> Vec<MarkerResult> {
+    timeline::marks(events)
+        .filter(|(time, _, _)| *time as u64 >= started_at)
+        .filter(|(time, _, _)| !matches!(until, Some(until) if *time >= until))
+        .map(|(time, label, payload)| MarkerResult {
+            time: time as u64 - started_at,
+            label: label.to_owned(),
+            payload: payload.cloned(),
+        })
+        .collect()
+}
@@ -0,0 +1,66 @@
+use anyhow::{format_err, Error};
+use async_trait::async_trait;
+use http::StatusCode;
+use serde_json::Value as JsonValue;
+use svc_error::Error as SvcError;
+use uuid::Uuid;
+
+use crate::message_handler::generic::MethodKind;
+use crate::switchboard::StreamId;
+use crate::upload_queue::JobState;
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct Request {
+    job_id: Uuid,
+}
+
+#[derive(Serialize)]
+struct Response {
+    id: StreamId,
+    job_id: Uuid,
+    state: JobState,
+    attempts: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    result: Option<JsonValue>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+#[async_trait]
+impl super::Operation for Request {
+    async fn call(&self, _request: &super::Request) -> super::OperationResult {
+        verb!("Calling upload.status operation"; {"job_id": self.job_id});
+
+        let error = |status: StatusCode, err: Error| {
+            SvcError::builder()
+                .kind("upload_status_error", "Error getting upload job status")
+                .status(status)
+                .detail(&err.to_string())
+                .build()
+        };
+
+        let job = app!()
+            .and_then(|app| app.upload_queue.get(self.job_id))
+            .map_err(|err| error(StatusCode::INTERNAL_SERVER_ERROR, err))?
+            .ok_or_else(|| format_err!("Upload job {} not found", self.job_id))
+            .map_err(|err| error(StatusCode::NOT_FOUND, err))?;
+
+        Ok(Response {
+            id: job.task.id,
+            job_id: job.id,
+            state: job.state,
+            attempts: job.attempts,
+            result: job.result,
+            error: job.error,
+        }
+        .into())
+    }
+
+    fn stream_id(&self) -> Option<StreamId> {
+        None
+    }
+
+    fn method_kind(&self) -> Option<MethodKind> {
+        Some(MethodKind::UploadStatus)
+    }
+}
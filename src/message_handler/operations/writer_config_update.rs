@@ -57,9 +57,19 @@ impl super::Operation for Request {
                     }
                     let prev_config =
                         switchboard.set_writer_config(config_item.stream_id, writer_config);
-                    if let (Some(prev_config), Some(session_id)) =
-                        (prev_config, switchboard.publisher_of(config_item.stream_id))
+
+                    let publisher = switchboard.publisher_of(config_item.stream_id);
+
+                    // Let the recorder mark muted intervals on the timeline.
+                    if let Some(recorder) = publisher
+                        .and_then(|session_id| switchboard.state(session_id).ok())
+                        .and_then(|state| state.recorder())
                     {
+                        recorder
+                            .update_writer_config(config_item.send_audio, config_item.send_video)?;
+                    }
+
+                    if let (Some(prev_config), Some(session_id)) = (prev_config, publisher) {
                         if (config_item.send_audio && !prev_config.send_audio())
                             || (config_item.send_video && !prev_config.send_video())
                         {
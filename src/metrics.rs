@@ -3,7 +3,7 @@ use std::time::{Duration, Instant};
 use prometheus::{HistogramOpts, HistogramVec, IntCounterVec, IntGaugeVec, Opts, Registry};
 use prometheus_static_metric::make_static_metric;
 
-use crate::{message_handler::MethodKind, switchboard::Switchboard};
+use crate::{disk_space::Level, message_handler::MethodKind, switchboard::Switchboard};
 
 make_static_metric! {
     pub struct RequestStats: IntCounter {
@@ -19,9 +19,19 @@ make_static_metric! {
         "method" => {
             agent_leave,
             reader_config_update,
+            recording_delete,
+            recording_event,
+            recording_list,
+            recording_mark,
+            recording_pause,
+            recording_resume,
             stream_create,
+            stream_forward_start,
+            stream_forward_stop,
+            stream_ingest_create,
             stream_read,
             stream_upload,
+            upload_status,
             writer_config_update,
         },
     }
@@ -45,7 +55,27 @@ make_static_metric! {
         "field" => {
             recorders,
             waiters,
-            queue
+            queue,
+            free_space,
+            disk_space_level,
+        },
+    }
+}
+
+make_static_metric! {
+    pub struct RecorderDrops: IntCounter {
+        "kind" => {
+            audio,
+            video,
+        },
+    }
+}
+
+make_static_metric! {
+    pub struct JanitorStats: IntCounter {
+        "field" => {
+            removed_records,
+            removed_bytes,
         },
     }
 }
@@ -55,6 +85,8 @@ pub struct Metrics {
     request_stats: RequestStats,
     switchboard_stats: SwitchboardStats,
     recorder_stats: RecorderStats,
+    recorder_drops: RecorderDrops,
+    janitor_stats: JanitorStats,
 }
 
 impl std::fmt::Debug for Metrics {
@@ -77,16 +109,29 @@ impl Metrics {
         )?;
         let recorder_stats =
             IntGaugeVec::new(Opts::new("recorder_stats", "Recorder stats"), &["field"])?;
+        let recorder_drops = IntCounterVec::new(
+            Opts::new(
+                "recorder_dropped_packets",
+                "Packets dropped by recorder queue overflow",
+            ),
+            &["kind"],
+        )?;
+        let janitor_stats =
+            IntCounterVec::new(Opts::new("janitor_stats", "Janitor stats"), &["field"])?;
 
         registry.register(Box::new(request_duration.clone()))?;
         registry.register(Box::new(request_stats.clone()))?;
         registry.register(Box::new(switchboard_stats.clone()))?;
         registry.register(Box::new(recorder_stats.clone()))?;
+        registry.register(Box::new(recorder_drops.clone()))?;
+        registry.register(Box::new(janitor_stats.clone()))?;
         Ok(Self {
             request_duration: RequestDuration::from(&request_duration),
             request_stats: RequestStats::from(&request_stats),
             switchboard_stats: SwitchboardStats::from(&switchboard_stats),
             recorder_stats: RecorderStats::from(&recorder_stats),
+            recorder_drops: RecorderDrops::from(&recorder_drops),
+            janitor_stats: JanitorStats::from(&janitor_stats),
         })
     }
 
@@ -135,10 +180,26 @@ impl Metrics {
                 MethodKind::ReaderConfigUpdate => {
                     request_duration.reader_config_update.observe(elapsed)
                 }
+                MethodKind::RecordingDelete => request_duration.recording_delete.observe(elapsed),
+                MethodKind::RecordingEvent => request_duration.recording_event.observe(elapsed),
+                MethodKind::RecordingList => request_duration.recording_list.observe(elapsed),
+                MethodKind::RecordingMark => request_duration.recording_mark.observe(elapsed),
+                MethodKind::RecordingPause => request_duration.recording_pause.observe(elapsed),
+                MethodKind::RecordingResume => request_duration.recording_resume.observe(elapsed),
 
                 MethodKind::StreamCreate => request_duration.stream_create.observe(elapsed),
+                MethodKind::StreamForwardStart => {
+                    request_duration.stream_forward_start.observe(elapsed)
+                }
+                MethodKind::StreamForwardStop => {
+                    request_duration.stream_forward_stop.observe(elapsed)
+                }
+                MethodKind::StreamIngestCreate => {
+                    request_duration.stream_ingest_create.observe(elapsed)
+                }
                 MethodKind::StreamRead => request_duration.stream_read.observe(elapsed),
                 MethodKind::StreamUpload => request_duration.stream_upload.observe(elapsed),
+                MethodKind::UploadStatus => request_duration.upload_status.observe(elapsed),
                 MethodKind::WriterConfigUpdate => {
                     request_duration.writer_config_update.observe(elapsed)
                 }
@@ -157,6 +218,33 @@ impl Metrics {
         }
     }
 
+    pub fn observe_dropped_packet(is_video: bool) {
+        if let Ok(app) = app!() {
+            match is_video {
+                true => app.metrics.recorder_drops.video.inc(),
+                false => app.metrics.recorder_drops.audio.inc(),
+            }
+        }
+    }
+
+    pub fn observe_disk_space(free_space: u64, level: Level) {
+        if let Ok(app) = app!() {
+            app.metrics.recorder_stats.free_space.set(free_space as i64);
+
+            app.metrics
+                .recorder_stats
+                .disk_space_level
+                .set(level as i64);
+        }
+    }
+
+    pub fn observe_record_removal(size: u64) {
+        if let Ok(app) = app!() {
+            app.metrics.janitor_stats.removed_records.inc();
+            app.metrics.janitor_stats.removed_bytes.inc_by(size);
+        }
+    }
+
     #[inline]
     pub fn duration_to_seconds(d: Duration) -> f64 {
         let nanos = f64::from(d.subsec_nanos()) / 1e9;
@@ -0,0 +1,748 @@
+use std::error::Error as StdError;
+use std::fmt;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufReader, Read, Write};
+use std::path::Path;
+use std::time::Instant;
+
+use chrono::Utc;
+
+use crate::crypto::{self, DataKey};
+
+///////////////////////////////////////////////////////////////////////////////
+
+const MAGIC: &[u8] = b"MJR00002";
+const FRAME_MARKER: &[u8] = b"MEET";
+const RTP_HEADER_SIZE: usize = 12;
+
+/// Info header written by Janus before the first frame.
+/// Timestamps are in microseconds.
+#[derive(Debug, Clone, Deserialize)]
+pub struct MjrHeader {
+    #[serde(rename = "c")]
+    codec: String,
+    #[serde(rename = "u")]
+    started_at: i64,
+}
+
+impl MjrHeader {
+    fn clock_rate(&self) -> Option<u64> {
+        match self.codec.as_str() {
+            "opus" => Some(48000),
+            "vp8" | "vp9" | "h264" => Some(90000),
+            "g711" | "pcma" | "pcmu" => Some(8000),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct MjrFrame {
+    /// Milliseconds since the first frame was written.
+    timestamp: u32,
+    data: Vec<u8>,
+}
+
+impl MjrFrame {
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    fn rtp_timestamp(&self) -> Option<u32> {
+        if self.data.len() < RTP_HEADER_SIZE {
+            return None;
+        }
+
+        let bytes = [self.data[4], self.data[5], self.data[6], self.data[7]];
+        Some(u32::from_be_bytes(bytes))
+    }
+}
+
+/// Streaming reader of Janus .mjr recordings.
+///
+/// The layout is the following: `MJR00002` magic, then info header as a JSON preceded with
+/// its u16 BE length, then frames each of which is `MEET` marker, u32 BE timestamp in ms,
+/// u16 BE length and the data itself. A file without frames consists of the magic only.
+pub struct MjrReader<R> {
+    reader: R,
+    header: Option<MjrHeader>,
+    offset: u64,
+}
+
+impl MjrReader<Box<dyn Read>> {
+    /// Opens the file decrypting it with `key` if it's encrypted.
+    pub fn open(path: &Path, key: Option<&DataKey>) -> Result<Self, MjrError> {
+        Self::new(crypto::open(path, key)?)
+    }
+}
+
+impl<R: Read> MjrReader<R> {
+    pub fn new(reader: R) -> Result<Self, MjrError> {
+        let mut mjr_reader = Self {
+            reader,
+            header: None,
+            offset: 0,
+        };
+
+        let mut magic = [0; 8];
+
+        if mjr_reader.read(&mut magic)? < magic.len() || magic != MAGIC {
+            return Err(MjrError::InvalidMagic);
+        }
+
+        let mut length = [0; 2];
+
+        match mjr_reader.read(&mut length)? {
+            0 => return Ok(mjr_reader),
+            2 => (),
+            _ => return Err(MjrError::InvalidHeader("Truncated length".into())),
+        }
+
+        let mut header = vec![0; u16::from_be_bytes(length) as usize];
+
+        if mjr_reader.read(&mut header)? < header.len() {
+            return Err(MjrError::InvalidHeader("Truncated JSON".into()));
+        }
+
+        let header = serde_json::from_slice::<MjrHeader>(&header)
+            .map_err(|err| MjrError::InvalidHeader(err.to_string()))?;
+
+        mjr_reader.header = Some(header);
+        Ok(mjr_reader)
+    }
+
+    /// Missing when no frames have been written.
+    pub fn header(&self) -> Option<&MjrHeader> {
+        self.header.as_ref()
+    }
+
+    pub fn next_frame(&mut self) -> Result<Option<MjrFrame>, MjrError> {
+        let offset = self.offset;
+        let mut frame_header = [0; 10];
+
+        match self.read(&mut frame_header)? {
+            0 => return Ok(None),
+            10 => (),
+            _ => return Err(MjrError::TruncatedFrame { offset }),
+        }
+
+        if &frame_header[..4] != FRAME_MARKER {
+            return Err(MjrError::InvalidFrameMarker { offset });
+        }
+
+        let timestamp = [
+            frame_header[4],
+            frame_header[5],
+            frame_header[6],
+            frame_header[7],
+        ];
+
+        let length = [frame_header[8], frame_header[9]];
+        let mut data = vec![0; u16::from_be_bytes(length) as usize];
+
+        if self.read(&mut data)? < data.len() {
+            return Err(MjrError::TruncatedFrame { offset });
+        }
+
+        Ok(Some(MjrFrame {
+            timestamp: u32::from_be_bytes(timestamp),
+            data,
+        }))
+    }
+
+    // Reads until the buffer is full or EOF is reached.
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut total = 0;
+
+        while total < buf.len() {
+            match self.reader.read(&mut buf[total..]) {
+                Ok(0) => break,
+                Ok(count) => total += count,
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+                Err(err) => return Err(err),
+            }
+        }
+
+        self.offset += total as u64;
+        Ok(total)
+    }
+}
+
+///////////////////////////////////////////////////////////////////////////////
+
+/// Writes frames in the same format as Janus recorder does for cases when
+/// Janus recorder can't write to the file directly, e.g. when it's encrypted.
+///
+/// Every frame as well as the magic and the info header is written with a single `write_all`.
+pub struct MjrWriter<W: Write> {
+    writer: W,
+    codec: &'static str,
+    created_at: i64,
+    started: Option<Instant>,
+    buf: Vec<u8>,
+}
+
+impl<W: Write> MjrWriter<W> {
+    pub fn new(mut writer: W, codec: &'static str) -> io::Result<Self> {
+        writer.write_all(MAGIC)?;
+
+        Ok(Self {
+            writer,
+            codec,
+            created_at: unix_micros(),
+            started: None,
+            buf: vec![],
+        })
+    }
+
+    pub fn save_frame(&mut self, data: &[u8]) -> io::Result<()> {
+        let is_data = self.medium() == "d";
+
+        // Data frames are prefixed with the wall clock time in microseconds.
+        let len = match is_data {
+            true => data.len() + 8,
+            false => data.len(),
+        };
+
+        if len > u16::MAX as usize {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Frame is too big",
+            ));
+        }
+
+        let started = match self.started {
+            Some(started) => started,
+            None => self.write_header()?,
+        };
+
+        let timestamp = started.elapsed().as_millis() as u32;
+        self.buf.clear();
+        self.buf.extend_from_slice(FRAME_MARKER);
+        self.buf.extend_from_slice(&timestamp.to_be_bytes());
+        self.buf.extend_from_slice(&(len as u16).to_be_bytes());
+
+        if is_data {
+            self.buf.extend_from_slice(&unix_micros().to_be_bytes());
+        }
+
+        self.buf.extend_from_slice(data);
+        self.writer.write_all(&self.buf)
+    }
+
+    /// Frames are written through right away so the underlying writer may be finished directly.
+    pub fn get_mut(&mut self) -> &mut W {
+        &mut self.writer
+    }
+
+    // Janus writes the info header along with the first frame.
+    fn write_header(&mut self) -> io::Result<Instant> {
+        let header = serde_json::json!({
+            "t": self.medium(),
+            "c": self.codec,
+            "s": self.created_at,
+            "u": unix_micros(),
+        });
+
+        let header = header.to_string();
+        self.buf.clear();
+        self.buf
+            .extend_from_slice(&(header.len() as u16).to_be_bytes());
+        self.buf.extend_from_slice(header.as_bytes());
+        self.writer.write_all(&self.buf)?;
+
+        let started = Instant::now();
+        self.started = Some(started);
+        Ok(started)
+    }
+
+    // Janus recorder determines the medium by the codec too.
+    fn medium(&self) -> &'static str {
+        match self.codec {
+            "vp8" | "vp9" | "h264" => "v",
+            "text" | "binary" => "d",
+            _ => "a",
+        }
+    }
+}
+
+fn unix_micros() -> i64 {
+    let now = Utc::now();
+    now.timestamp() * 1_000_000 + now.timestamp_subsec_micros() as i64
+}
+
+///////////////////////////////////////////////////////////////////////////////
+
+#[derive(Debug, Clone)]
+pub struct MjrSummary {
+    header: MjrHeader,
+    frames: usize,
+    duration: u64,
+    is_truncated: bool,
+}
+
+impl MjrSummary {
+    /// Reads the whole file. Returns `None` if it has no frames.
+    ///
+    /// A truncated trailing frame is the usual result of a crash so it's skipped
+    /// instead of failing. Duration in ms is calculated from RTP timestamps if the codec's
+    /// clock rate is known or from frame write timestamps otherwise.
+    pub fn read<R: Read>(mut reader: MjrReader<R>) -> Result<Option<Self>, MjrError> {
+        let header = match reader.header() {
+            Some(header) => header.to_owned(),
+            None => return Ok(None),
+        };
+
+        let mut frames = 0;
+        let mut is_truncated = false;
+        let mut write_time_range = TimeRange::default();
+        let mut rtp_time_range = TimeRange::default();
+
+        loop {
+            let frame = match reader.next_frame() {
+                Ok(Some(frame)) => frame,
+                Ok(None) => break,
+                Err(MjrError::TruncatedFrame { .. }) => {
+                    is_truncated = true;
+                    break;
+                }
+                Err(err) => return Err(err),
+            };
+
+            frames += 1;
+            write_time_range.push(frame.timestamp);
+
+            if let Some(rtp_timestamp) = frame.rtp_timestamp() {
+                rtp_time_range.push(rtp_timestamp);
+            }
+        }
+
+        let duration = match header.clock_rate() {
+            Some(clock_rate) => rtp_time_range.length() * 1000 / clock_rate,
+            None => write_time_range.length(),
+        };
+
+        Ok(Some(Self {
+            header,
+            frames,
+            duration,
+            is_truncated,
+        }))
+    }
+
+    pub fn frames(&self) -> usize {
+        self.frames
+    }
+
+    pub fn is_truncated(&self) -> bool {
+        self.is_truncated
+    }
+
+    /// Absolute timestamp of the first frame in milliseconds.
+    pub fn started_at(&self) -> u64 {
+        (self.header.started_at / 1000) as u64
+    }
+
+    fn stopped_at(&self) -> u64 {
+        self.started_at() + self.duration
+    }
+}
+
+/// Tracks the range of 32 bit timestamps which may wrap around.
+#[derive(Default)]
+struct TimeRange {
+    last: Option<u32>,
+    current: i64,
+    min: i64,
+    max: i64,
+}
+
+impl TimeRange {
+    fn push(&mut self, timestamp: u32) {
+        if let Some(last) = self.last {
+            // Signed difference handles both wraparound and reordered packets.
+            self.current += timestamp.wrapping_sub(last) as i32 as i64;
+            self.min = self.min.min(self.current);
+            self.max = self.max.max(self.current);
+        }
+
+        self.last = Some(timestamp);
+    }
+
+    fn length(&self) -> u64 {
+        (self.max - self.min) as u64
+    }
+}
+
+///////////////////////////////////////////////////////////////////////////////
+
+/// Audio and video files of a recording segment sharing the same filename prefix.
+#[derive(Debug, Clone)]
+pub struct Segment {
+    prefix: String,
+    audio: Option<MjrSummary>,
+    video: Option<MjrSummary>,
+}
+
+impl Segment {
+    pub fn prefix(&self) -> &str {
+        &self.prefix
+    }
+
+    pub fn audio(&self) -> Option<&MjrSummary> {
+        self.audio.as_ref()
+    }
+
+    pub fn video(&self) -> Option<&MjrSummary> {
+        self.video.as_ref()
+    }
+
+    /// Absolute timestamp of the first frame of the segment in milliseconds.
+    pub fn started_at(&self) -> u64 {
+        self.summaries()
+            .map(MjrSummary::started_at)
+            .min()
+            .unwrap_or_default()
+    }
+
+    /// Duration in milliseconds including the skew between audio and video.
+    pub fn duration(&self) -> u64 {
+        let stopped_at = self
+            .summaries()
+            .map(MjrSummary::stopped_at)
+            .max()
+            .unwrap_or_default();
+
+        stopped_at.saturating_sub(self.started_at())
+    }
+
+    /// How many milliseconds audio started later than video.
+    pub fn skew(&self) -> Option<i64> {
+        match (&self.audio, &self.video) {
+            (Some(audio), Some(video)) => {
+                Some(audio.started_at() as i64 - video.started_at() as i64)
+            }
+            _ => None,
+        }
+    }
+
+    fn summaries(&self) -> impl Iterator<Item = &MjrSummary> {
+        self.audio.iter().chain(self.video.iter())
+    }
+}
+
+/// Reads all `<prefix>.audio.mjr` and `<prefix>.video.mjr` files of the directory
+/// and groups them into segments ordered by start time. Files without frames are skipped
+/// as well as files of segments with prefixes not matching the `filter`.
+/// Encrypted files are decrypted with `key`.
+pub fn read_segments<F>(
+    dir: &Path,
+    key: Option<&DataKey>,
+    filter: F,
+) -> Result<Vec<Segment>, MjrError>
+where
+    F: Fn(&str) -> bool,
+{
+    let mut segments: Vec<Segment> = vec![];
+
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+
+        let filename = match path.file_name().and_then(|name| name.to_str()) {
+            Some(filename) => filename,
+            None => continue,
+        };
+
+        let (prefix, is_video) = if let Some(prefix) = filename.strip_suffix(".audio.mjr") {
+            (prefix, false)
+        } else if let Some(prefix) = filename.strip_suffix(".video.mjr") {
+            (prefix, true)
+        } else {
+            continue;
+        };
+
+        if !filter(prefix) {
+            continue;
+        }
+
+        let summary = MjrReader::open(&path, key)
+            .and_then(MjrSummary::read)
+            .map_err(|err| MjrError::File {
+                filename: filename.to_owned(),
+                source: Box::new(err),
+            })?;
+
+        let summary = match summary {
+            Some(summary) => summary,
+            None => continue,
+        };
+
+        let segment = match segments.iter_mut().find(|s| s.prefix == prefix) {
+            Some(segment) => segment,
+            None => {
+                segments.push(Segment {
+                    prefix: prefix.to_owned(),
+                    audio: None,
+                    video: None,
+                });
+
+                segments.last_mut().expect("Segment just pushed")
+            }
+        };
+
+        match is_video {
+            true => segment.video = Some(summary),
+            false => segment.audio = Some(summary),
+        }
+    }
+
+    segments.sort_by_key(Segment::started_at);
+    Ok(segments)
+}
+
+///////////////////////////////////////////////////////////////////////////////
+
+/// Result of repairing a file which hasn't been closed properly.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Repair {
+    pub frames: u64,
+    /// Number of bytes cut off the end of the file.
+    pub truncated: u64,
+}
+
+/// Cuts off a partially written or corrupted tail of the file left after a crash
+/// so only complete frames remain. A file with a corrupted info header is left without frames.
+pub fn repair(path: &Path) -> Result<Repair, MjrError> {
+    let size = fs::metadata(path)?.len();
+
+    // The magic itself may be incomplete if the crash happened right after creating the file.
+    if size < MAGIC.len() as u64 {
+        fs::write(path, MAGIC)?;
+        return Ok(Repair::default());
+    }
+
+    let (valid_size, frames) = valid_size(BufReader::new(File::open(path)?))?;
+
+    if valid_size < size {
+        OpenOptions::new()
+            .write(true)
+            .open(path)?
+            .set_len(valid_size)?;
+    }
+
+    Ok(Repair {
+        frames,
+        truncated: size - valid_size,
+    })
+}
+
+// Returns the size of the beginning of the file with complete frames and their number.
+fn valid_size<R: Read>(reader: R) -> Result<(u64, u64), MjrError> {
+    let mut reader = match MjrReader::new(reader) {
+        Ok(reader) => reader,
+        Err(MjrError::InvalidHeader(_)) => return Ok((MAGIC.len() as u64, 0)),
+        Err(err) => return Err(err),
+    };
+
+    let mut frames = 0;
+
+    loop {
+        match reader.next_frame() {
+            Ok(Some(_)) => frames += 1,
+            Ok(None) => return Ok((reader.offset, frames)),
+            Err(MjrError::TruncatedFrame { offset })
+            | Err(MjrError::InvalidFrameMarker { offset }) => return Ok((offset, frames)),
+            Err(err) => return Err(err),
+        }
+    }
+}
+
+///////////////////////////////////////////////////////////////////////////////
+
+#[derive(Debug)]
+pub enum MjrError {
+    IoError(io::Error),
+    InvalidMagic,
+    InvalidHeader(String),
+    InvalidFrameMarker {
+        offset: u64,
+    },
+    TruncatedFrame {
+        offset: u64,
+    },
+    File {
+        filename: String,
+        source: Box<MjrError>,
+    },
+}
+
+impl fmt::Display for MjrError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::IoError(source) => write!(f, "{}", source),
+            Self::InvalidMagic => write!(f, "Not an MJR00002 file"),
+            Self::InvalidHeader(reason) => write!(f, "Invalid info header: {}", reason),
+            Self::InvalidFrameMarker { offset } => {
+                write!(f, "Invalid frame marker at offset {}", offset)
+            }
+            Self::TruncatedFrame { offset } => write!(f, "Truncated frame at offset {}", offset),
+            Self::File { filename, source } => write!(f, "{}: {}", filename, source),
+        }
+    }
+}
+
+impl StdError for MjrError {
+    fn source(&self) -> Option<&(dyn StdError + 'static)> {
+        match self {
+            Self::IoError(source) => Some(source),
+            Self::File { source, .. } => Some(source.as_ref()),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for MjrError {
+    fn from(err: io::Error) -> MjrError {
+        MjrError::IoError(err)
+    }
+}
+
+///////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+
+    fn mjr(header: &str, frames: &[(u32, Vec<u8>)]) -> Vec<u8> {
+        let mut buf = MAGIC.to_vec();
+        buf.extend_from_slice(&(header.len() as u16).to_be_bytes());
+        buf.extend_from_slice(header.as_bytes());
+
+        for (timestamp, data) in frames {
+            buf.extend_from_slice(FRAME_MARKER);
+            buf.extend_from_slice(&timestamp.to_be_bytes());
+            buf.extend_from_slice(&(data.len() as u16).to_be_bytes());
+            buf.extend_from_slice(data);
+        }
+
+        buf
+    }
+
+    fn rtp(timestamp: u32) -> Vec<u8> {
+        let mut packet = vec![0x80, 0x60, 0, 1];
+        packet.extend_from_slice(&timestamp.to_be_bytes());
+        packet.extend_from_slice(&[0, 0, 0, 1, 0xff]);
+        packet
+    }
+
+    const AUDIO_HEADER: &str = r#"{"t":"a","c":"opus","s":1000000000,"u":1000500000}"#;
+
+    #[test]
+    fn read_frames() -> Result<(), MjrError> {
+        let buf = mjr(AUDIO_HEADER, &[(0, rtp(100)), (20, rtp(1060))]);
+        let mut reader = MjrReader::new(Cursor::new(buf))?;
+
+        let header = reader.header().expect("Missing header");
+        assert_eq!(header.codec, "opus");
+        assert_eq!(header.started_at, 1000500000);
+
+        let frame = reader.next_frame()?.expect("Missing first frame");
+        assert_eq!(frame.timestamp, 0);
+        assert_eq!(frame.data, rtp(100));
+
+        let frame = reader.next_frame()?.expect("Missing second frame");
+        assert_eq!(frame.timestamp, 20);
+        assert!(reader.next_frame()?.is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn summarize() -> Result<(), MjrError> {
+        // 2 seconds of opus with RTP timestamp wraparound.
+        let frames = vec![
+            (0, rtp(u32::MAX - 47999)),
+            (1000, rtp(0)),
+            (2000, rtp(48000)),
+        ];
+
+        let buf = mjr(AUDIO_HEADER, &frames);
+        let summary = MjrSummary::read(MjrReader::new(Cursor::new(buf))?)?.expect("Empty");
+        assert_eq!(summary.frames(), 3);
+        assert_eq!(summary.duration, 2000);
+        assert_eq!(summary.started_at(), 1000500);
+        assert!(!summary.is_truncated());
+        Ok(())
+    }
+
+    #[test]
+    fn report_corruption() -> Result<(), MjrError> {
+        let empty = MjrReader::new(Cursor::new(MAGIC.to_vec()))?;
+        assert!(MjrSummary::read(empty)?.is_none());
+
+        let result = MjrReader::new(Cursor::new(b"MJR00001".to_vec()));
+        assert!(matches!(result, Err(MjrError::InvalidMagic)));
+
+        let mut buf = mjr(AUDIO_HEADER, &[(0, rtp(100)), (20, rtp(1060))]);
+        buf.truncate(buf.len() - 3);
+        let summary = MjrSummary::read(MjrReader::new(Cursor::new(buf))?)?.expect("Empty");
+        assert_eq!(summary.frames(), 1);
+        assert!(summary.is_truncated());
+
+        let mut buf = mjr(AUDIO_HEADER, &[(0, rtp(100))]);
+        let frame_offset = buf.len() as u64;
+        buf.extend_from_slice(b"MEEP");
+        buf.extend_from_slice(&[0; 6]);
+        let mut reader = MjrReader::new(Cursor::new(buf))?;
+        reader.next_frame()?;
+
+        match reader.next_frame() {
+            Err(MjrError::InvalidFrameMarker { offset }) => assert_eq!(offset, frame_offset),
+            other => panic!("Unexpected result: {:?}", other),
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn write_frames() -> Result<(), MjrError> {
+        let mut writer = MjrWriter::new(vec![], "opus")?;
+        writer.save_frame(&rtp(100))?;
+        writer.save_frame(&rtp(1060))?;
+
+        let mut reader = MjrReader::new(Cursor::new(writer.writer))?;
+        assert_eq!(reader.header().expect("Missing header").codec, "opus");
+        assert_eq!(reader.next_frame()?.expect("Missing frame").data, rtp(100));
+        assert_eq!(reader.next_frame()?.expect("Missing frame").data, rtp(1060));
+        assert!(reader.next_frame()?.is_none());
+
+        let mut writer = MjrWriter::new(vec![], "text")?;
+        writer.save_frame(b"hello")?;
+
+        let mut reader = MjrReader::new(Cursor::new(writer.writer))?;
+        let frame = reader.next_frame()?.expect("Missing frame");
+        assert_eq!(&frame.data[8..], b"hello");
+        Ok(())
+    }
+
+    #[test]
+    fn find_valid_size() -> Result<(), MjrError> {
+        let buf = mjr(AUDIO_HEADER, &[(0, rtp(100)), (20, rtp(1060))]);
+        let size = buf.len() as u64;
+        assert_eq!(valid_size(Cursor::new(buf.clone()))?, (size, 2));
+
+        let mut truncated = buf.clone();
+        truncated.truncate(buf.len() - 3);
+        assert_eq!(valid_size(Cursor::new(truncated))?, (size - 23, 1));
+
+        let mut garbage = buf.clone();
+        garbage.extend_from_slice(&[0; 100]);
+        assert_eq!(valid_size(Cursor::new(garbage))?, (size, 2));
+
+        let mut broken_header = buf;
+        broken_header.truncate(20);
+        assert_eq!(valid_size(Cursor::new(broken_header))?, (8, 0));
+        Ok(())
+    }
+}
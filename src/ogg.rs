@@ -0,0 +1,231 @@
+use std::io::{self, Write};
+
+use crate::webm::opus_head;
+
+///////////////////////////////////////////////////////////////////////////////
+
+const CAPTURE_PATTERN: &[u8] = b"OggS";
+const BEGINNING_OF_STREAM: u8 = 0x02;
+const END_OF_STREAM: u8 = 0x04;
+
+// There's a single logical stream in the file so any serial number fits.
+const SERIAL_NUMBER: u32 = 1;
+
+// Opus granule position is always in 48 kHz samples.
+const SAMPLES_PER_MILLISECOND: u64 = 48;
+
+const MAX_SEGMENT_SIZE: usize = 255;
+
+/// Writes Opus packets to an Ogg file, one packet per page.
+///
+/// Packets are expected in timecode order. The last page has to be marked
+/// with end of stream flag so a packet is written only when the next one comes.
+pub struct OggWriter<W: Write> {
+    writer: W,
+    sequence: u32,
+    pending: Option<(Vec<u8>, u64)>,
+    last_granule: u64,
+    last_timecode: u64,
+}
+
+impl<W: Write> OggWriter<W> {
+    pub fn new(writer: W) -> io::Result<Self> {
+        let mut ogg = Self {
+            writer,
+            sequence: 0,
+            pending: None,
+            last_granule: 0,
+            last_timecode: 0,
+        };
+
+        ogg.write_page(BEGINNING_OF_STREAM, 0, &opus_head())?;
+        ogg.write_page(0, 0, &opus_tags())?;
+        Ok(ogg)
+    }
+
+    /// Writes a packet with absolute `timecode` in milliseconds.
+    /// Timecodes going backwards are clamped to keep the file valid.
+    pub fn write_packet(&mut self, timecode: u64, data: &[u8]) -> io::Result<()> {
+        let timecode = timecode.max(self.last_timecode);
+
+        // Granule position of a page is the end of its last packet.
+        let granule =
+            (timecode * SAMPLES_PER_MILLISECOND + packet_samples(data)).max(self.last_granule);
+
+        if let Some((packet, granule)) = self.pending.take() {
+            self.write_page(0, granule, &packet)?;
+        }
+
+        self.pending = Some((data.to_vec(), granule));
+        self.last_granule = granule;
+        self.last_timecode = timecode;
+        Ok(())
+    }
+
+    /// Timecode of the last written packet in milliseconds.
+    pub fn last_timecode(&self) -> u64 {
+        self.last_timecode
+    }
+
+    pub fn finish(mut self) -> io::Result<W> {
+        let (packet, granule) = self.pending.take().unwrap_or((vec![], self.last_granule));
+
+        self.write_page(END_OF_STREAM, granule, &packet)?;
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+
+    fn write_page(&mut self, header_type: u8, granule: u64, packet: &[u8]) -> io::Result<()> {
+        // Packets longer than a page are never produced by WebRTC Opus encoders.
+        if packet.len() >= MAX_SEGMENT_SIZE * MAX_SEGMENT_SIZE {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Packet doesn't fit into a page",
+            ));
+        }
+
+        // The last lacing value is less than 255 which marks the end of the packet.
+        let mut lacing = vec![MAX_SEGMENT_SIZE as u8; packet.len() / MAX_SEGMENT_SIZE];
+        lacing.push((packet.len() % MAX_SEGMENT_SIZE) as u8);
+
+        let mut page = Vec::with_capacity(27 + lacing.len() + packet.len());
+        page.extend_from_slice(CAPTURE_PATTERN);
+        page.push(0);
+        page.push(header_type);
+        page.extend_from_slice(&granule.to_le_bytes());
+        page.extend_from_slice(&SERIAL_NUMBER.to_le_bytes());
+        page.extend_from_slice(&self.sequence.to_le_bytes());
+        // Checksum is calculated with this field zeroed.
+        page.extend_from_slice(&[0; 4]);
+        page.push(lacing.len() as u8);
+        page.extend_from_slice(&lacing);
+        page.extend_from_slice(packet);
+
+        let checksum = crc32(&page);
+        page[22..26].copy_from_slice(&checksum.to_le_bytes());
+
+        self.writer.write_all(&page)?;
+        self.sequence += 1;
+        Ok(())
+    }
+}
+
+fn opus_tags() -> Vec<u8> {
+    let vendor = env!("CARGO_PKG_NAME").as_bytes();
+    let mut tags = b"OpusTags".to_vec();
+    tags.extend_from_slice(&(vendor.len() as u32).to_le_bytes());
+    tags.extend_from_slice(vendor);
+    // No user comments.
+    tags.extend_from_slice(&0u32.to_le_bytes());
+    tags
+}
+
+/// Number of 48 kHz samples in an Opus packet according to its TOC byte (RFC 6716, 3.1).
+fn packet_samples(packet: &[u8]) -> u64 {
+    let toc = match packet.first() {
+        Some(toc) => *toc,
+        None => return 0,
+    };
+
+    let config = toc >> 3;
+
+    // Frame durations in 1/400 of a second.
+    let frame_size = match config {
+        0..=11 => [4, 8, 16, 24][(config % 4) as usize],
+        12..=15 => [4, 8][(config % 2) as usize],
+        _ => [1, 2, 4, 8][(config % 4) as usize],
+    };
+
+    let frames = match toc & 0x03 {
+        0 => 1,
+        1 | 2 => 2,
+        _ => packet.get(1).map_or(0, |count| count & 0x3F) as u64,
+    };
+
+    frames * frame_size * 120
+}
+
+// CRC-32 with 0x04C11DB7 polynomial, zero initial value and no reflection.
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = 0u32;
+
+    for byte in data {
+        crc ^= (*byte as u32) << 24;
+
+        for _ in 0..8 {
+            crc = match crc & 0x8000_0000 {
+                0 => crc << 1,
+                _ => (crc << 1) ^ 0x04C1_1DB7,
+            };
+        }
+    }
+
+    crc
+}
+
+///////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Returns header type, granule position and packet of each page.
+    fn pages(mut buf: &[u8]) -> Vec<(u8, u64, Vec<u8>)> {
+        let mut pages = vec![];
+
+        while !buf.is_empty() {
+            assert_eq!(&buf[..4], CAPTURE_PATTERN);
+            let mut granule = [0; 8];
+            granule.copy_from_slice(&buf[6..14]);
+            let segments = buf[26] as usize;
+            let size = buf[27..27 + segments]
+                .iter()
+                .map(|x| *x as usize)
+                .sum::<usize>();
+            let page_size = 27 + segments + size;
+
+            let mut page = buf[..page_size].to_vec();
+            let checksum = page[22..26].to_vec();
+            page[22..26].copy_from_slice(&[0; 4]);
+            assert_eq!(crc32(&page).to_le_bytes().to_vec(), checksum);
+
+            pages.push((
+                buf[5],
+                u64::from_le_bytes(granule),
+                buf[27 + segments..page_size].to_vec(),
+            ));
+
+            buf = &buf[page_size..];
+        }
+
+        pages
+    }
+
+    #[test]
+    fn write_file() -> io::Result<()> {
+        // 20 ms CELT frames.
+        let mut writer = OggWriter::new(vec![])?;
+        writer.write_packet(0, &[0xfc, 0x01])?;
+        writer.write_packet(20, &[0xfc, 0x02])?;
+        let buf = writer.finish()?;
+
+        let pages = pages(&buf);
+        assert_eq!(pages.len(), 4);
+        assert_eq!(pages[0].0, BEGINNING_OF_STREAM);
+        assert_eq!(&pages[0].2[..8], b"OpusHead");
+        assert_eq!(&pages[1].2[..8], b"OpusTags");
+        assert_eq!(pages[2], (0, 960, vec![0xfc, 0x01]));
+        assert_eq!(pages[3], (END_OF_STREAM, 1920, vec![0xfc, 0x02]));
+        Ok(())
+    }
+
+    #[test]
+    fn count_samples() {
+        // SILK 60 ms, hybrid 10 ms, CELT 2.5 ms x 2 and CELT 20 ms x 3 (code 3).
+        assert_eq!(packet_samples(&[0x18]), 2880);
+        assert_eq!(packet_samples(&[0x60]), 480);
+        assert_eq!(packet_samples(&[0x81]), 240);
+        assert_eq!(packet_samples(&[0xfb, 0x03]), 2880);
+        assert_eq!(packet_samples(&[]), 0);
+    }
+}
@@ -0,0 +1,476 @@
+use std::fs::File;
+use std::io::{self, BufWriter, Read};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use anyhow::{bail, Context, Result};
+
+use crate::crypto::DataKey;
+use crate::mjr::{MjrError, MjrReader, MjrSummary, Segment};
+use crate::ogg::OggWriter;
+use crate::rtp;
+use crate::vp8;
+use crate::webm::{Track, WebmWriter};
+
+const OPUS_CLOCK_RATE: i64 = 48000;
+const VP8_CLOCK_RATE: i64 = 90000;
+
+/// Subdirectory of a record to write muxed and transcoded files to.
+pub const OUTPUTS_DIR: &str = ".outputs";
+
+// Used when there's no keyframe in the recording to get the size from.
+const DEFAULT_VIDEO_SIZE: (u16, u16) = (640, 480);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Container {
+    Webm,
+    /// Audio only.
+    Ogg,
+    /// Can't be muxed natively and requires an external encoder.
+    Mp4,
+}
+
+impl Container {
+    pub fn extension(self) -> &'static str {
+        match self {
+            Self::Webm => "webm",
+            Self::Ogg => "ogg",
+            Self::Mp4 => "mp4",
+        }
+    }
+
+    pub fn content_type(self) -> &'static str {
+        match self {
+            Self::Webm => "video/webm",
+            Self::Ogg => "audio/ogg",
+            Self::Mp4 => "video/mp4",
+        }
+    }
+}
+
+/// External command to transcode a muxed WebM file.
+#[derive(Clone, Debug, Deserialize)]
+pub struct EncoderConfig {
+    /// Program and its arguments. `{input}` and `{output}` get replaced with file paths.
+    pub command: Vec<String>,
+}
+
+impl EncoderConfig {
+    pub fn check(&self) -> Result<()> {
+        if self.command.is_empty() {
+            bail!("Encoder command is empty");
+        }
+
+        if !self.command.iter().any(|arg| arg.contains("{output}")) {
+            bail!("Encoder command has no {{output}} placeholder");
+        }
+
+        Ok(())
+    }
+}
+
+/// Runs the encoder to convert `input` to `output`.
+pub fn transcode(config: &EncoderConfig, input: &Path, output: &Path) -> Result<()> {
+    let args = config
+        .command
+        .iter()
+        .map(|arg| {
+            arg.replace("{input}", &input.to_string_lossy())
+                .replace("{output}", &output.to_string_lossy())
+        })
+        .collect::<Vec<_>>();
+
+    let result = Command::new(&args[0])
+        .args(&args[1..])
+        .output()
+        .with_context(|| format!("Failed to run {}", args[0]))?;
+
+    if !result.status.success() {
+        let stderr = String::from_utf8_lossy(&result.stderr);
+        let reason = stderr.trim().lines().last().unwrap_or_default();
+        bail!("{} exited with {}: {}", args[0], result.status, reason);
+    }
+
+    Ok(())
+}
+
+/// Muxes VP8 and Opus recordings of the segments into a single WebM file or
+/// Opus recordings only into an Ogg file.
+///
+/// Segments are concatenated one after another. Within a segment the track which
+/// started later is delayed by the difference of the first frames' write timestamps
+/// to keep audio and video in sync.
+///
+/// If `range` of absolute times in milliseconds is specified only frames within it
+/// get muxed and the clip starts from zero timecode. Returns absolute times of the first
+/// and the last muxed frames or `None` if there're no frames.
+///
+/// Encrypted recordings are decrypted with `key` while being read.
+pub fn mux(
+    dir: &Path,
+    key: Option<&DataKey>,
+    segments: &[Segment],
+    output: &Path,
+    range: Option<(u64, u64)>,
+    container: Container,
+) -> Result<Option<(u64, u64)>> {
+    if segments.is_empty() {
+        bail!("No segments to mux");
+    }
+
+    let has_audio = segments.iter().any(|segment| segment.audio().is_some());
+    let has_video = container == Container::Webm;
+
+    let file =
+        File::create(output).with_context(|| format!("Failed to create {}", output.display()))?;
+
+    let mut writer = match container {
+        Container::Webm => {
+            let video_size = match segments.iter().any(|segment| segment.video().is_some()) {
+                true => Some(probe_video_size(dir, key, segments)?.unwrap_or(DEFAULT_VIDEO_SIZE)),
+                false => None,
+            };
+
+            let writer = WebmWriter::new(BufWriter::new(file), video_size, has_audio)
+                .context("Failed to write WebM header")?;
+
+            Writer::Webm(writer)
+        }
+        Container::Ogg => {
+            let writer =
+                OggWriter::new(BufWriter::new(file)).context("Failed to write Ogg header")?;
+
+            Writer::Ogg(writer)
+        }
+        Container::Mp4 => bail!("MP4 can't be muxed natively"),
+    };
+
+    let mut base = 0;
+    // Clip timecodes are shifted by the timecode of its first frame.
+    let mut clip_offset = None;
+    let mut bounds: Option<(u64, u64)> = None;
+    let mut has_video_keyframe = range.is_none();
+
+    for segment in segments {
+        if let Some((start, end)) = range {
+            if segment.started_at() >= end || segment.started_at() + segment.duration() < start {
+                base += segment.duration();
+                continue;
+            }
+        }
+
+        let mut audio = TrackReader::open(dir, key, segment, Track::Audio, base)?;
+        let mut video = match has_video {
+            true => TrackReader::open(dir, key, segment, Track::Video, base)?,
+            false => None,
+        };
+
+        let mut next_audio = TrackReader::next(&mut audio)?;
+        let mut next_video = TrackReader::next(&mut video)?;
+
+        loop {
+            let is_audio = match (&next_audio, &next_video) {
+                (None, None) => break,
+                (Some(_), None) => true,
+                (None, Some(_)) => false,
+                (Some(audio), Some(video)) => audio.timecode <= video.timecode,
+            };
+
+            let (frame, track) = if is_audio {
+                let frame = std::mem::replace(&mut next_audio, TrackReader::next(&mut audio)?);
+                (frame, Track::Audio)
+            } else {
+                let frame = std::mem::replace(&mut next_video, TrackReader::next(&mut video)?);
+                (frame, Track::Video)
+            };
+
+            let frame = match frame {
+                Some(frame) => frame,
+                None => continue,
+            };
+
+            if let Some((start, end)) = range {
+                if frame.time < start || frame.time >= end {
+                    continue;
+                }
+
+                // Segment readers start with a keyframe but a clip may start in the middle.
+                if track == Track::Video && !has_video_keyframe {
+                    if !frame.is_keyframe {
+                        continue;
+                    }
+
+                    has_video_keyframe = true;
+                }
+            }
+
+            let offset = *clip_offset.get_or_insert(frame.timecode);
+
+            writer
+                .write_frame(
+                    track,
+                    frame.timecode - offset,
+                    &frame.data,
+                    frame.is_keyframe,
+                )
+                .context("Failed to write frame")?;
+
+            bounds = match bounds {
+                None => Some((frame.time, frame.time)),
+                Some((first, last)) => Some((first, last.max(frame.time))),
+            };
+        }
+
+        let last_timecode = writer.last_timecode() + clip_offset.unwrap_or(0);
+        base = (base + segment.duration()).max(last_timecode);
+    }
+
+    writer.finish().context("Failed to finish file")?;
+    Ok(bounds)
+}
+
+/// Returns absolute times in milliseconds of video keyframes of the segments.
+pub fn keyframes(dir: &Path, key: Option<&DataKey>, segments: &[Segment]) -> Result<Vec<u64>> {
+    let mut keyframes = vec![];
+
+    for segment in segments {
+        if let Some(mut reader) = TrackReader::open(dir, key, segment, Track::Video, 0)? {
+            while let Some(frame) = reader.next_frame()? {
+                if frame.is_keyframe {
+                    keyframes.push(frame.time);
+                }
+            }
+        }
+    }
+
+    Ok(keyframes)
+}
+
+/// Moves the start of the range back to the nearest keyframe so the clip's video
+/// doesn't begin with a gap. The range is kept as is if there's no keyframe before it.
+pub fn align_to_keyframe(keyframes: &[u64], (start, end): (u64, u64)) -> (u64, u64) {
+    match keyframes.iter().rev().find(|keyframe| **keyframe <= start) {
+        Some(keyframe) => (*keyframe, end),
+        None => (start, end),
+    }
+}
+
+fn mjr_path(dir: &Path, segment: &Segment, track: Track) -> PathBuf {
+    let kind = match track {
+        Track::Audio => "audio",
+        Track::Video => "video",
+    };
+
+    dir.join(format!("{}.{}.mjr", segment.prefix(), kind))
+}
+
+fn probe_video_size(
+    dir: &Path,
+    key: Option<&DataKey>,
+    segments: &[Segment],
+) -> Result<Option<(u16, u16)>> {
+    for segment in segments.iter().filter(|segment| segment.video().is_some()) {
+        if let Some(mut reader) = TrackReader::open(dir, key, segment, Track::Video, 0)? {
+            while let Some(frame) = reader.next_frame()? {
+                if let Some(size) = vp8::frame_size(&frame.data) {
+                    return Ok(Some(size));
+                }
+            }
+        }
+    }
+
+    Ok(None)
+}
+
+///////////////////////////////////////////////////////////////////////////////
+
+enum Writer {
+    Webm(WebmWriter<BufWriter<File>>),
+    Ogg(OggWriter<BufWriter<File>>),
+}
+
+impl Writer {
+    fn write_frame(
+        &mut self,
+        track: Track,
+        timecode: u64,
+        data: &[u8],
+        is_keyframe: bool,
+    ) -> io::Result<()> {
+        match self {
+            Self::Webm(writer) => writer.write_frame(track, timecode, data, is_keyframe),
+            Self::Ogg(writer) => writer.write_packet(timecode, data),
+        }
+    }
+
+    fn last_timecode(&self) -> u64 {
+        match self {
+            Self::Webm(writer) => writer.last_timecode(),
+            Self::Ogg(writer) => writer.last_timecode(),
+        }
+    }
+
+    fn finish(self) -> io::Result<()> {
+        match self {
+            Self::Webm(writer) => writer.finish().map(|_| ()),
+            Self::Ogg(writer) => writer.finish().map(|_| ()),
+        }
+    }
+}
+
+struct TrackFrame {
+    /// Absolute timecode in the output file in milliseconds.
+    timecode: u64,
+    /// Absolute time in milliseconds.
+    time: u64,
+    data: Vec<u8>,
+    is_keyframe: bool,
+}
+
+/// Reads frames of a single track of a segment and converts RTP timestamps to timecodes.
+struct TrackReader {
+    reader: MjrReader<Box<dyn Read>>,
+    track: Track,
+    started_at: u64,
+    offset: u64,
+    depacketizer: vp8::Depacketizer,
+    last_timestamp: Option<u32>,
+    extended_timestamp: i64,
+}
+
+impl TrackReader {
+    /// `base` is the timecode of the segment start. Returns `None` if the segment
+    /// has no such track.
+    fn open(
+        dir: &Path,
+        key: Option<&DataKey>,
+        segment: &Segment,
+        track: Track,
+        base: u64,
+    ) -> Result<Option<Self>> {
+        let summary = match track {
+            Track::Audio => segment.audio(),
+            Track::Video => segment.video(),
+        };
+
+        let started_at = match summary.map(MjrSummary::started_at) {
+            Some(started_at) => started_at,
+            None => return Ok(None),
+        };
+
+        let path = mjr_path(dir, segment, track);
+
+        let reader = MjrReader::open(&path, key)
+            .with_context(|| format!("Failed to open {}", path.display()))?;
+
+        Ok(Some(Self {
+            reader,
+            track,
+            started_at,
+            offset: base + started_at - segment.started_at(),
+            depacketizer: vp8::Depacketizer::new(),
+            last_timestamp: None,
+            extended_timestamp: 0,
+        }))
+    }
+
+    fn next(reader: &mut Option<Self>) -> Result<Option<TrackFrame>> {
+        match reader {
+            Some(reader) => reader.next_frame(),
+            None => Ok(None),
+        }
+    }
+
+    fn next_frame(&mut self) -> Result<Option<TrackFrame>> {
+        loop {
+            let mjr_frame = match self.reader.next_frame() {
+                Ok(Some(mjr_frame)) => Some(mjr_frame),
+                // A truncated trailing frame is left by a crash so the track just ends there.
+                Ok(None) | Err(MjrError::TruncatedFrame { .. }) => None,
+                Err(err) => return Err(err).context("Failed to read frame"),
+            };
+
+            let packet = mjr_frame.as_ref().map(|mjr_frame| mjr_frame.data());
+
+            let (timestamp, data, is_keyframe) = match (self.track, packet) {
+                (Track::Audio, None) => return Ok(None),
+                (Track::Audio, Some(packet)) => {
+                    match (rtp::timestamp(packet), rtp::payload(packet)) {
+                        (Some(timestamp), Some(payload)) if !payload.is_empty() => {
+                            (timestamp, payload.to_vec(), true)
+                        }
+                        _ => continue,
+                    }
+                }
+                (Track::Video, packet) => {
+                    let frame = match packet {
+                        Some(packet) => {
+                            // Timestamps of dropped frames count too to keep
+                            // the offset from the segment start.
+                            if let Some(timestamp) = rtp::timestamp(packet) {
+                                self.timecode(timestamp);
+                            }
+
+                            self.depacketizer.push(packet)
+                        }
+                        None => match self.depacketizer.flush() {
+                            Some(frame) => Some(frame),
+                            None => return Ok(None),
+                        },
+                    };
+
+                    match frame {
+                        Some(frame) => {
+                            let is_keyframe = frame.is_keyframe();
+                            (frame.timestamp(), frame.into_data(), is_keyframe)
+                        }
+                        None => continue,
+                    }
+                }
+            };
+
+            let relative_timecode = self.timecode(timestamp);
+            let timecode = self.offset + relative_timecode;
+
+            return Ok(Some(TrackFrame {
+                timecode,
+                time: self.started_at + relative_timecode,
+                data,
+                is_keyframe,
+            }));
+        }
+    }
+
+    // Milliseconds since the first frame of the track handling timestamp wraparound.
+    fn timecode(&mut self, timestamp: u32) -> u64 {
+        if let Some(last_timestamp) = self.last_timestamp {
+            self.extended_timestamp += timestamp.wrapping_sub(last_timestamp) as i32 as i64;
+        }
+
+        self.last_timestamp = Some(timestamp);
+
+        let clock_rate = match self.track {
+            Track::Audio => OPUS_CLOCK_RATE,
+            Track::Video => VP8_CLOCK_RATE,
+        };
+
+        (self.extended_timestamp.max(0) * 1000 / clock_rate) as u64
+    }
+}
+
+///////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn align_clip_start() {
+        let keyframes = [1000, 3000, 5000];
+        assert_eq!(align_to_keyframe(&keyframes, (3500, 4000)), (3000, 4000));
+        assert_eq!(align_to_keyframe(&keyframes, (5000, 6000)), (5000, 6000));
+        assert_eq!(align_to_keyframe(&keyframes, (500, 2000)), (500, 2000));
+        assert_eq!(align_to_keyframe(&[], (500, 2000)), (500, 2000));
+    }
+}
@@ -1,20 +1,31 @@
-use std::{
-    collections::hash_map::Entry,
-    path::{Path, PathBuf},
-};
+use std::fs::{self, File};
+use std::hash::{Hash, Hasher};
+use std::io::{self, BufWriter};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Arc;
 use std::{error::Error as StdError, time::Duration};
 use std::{fmt, time::Instant};
-use std::{fs, io};
 
 use anyhow::{bail, Context, Error, Result};
 use chrono::{DateTime, Utc};
 use crossbeam_channel::{Receiver, Sender};
-use fnv::FnvHashMap;
+use fnv::{FnvHashMap, FnvHasher};
+use serde_json::Value as JsonValue;
 
-use crate::switchboard::StreamId;
+use crate::catalog::{Catalog, RecordingState};
+use crate::disk_space::{self, DiskSpace};
+use crate::janitor;
+use crate::manifest::{self, FileEntry, Manifest, SegmentEntry};
+use crate::packet_pool::{Packet, PacketPool};
+use crate::switchboard::{AgentId, SessionId, StreamId};
+use crate::timeline::{self, TimelineEvent};
+use crate::vp8;
 use crate::{
+    crypto::{self, DataKey, EncryptedWriter},
     janus_recorder::{Codec, JanusRecorder},
     metrics::Metrics,
+    mjr::MjrWriter,
 };
 
 #[derive(Clone, Deserialize, Debug)]
@@ -22,10 +33,142 @@ pub struct Config {
     pub directory: String,
     pub enabled: bool,
     pub delete_records: bool,
+    /// Write a data track of data channel messages and API events.
+    #[serde(default)]
+    pub record_data: bool,
+    #[serde(default)]
+    pub rotation: RotationConfig,
+    #[serde(default)]
+    pub retention: janitor::Config,
+    #[serde(default)]
+    pub disk_space: disk_space::Config,
+    #[serde(default)]
+    pub queue: QueueConfig,
+    pub encryption: Option<crypto::Config>,
+}
+
+/// Streams are distributed between `threads` recorder threads each having a queue
+/// of `capacity` messages.
+#[derive(Clone, Deserialize, Debug)]
+#[serde(default)]
+pub struct QueueConfig {
+    pub threads: usize,
+    pub capacity: usize,
+    pub overflow: OverflowPolicy,
+}
+
+impl Default for QueueConfig {
+    fn default() -> Self {
+        Self {
+            threads: 1,
+            capacity: 10000,
+            overflow: OverflowPolicy::DropVideoFirst,
+        }
+    }
+}
+
+/// What to do with packets when the recorder can't keep up.
+/// Control messages are never dropped.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OverflowPolicy {
+    /// Video packets are dropped when the queue is filled by `VIDEO_DROP_RATIO`
+    /// so the remaining room is left for audio. Audio is dropped only when the queue is full.
+    DropVideoFirst,
+    /// Any packet is dropped when the queue is full.
+    DropNewest,
+}
+
+impl OverflowPolicy {
+    fn is_video_dropped(self, queue_len: usize, capacity: usize) -> bool {
+        match self {
+            Self::DropVideoFirst => queue_len as f64 >= capacity as f64 * VIDEO_DROP_RATIO,
+            Self::DropNewest => false,
+        }
+    }
+}
+
+const VIDEO_DROP_RATIO: f64 = 0.75;
+
+/// Recording files get rotated when any of the limits is reached.
+/// Rotation happens only on a video keyframe so each segment is decodable by itself.
+#[derive(Clone, Deserialize, Debug, Default)]
+pub struct RotationConfig {
+    #[serde(default, with = "humantime_serde")]
+    pub interval: Option<Duration>,
+    /// Approximate size in bytes of audio and video files of a segment in total.
+    pub max_size: Option<usize>,
+}
+
+/// Frame of the data track written as JSON.
+#[derive(Debug, Serialize)]
+#[serde(tag = "source", rename_all = "snake_case")]
+pub enum DataFrame<'a> {
+    /// Text message from a data channel of the publisher.
+    DataChannel { label: &'a str, data: &'a str },
+    /// Event pushed with `recording.event`.
+    Api { data: &'a JsonValue },
+}
+
+/// Media kinds of a stream to record.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RecordMode {
+    All,
+    Audio,
+    Video,
+    None,
+}
+
+impl RecordMode {
+    pub fn is_enabled(self) -> bool {
+        self != Self::None
+    }
+
+    fn is_audio_enabled(self) -> bool {
+        matches!(self, Self::All | Self::Audio)
+    }
+
+    fn is_video_enabled(self) -> bool {
+        matches!(self, Self::All | Self::Video)
+    }
+}
+
+impl From<bool> for RecordMode {
+    fn from(flag: bool) -> Self {
+        match flag {
+            true => Self::All,
+            false => Self::None,
+        }
+    }
+}
+
+impl RotationConfig {
+    fn is_due(&self, recorders: &Recorders<'_>) -> bool {
+        let is_interval_exceeded = matches!(
+            self.interval,
+            Some(interval) if recorders.opened_at.elapsed() >= interval
+        );
+
+        let is_size_exceeded = matches!(
+            self.max_size,
+            Some(max_size) if recorders.size >= max_size
+        );
+
+        is_interval_exceeded || is_size_exceeded
+    }
 }
 
 impl Config {
     pub fn check(&mut self) -> Result<()> {
+        if self.queue.threads == 0 || self.queue.capacity == 0 {
+            bail!("Recordings: queue threads and capacity must be positive");
+        }
+
+        if let Some(ref encryption) = self.encryption {
+            encryption.check()?;
+        }
+
         if !self.enabled {
             return Ok(());
         }
@@ -41,104 +184,351 @@ impl Config {
     }
 }
 
+// Requested rotation doesn't wait for a keyframe longer than that.
+const FORCED_ROTATION_DELAY: Duration = Duration::from_secs(3);
+
+// A keyframe is requested again if the publisher hasn't sent it in that time.
+const KEYFRAME_REQUEST_INTERVAL: Duration = Duration::from_secs(2);
+
+// Failures to record a frame are logged for a stream not more often than that.
+const PACKET_ERROR_LOG_INTERVAL: Duration = Duration::from_secs(10);
+
 #[derive(Debug)]
 enum RecorderMsg {
     Stop {
         stream_id: StreamId,
     },
+    StopAll,
     Packet {
-        buf: Vec<i8>,
+        buf: Packet,
         is_video: bool,
         stream_id: StreamId,
     },
+    Data {
+        data: Vec<u8>,
+        stream_id: StreamId,
+    },
     Start {
         stream_id: StreamId,
         dir: String,
         start_time: DateTime<Utc>,
+        mode: RecordMode,
+        agent_id: AgentId,
+        handle_id: SessionId,
+    },
+    Pause {
+        stream_id: StreamId,
+        time: DateTime<Utc>,
+    },
+    Resume {
+        stream_id: StreamId,
+        time: DateTime<Utc>,
+    },
+    UpdateWriterConfig {
+        stream_id: StreamId,
+        time: DateTime<Utc>,
+        send_audio: bool,
+        send_video: bool,
     },
     WaitStop {
         waiter: async_oneshot::Sender<()>,
         stream_id: StreamId,
     },
+    Rotate {
+        waiter: async_oneshot::Sender<Option<i64>>,
+        stream_id: StreamId,
+    },
+    Mark {
+        waiter: async_oneshot::Sender<i64>,
+        stream_id: StreamId,
+        label: String,
+        payload: Option<JsonValue>,
+    },
 }
 
 #[derive(Debug)]
 pub struct RecorderHandlesCreator {
-    sender: Sender<RecorderMsg>,
+    /// Message queue and packet buffers of each recorder thread.
+    shards: Vec<(Sender<RecorderMsg>, PacketPool)>,
     config: Config,
+    disk_space: DiskSpace,
 }
 
 impl RecorderHandlesCreator {
-    fn new(sender: Sender<RecorderMsg>, config: Config) -> Self {
-        Self { sender, config }
+    fn new(shards: Vec<(Sender<RecorderMsg>, PacketPool)>, config: Config) -> Self {
+        Self {
+            shards,
+            config,
+            disk_space: DiskSpace::new(),
+        }
     }
 
+    /// All the messages of a stream go to the same recorder thread.
     pub fn new_handle(&self, stream_id: StreamId) -> RecorderHandle {
-        RecorderHandle::new(&self.config, stream_id, self.sender.clone())
+        let (sender, packet_pool) = &self.shards[shard(stream_id, self.shards.len())];
+
+        RecorderHandle::new(
+            &self.config,
+            stream_id,
+            (sender.clone(), packet_pool.clone()),
+            self.disk_space.clone(),
+        )
+    }
+
+    /// Free space level of the recordings volume. Stays normal unless the watcher is started.
+    pub fn disk_space(&self) -> &DiskSpace {
+        &self.disk_space
     }
+
+    /// Stops all the ongoing recordings, e.g. when the disk is full.
+    pub fn stop_all(&self) -> Result<()> {
+        for (sender, _) in &self.shards {
+            sender
+                .send(RecorderMsg::StopAll)
+                .context("Failed to stop all recordings")?;
+        }
+
+        Ok(())
+    }
+}
+
+fn shard(stream_id: StreamId, shards: usize) -> usize {
+    let mut hasher = FnvHasher::default();
+    stream_id.hash(&mut hasher);
+    (hasher.finish() % shards as u64) as usize
+}
+
+/// Load of a recorder thread. Each thread reports the total of all threads to metrics.
+#[derive(Debug, Default)]
+struct ShardLoad {
+    recorders: AtomicUsize,
+    queue: AtomicUsize,
+    waiters: AtomicUsize,
+}
+
+/// Suppresses repeated errors of a stream so a failing disk doesn't flood the log.
+struct ErrorLog {
+    logged_at: Instant,
+    suppressed: u64,
 }
 
 pub struct Recorder {
     messages: Receiver<RecorderMsg>,
     metrics_update_interval: Duration,
+    rotation: RotationConfig,
+    encryption: Option<crypto::Config>,
+    record_data: bool,
+    catalog: Arc<Catalog>,
+    shard: usize,
+    loads: Arc<Vec<ShardLoad>>,
 }
 
 impl Recorder {
-    fn new(messages: Receiver<RecorderMsg>, metrics_update_interval: Duration) -> Self {
+    fn new(
+        messages: Receiver<RecorderMsg>,
+        config: &Config,
+        metrics_update_interval: Duration,
+        catalog: Arc<Catalog>,
+        (shard, loads): (usize, Arc<Vec<ShardLoad>>),
+    ) -> Self {
         Self {
             messages,
             metrics_update_interval,
+            rotation: config.rotation.clone(),
+            encryption: config.encryption.clone(),
+            record_data: config.record_data,
+            catalog,
+            shard,
+            loads,
         }
     }
 
+    fn observe_load(&self, recorders: usize, waiters: usize) {
+        let load = &self.loads[self.shard];
+        load.recorders.store(recorders, Ordering::Relaxed);
+        load.queue.store(self.messages.len(), Ordering::Relaxed);
+        load.waiters.store(waiters, Ordering::Relaxed);
+
+        let total = |field: fn(&ShardLoad) -> &AtomicUsize| {
+            self.loads
+                .iter()
+                .map(|load| field(load).load(Ordering::Relaxed))
+                .sum()
+        };
+
+        Metrics::observe_recorder(
+            total(|load| &load.recorders),
+            total(|load| &load.queue),
+            total(|load| &load.waiters),
+        );
+    }
+
     pub fn start(self) {
         let mut recorders = FnvHashMap::default();
         let mut now = Instant::now();
         let mut waiters: FnvHashMap<_, Vec<async_oneshot::Sender<()>>> = FnvHashMap::default();
+        let mut packet_errors: FnvHashMap<StreamId, ErrorLog> = FnvHashMap::default();
         loop {
             let msg = self.messages.recv().expect("All senders dropped");
             if now.elapsed() > self.metrics_update_interval {
-                Metrics::observe_recorder(recorders.len(), self.messages.len(), waiters.len());
+                self.observe_load(recorders.len(), waiters.len());
                 now = Instant::now();
             }
 
             match msg {
                 RecorderMsg::Stop { stream_id } => {
+                    let is_recording = recorders.contains_key(&stream_id);
+
                     if let Err(err) = Self::handle_stop(&mut recorders, stream_id).context("Stop") {
                         err!("Recording stopping error: {:?}", err; {"rtc_id": stream_id});
                     } else {
                         info!("Recording stopped"; {"rtc_id": stream_id});
                     }
+
+                    if is_recording {
+                        self.catalog.set_state(stream_id, RecordingState::Stopped);
+                    }
                     if let Some(waiters) = waiters.remove(&stream_id) {
                         for mut waiter in waiters {
                             let _ = waiter.send(());
                         }
                     }
+
+                    packet_errors.remove(&stream_id);
+                }
+                RecorderMsg::StopAll => {
+                    for (stream_id, mut recording) in recorders.drain() {
+                        if let Err(err) = recording.close(Utc::now()).context("StopAll") {
+                            err!("Recording stopping error: {:?}", err; {"rtc_id": stream_id});
+                        } else {
+                            warn!("Recording stopped forcibly"; {"rtc_id": stream_id});
+                        }
+
+                        self.catalog.set_state(stream_id, RecordingState::Stopped);
+                    }
+
+                    for mut waiter in waiters.drain().flat_map(|(_, waiters)| waiters) {
+                        let _ = waiter.send(());
+                    }
+
+                    packet_errors.clear();
                 }
                 RecorderMsg::Packet {
                     buf,
                     is_video,
                     stream_id,
                 } => {
-                    if let Err(err) =
-                        Self::handle_packet(&mut recorders, stream_id, buf.as_slice(), is_video)
-                            .context("Packet")
+                    if let Err(err) = self
+                        .handle_packet(&mut recorders, stream_id, &buf, is_video)
+                        .context("Packet")
                     {
-                        err!("Failed to record frame: {:?}", err; {"rtc_id": stream_id});
+                        match packet_errors.get_mut(&stream_id) {
+                            Some(log) if log.logged_at.elapsed() < PACKET_ERROR_LOG_INTERVAL => {
+                                log.suppressed += 1;
+                            }
+                            log => {
+                                let suppressed = log.map_or(0, |log| log.suppressed);
+
+                                err!(
+                                    "Failed to record frame ({} similar errors suppressed): {:?}",
+                                    suppressed, err;
+                                    {"rtc_id": stream_id}
+                                );
+
+                                packet_errors.insert(
+                                    stream_id,
+                                    ErrorLog {
+                                        logged_at: Instant::now(),
+                                        suppressed: 0,
+                                    },
+                                );
+                            }
+                        }
+                    }
+                }
+                RecorderMsg::Data { data, stream_id } => {
+                    if let Err(err) = Self::handle_data(&mut recorders, stream_id, &data) {
+                        err!("Failed to record data: {:?}", err; {"rtc_id": stream_id});
                     }
                 }
                 RecorderMsg::Start {
                     dir,
                     stream_id,
                     start_time,
+                    mode,
+                    agent_id,
+                    handle_id,
                 } => {
-                    if let Err(err) =
-                        Self::handle_start(&mut recorders, stream_id, &dir, start_time)
-                            .context("Start")
+                    if let Err(err) = self
+                        .handle_start(
+                            &mut recorders,
+                            stream_id,
+                            &dir,
+                            start_time,
+                            mode,
+                            (agent_id, handle_id),
+                        )
+                        .context("Start")
                     {
                         err!("Failed to create recorders: {:?}", err; {"rtc_id": stream_id})
                     } else {
-                        info!("Recording to {}", dir; {"rtc_id": stream_id});
+                        info!("Recording {:?} to {}", mode, dir; {"rtc_id": stream_id});
+                        self.catalog.set_state(stream_id, RecordingState::Recording);
+                    }
+                }
+                RecorderMsg::Pause { stream_id, time } => {
+                    if let Err(err) =
+                        Self::handle_pause(&mut recorders, stream_id, time).context("Pause")
+                    {
+                        err!("Failed to pause recording: {:?}", err; {"rtc_id": stream_id});
+                    } else {
+                        info!("Recording paused"; {"rtc_id": stream_id});
+                    }
+                }
+                RecorderMsg::Resume { stream_id, time } => {
+                    if let Err(err) =
+                        Self::handle_resume(&mut recorders, stream_id, time).context("Resume")
+                    {
+                        err!("Failed to resume recording: {:?}", err; {"rtc_id": stream_id});
+                    } else {
+                        info!("Recording resumed"; {"rtc_id": stream_id});
+                    }
+                }
+                RecorderMsg::UpdateWriterConfig {
+                    stream_id,
+                    time,
+                    send_audio,
+                    send_video,
+                } => {
+                    if let Err(err) = Self::handle_update_writer_config(
+                        &mut recorders,
+                        stream_id,
+                        time,
+                        (send_audio, send_video),
+                    )
+                    .context("UpdateWriterConfig")
+                    {
+                        err!("Failed to write writer config: {:?}", err; {"rtc_id": stream_id});
+                    }
+                }
+                RecorderMsg::Rotate { waiter, stream_id } => {
+                    if let Err(err) =
+                        Self::handle_rotate(&mut recorders, stream_id, waiter).context("Rotate")
+                    {
+                        err!("Failed to rotate recording: {:?}", err; {"rtc_id": stream_id});
+                    }
+                }
+                RecorderMsg::Mark {
+                    waiter,
+                    stream_id,
+                    label,
+                    payload,
+                } => {
+                    if let Err(err) =
+                        Self::handle_mark(&mut recorders, stream_id, waiter, (label, payload))
+                            .context("Mark")
+                    {
+                        err!("Failed to mark recording: {:?}", err; {"rtc_id": stream_id});
                     }
                 }
                 RecorderMsg::WaitStop {
@@ -159,57 +549,278 @@ impl Recorder {
     }
 
     fn handle_stop(
-        recorders: &mut FnvHashMap<StreamId, Recorders<'_>>,
+        recordings: &mut FnvHashMap<StreamId, Recording<'_>>,
         stream_id: StreamId,
     ) -> Result<()> {
-        if let Some(mut recorders) = recorders.remove(&stream_id) {
-            recorders.audio.close()?;
-            recorders.video.close()?;
+        if let Some(mut recording) = recordings.remove(&stream_id) {
+            recording.close(Utc::now())?;
         }
         Ok(())
     }
 
     fn handle_packet(
-        recorders: &mut FnvHashMap<StreamId, Recorders<'_>>,
+        &self,
+        recordings: &mut FnvHashMap<StreamId, Recording<'_>>,
         stream_id: StreamId,
         packet: &[i8],
         is_video: bool,
     ) -> Result<()> {
-        let recorders = recorders
+        let recording = recordings
+            .get_mut(&stream_id)
+            .ok_or_else(|| anyhow!("Recorders missing"))?;
+
+        let recorders = match recording.segment {
+            Some(ref mut recorders) => recorders,
+            // Packets are dropped while the recording is paused.
+            None => return Ok(()),
+        };
+
+        let is_requested = recording.rotation_requested_at.is_some();
+
+        let is_forced = matches!(
+            recording.rotation_requested_at,
+            Some(requested_at) if requested_at.elapsed() >= FORCED_ROTATION_DELAY
+        );
+
+        let is_keyframe = is_video && {
+            let packet =
+                unsafe { std::slice::from_raw_parts(packet.as_ptr() as *const u8, packet.len()) };
+
+            vp8::is_keyframe(packet)
+        };
+
+        let is_rotation_due = self.rotation.is_due(recorders) || is_requested;
+
+        let is_rotation = is_forced
+            || is_rotation_due && Self::is_rotation_point(recorders, is_video, is_keyframe);
+
+        // The packet goes to the new segment so nothing gets lost in between.
+        let rotated_to = match is_rotation {
+            true => Some(recording.rotate()?),
+            false => None,
+        };
+
+        let handle_id = recording.publisher.1;
+
+        if let Some(ref mut recorders) = recording.segment {
+            if let Some(wait) = recorders.save_frame(packet, is_video, is_keyframe)? {
+                info!(
+                    "Video started on a keyframe after {} ms", wait.as_millis();
+                    {"rtc_id": stream_id}
+                );
+            }
+
+            // Speed up the keyframe to start video or rotate on.
+            if (recorders.is_waiting_keyframe() || is_rotation_due && !is_rotation)
+                && recorders.is_keyframe_request_due()
+            {
+                verb!("Requesting a keyframe"; {"rtc_id": stream_id});
+                crate::request_keyframe(handle_id);
+                recorders.keyframe_requested_at = Some(Instant::now());
+            }
+        }
+
+        if let Some(start_time) = rotated_to {
+            info!("Recording rotated to {}", start_time; {"rtc_id": stream_id});
+            recording.notify_rotation(Some(start_time));
+        }
+
+        Ok(())
+    }
+
+    fn handle_data(
+        recordings: &mut FnvHashMap<StreamId, Recording<'_>>,
+        stream_id: StreamId,
+        data: &[u8],
+    ) -> Result<()> {
+        let recording = recordings
             .get_mut(&stream_id)
             .ok_or_else(|| anyhow!("Recorders missing"))?;
+
+        // Data is dropped while the recording is paused like packets are.
+        match recording.segment {
+            Some(ref mut recorders) => recorders.save_data(data),
+            None => Ok(()),
+        }
+    }
+
+    fn is_rotation_point(recorders: &Recorders<'_>, is_video: bool, is_keyframe: bool) -> bool {
         if is_video {
-            recorders.video.save_frame(packet)
+            is_keyframe
         } else {
-            recorders.audio.save_frame(packet)
+            // Audio-only streams have no keyframes to wait for.
+            !recorders.has_video
         }
     }
 
+    /// Replies with the start time of the new segment once the current one gets closed
+    /// or with `None` if there's no open segment.
+    fn handle_rotate(
+        recordings: &mut FnvHashMap<StreamId, Recording<'_>>,
+        stream_id: StreamId,
+        mut waiter: async_oneshot::Sender<Option<i64>>,
+    ) -> Result<()> {
+        let recording = match recordings.get_mut(&stream_id) {
+            Some(recording) => recording,
+            None => {
+                let _ = waiter.send(None);
+                return Ok(());
+            }
+        };
+
+        let recorders = match recording.segment {
+            Some(ref recorders) => recorders,
+            None => {
+                let _ = waiter.send(None);
+                return Ok(());
+            }
+        };
+
+        recording.rotation_waiters.push(waiter);
+
+        // There's no keyframe to wait for.
+        if !recorders.has_video || recorders.size == 0 {
+            let start_time = recording.rotate()?;
+            info!("Recording rotated to {} on request", start_time; {"rtc_id": stream_id});
+            recording.notify_rotation(Some(start_time));
+        } else if recording.rotation_requested_at.is_none() {
+            recording.rotation_requested_at = Some(Instant::now());
+        }
+
+        Ok(())
+    }
+
+    /// Stamps the marker with the current time and replies with it.
+    fn handle_mark(
+        recordings: &mut FnvHashMap<StreamId, Recording<'_>>,
+        stream_id: StreamId,
+        mut waiter: async_oneshot::Sender<i64>,
+        (label, payload): (String, Option<JsonValue>),
+    ) -> Result<()> {
+        let recording = recordings
+            .get_mut(&stream_id)
+            .ok_or_else(|| anyhow!("Recorders missing"))?;
+
+        let time = Utc::now().timestamp_millis();
+
+        let event = TimelineEvent::Mark {
+            time,
+            label,
+            payload,
+        };
+
+        timeline::append(Path::new(&recording.dir), &event)?;
+        let _ = waiter.send(time);
+        Ok(())
+    }
+
     fn handle_start(
-        recorders: &mut FnvHashMap<StreamId, Recorders<'_>>,
+        &self,
+        recordings: &mut FnvHashMap<StreamId, Recording<'_>>,
         stream_id: StreamId,
         dir: &str,
         start_time: DateTime<Utc>,
+        mode: RecordMode,
+        publisher: (AgentId, SessionId),
     ) -> Result<()> {
+        // The previous recording of the stream must save its manifest before the new one reads it.
+        if let Some(mut recording) = recordings.remove(&stream_id) {
+            recording.close(start_time)?;
+        }
+
         Self::create_records_dir(dir)?;
-        let video_filename = format!("{}.video", start_time.timestamp_millis());
-        let video = JanusRecorder::create(dir, &video_filename, Codec::VP8)?;
-
-        let audio_filename = format!("{}.audio", start_time.timestamp_millis());
-        let audio = JanusRecorder::create(dir, &audio_filename, Codec::Opus)?;
-
-        match recorders.entry(stream_id) {
-            Entry::Occupied(mut e) => {
-                let mut v = e.insert(Recorders { audio, video });
-                v.audio.close()?;
-                v.video.close()?;
-                Ok(())
-            }
-            Entry::Vacant(e) => {
-                e.insert(Recorders { audio, video });
-                Ok(())
+        let mut manifest = manifest::read(Path::new(dir))?;
+
+        let data_key = match self.encryption {
+            Some(ref config) => {
+                let data_key = crypto::data_key(Path::new(dir), config)?;
+                manifest.key_id = Some(data_key.key_id().to_owned());
+                Some(data_key)
             }
+            None => None,
+        };
+
+        let mut recording = Recording {
+            dir: dir.to_owned(),
+            mode,
+            record_data: self.record_data,
+            publisher,
+            manifest,
+            data_key,
+            segment: None,
+            writer_config: None,
+            rotation_requested_at: None,
+            rotation_waiters: vec![],
+        };
+
+        recording.open_segment(start_time)?;
+        let time = start_time.timestamp_millis();
+        timeline::append(Path::new(dir), &TimelineEvent::Start { time })?;
+        recordings.insert(stream_id, recording);
+        Ok(())
+    }
+
+    fn handle_pause(
+        recordings: &mut FnvHashMap<StreamId, Recording<'_>>,
+        stream_id: StreamId,
+        time: DateTime<Utc>,
+    ) -> Result<()> {
+        let recording = recordings
+            .get_mut(&stream_id)
+            .ok_or_else(|| anyhow!("Recorders missing"))?;
+
+        if recording.segment.is_some() {
+            recording.close_segment(time)?;
+            recording.notify_rotation(None);
+            let time = time.timestamp_millis();
+            timeline::append(Path::new(&recording.dir), &TimelineEvent::Pause { time })?;
+        }
+
+        Ok(())
+    }
+
+    fn handle_resume(
+        recordings: &mut FnvHashMap<StreamId, Recording<'_>>,
+        stream_id: StreamId,
+        time: DateTime<Utc>,
+    ) -> Result<()> {
+        let recording = recordings
+            .get_mut(&stream_id)
+            .ok_or_else(|| anyhow!("Recorders missing"))?;
+
+        if recording.segment.is_none() {
+            // Resumed recording goes to a new segment.
+            recording.open_segment(time)?;
+            let time = time.timestamp_millis();
+            timeline::append(Path::new(&recording.dir), &TimelineEvent::Resume { time })?;
+        }
+
+        Ok(())
+    }
+
+    fn handle_update_writer_config(
+        recordings: &mut FnvHashMap<StreamId, Recording<'_>>,
+        stream_id: StreamId,
+        time: DateTime<Utc>,
+        (send_audio, send_video): (bool, bool),
+    ) -> Result<()> {
+        let recording = recordings
+            .get_mut(&stream_id)
+            .ok_or_else(|| anyhow!("Recorders missing"))?;
+
+        if recording.writer_config == Some((send_audio, send_video)) {
+            return Ok(());
         }
+
+        recording.writer_config = Some((send_audio, send_video));
+
+        let event = TimelineEvent::WriterConfig {
+            time: time.timestamp_millis(),
+            send_audio,
+            send_video,
+        };
+
+        timeline::append(Path::new(&recording.dir), &event)
     }
 
     fn create_records_dir(dir: &str) -> Result<(), std::io::Error> {
@@ -224,27 +835,381 @@ impl Recorder {
     }
 }
 
+/// Recording of a stream which may consist of multiple segments.
+struct Recording<'a> {
+    dir: String,
+    mode: RecordMode,
+    record_data: bool,
+    publisher: (AgentId, SessionId),
+    manifest: Manifest,
+    /// Set if recordings are encrypted.
+    data_key: Option<DataKey>,
+    /// Missing while the recording is paused.
+    segment: Option<Recorders<'a>>,
+    /// Last written `(send_audio, send_video)` to skip events without changes.
+    writer_config: Option<(bool, bool)>,
+    /// Set when rotation is requested until it happens.
+    rotation_requested_at: Option<Instant>,
+    rotation_waiters: Vec<async_oneshot::Sender<Option<i64>>>,
+}
+
+impl Recording<'_> {
+    fn open_segment(&mut self, start_time: DateTime<Utc>) -> Result<()> {
+        let recorders = Recorders::create(
+            &self.dir,
+            start_time,
+            (self.mode, self.record_data),
+            self.data_key.as_ref(),
+        )?;
+        let (agent_id, handle_id) = self.publisher.to_owned();
+
+        self.manifest.segments.push(SegmentEntry {
+            started_at: start_time.timestamp_millis(),
+            stopped_at: None,
+            agent_id,
+            handle_id,
+            files: recorders
+                .tracks()
+                .map(|(filename, codec, _)| FileEntry {
+                    filename,
+                    codec: codec.as_str().to_owned(),
+                    packets: 0,
+                    size: None,
+                    sha256: None,
+                })
+                .collect(),
+            recovered: false,
+            keyframe_wait: None,
+        });
+
+        self.segment = Some(recorders);
+        manifest::write(Path::new(&self.dir), &self.manifest)
+    }
+
+    /// Closes the current segment and writes its packet counts, sizes and checksums
+    /// to the manifest.
+    fn close_segment(&mut self, time: DateTime<Utc>) -> Result<()> {
+        let mut recorders = match self.segment.take() {
+            Some(recorders) => recorders,
+            None => return Ok(()),
+        };
+
+        recorders.close()?;
+        let dir = Path::new(&self.dir);
+
+        if let Some(entry) = self
+            .manifest
+            .segment_mut(recorders.start_time.timestamp_millis())
+        {
+            entry.stopped_at = Some(time.timestamp_millis());
+            entry.keyframe_wait = recorders.keyframe_wait.map(|wait| wait.as_millis() as u64);
+
+            for (file, (filename, _, packets)) in entry.files.iter_mut().zip(recorders.tracks()) {
+                let (size, sha256) = manifest::digest(&dir.join(&filename))
+                    .with_context(|| format!("Failed to digest {}", filename))?;
+
+                file.packets = packets;
+                file.size = Some(size);
+                file.sha256 = Some(sha256);
+            }
+        }
+
+        manifest::write(dir, &self.manifest)
+    }
+
+    /// Closes the current segment and opens a new one. Returns its start time in milliseconds.
+    fn rotate(&mut self) -> Result<i64> {
+        let now = Utc::now();
+
+        let start_time = match self.segment {
+            Some(ref recorders) => std::cmp::max(
+                now,
+                recorders.start_time + chrono::Duration::milliseconds(1),
+            ),
+            None => now,
+        };
+
+        self.close_segment(now)?;
+        self.open_segment(start_time)?;
+        Ok(start_time.timestamp_millis())
+    }
+
+    /// Replies to rotation requests with the new segment start time.
+    fn notify_rotation(&mut self, start_time: Option<i64>) {
+        self.rotation_requested_at = None;
+
+        for mut waiter in self.rotation_waiters.drain(..) {
+            let _ = waiter.send(start_time);
+        }
+    }
+
+    fn close(&mut self, time: DateTime<Utc>) -> Result<()> {
+        self.close_segment(time)?;
+
+        let time = time.timestamp_millis();
+        timeline::append(Path::new(&self.dir), &TimelineEvent::Stop { time })
+    }
+}
+
+/// Audio, video and data files of a single recording segment.
+/// Files are named by the segment's start timestamp in milliseconds.
 struct Recorders<'a> {
-    audio: JanusRecorder<'a>,
-    video: JanusRecorder<'a>,
+    audio: Option<Track<'a>>,
+    video: Option<Track<'a>>,
+    data: Option<Track<'a>>,
+    start_time: DateTime<Utc>,
+    opened_at: Instant,
+    size: usize,
+    has_video: bool,
+    audio_packets: u64,
+    video_packets: u64,
+    data_packets: u64,
+    /// Time from opening to the first video keyframe. Video is held back until then
+    /// since frames before it can't be decoded.
+    keyframe_wait: Option<Duration>,
+    keyframe_requested_at: Option<Instant>,
+}
+
+impl Recorders<'_> {
+    fn create(
+        dir: &str,
+        start_time: DateTime<Utc>,
+        (mode, record_data): (RecordMode, bool),
+        data_key: Option<&DataKey>,
+    ) -> Result<Self> {
+        let video = match mode.is_video_enabled() {
+            true => {
+                let video_filename = format!("{}.video", start_time.timestamp_millis());
+                Some(Track::create(dir, &video_filename, Codec::VP8, data_key)?)
+            }
+            false => None,
+        };
+
+        let audio = match mode.is_audio_enabled() {
+            true => {
+                let audio_filename = format!("{}.audio", start_time.timestamp_millis());
+                Some(Track::create(dir, &audio_filename, Codec::Opus, data_key)?)
+            }
+            false => None,
+        };
+
+        let data = match record_data {
+            true => {
+                let data_filename = format!("{}.data", start_time.timestamp_millis());
+                Some(Track::create(dir, &data_filename, Codec::Text, data_key)?)
+            }
+            false => None,
+        };
+
+        Ok(Self {
+            audio,
+            video,
+            data,
+            start_time,
+            opened_at: Instant::now(),
+            size: 0,
+            has_video: false,
+            audio_packets: 0,
+            video_packets: 0,
+            data_packets: 0,
+            keyframe_wait: None,
+            keyframe_requested_at: None,
+        })
+    }
+
+    fn is_waiting_keyframe(&self) -> bool {
+        self.video.is_some() && self.keyframe_wait.is_none()
+    }
+
+    fn is_keyframe_request_due(&self) -> bool {
+        !matches!(
+            self.keyframe_requested_at,
+            Some(requested_at) if requested_at.elapsed() < KEYFRAME_REQUEST_INTERVAL
+        )
+    }
+
+    /// Returns filename, codec and number of packets of each recorded track.
+    fn tracks(&self) -> impl Iterator<Item = (String, Codec, u64)> {
+        let prefix = self.start_time.timestamp_millis();
+
+        let audio = self.audio.as_ref().map(|_| {
+            let filename = format!("{}.audio.mjr", prefix);
+            (filename, Codec::Opus, self.audio_packets)
+        });
+
+        let video = self.video.as_ref().map(|_| {
+            let filename = format!("{}.video.mjr", prefix);
+            (filename, Codec::VP8, self.video_packets)
+        });
+
+        let data = self.data.as_ref().map(|_| {
+            let filename = format!("{}.data.mjr", prefix);
+            (filename, Codec::Text, self.data_packets)
+        });
+
+        audio.into_iter().chain(video).chain(data)
+    }
+
+    /// Returns the keyframe wait if the packet is the first video keyframe.
+    fn save_frame(
+        &mut self,
+        packet: &[i8],
+        is_video: bool,
+        is_keyframe: bool,
+    ) -> Result<Option<Duration>> {
+        let mut keyframe_wait = None;
+
+        if is_video && self.is_waiting_keyframe() {
+            if !is_keyframe {
+                return Ok(None);
+            }
+
+            keyframe_wait = Some(self.opened_at.elapsed());
+            self.keyframe_wait = keyframe_wait;
+        }
+
+        let recorder = match is_video {
+            true => self.video.as_mut(),
+            false => self.audio.as_mut(),
+        };
+
+        if let Some(recorder) = recorder {
+            recorder.save_frame(packet)?;
+            self.has_video |= is_video;
+            self.size += packet.len();
+
+            match is_video {
+                true => self.video_packets += 1,
+                false => self.audio_packets += 1,
+            }
+        }
+
+        Ok(keyframe_wait)
+    }
+
+    fn save_data(&mut self, data: &[u8]) -> Result<()> {
+        if let Some(ref mut recorder) = self.data {
+            let frame =
+                unsafe { std::slice::from_raw_parts(data.as_ptr() as *const i8, data.len()) };
+            recorder.save_frame(frame)?;
+            self.size += data.len();
+            self.data_packets += 1;
+        }
+
+        Ok(())
+    }
+
+    fn close(&mut self) -> Result<()> {
+        if let Some(ref mut audio) = self.audio {
+            audio.close()?;
+        }
+
+        if let Some(ref mut video) = self.video {
+            video.close()?;
+        }
+
+        if let Some(ref mut data) = self.data {
+            data.close()?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Writer of a single .mjr file. Encrypted files are written by our own writer
+/// since Janus recorder writes to the file directly.
+enum Track<'a> {
+    Janus(JanusRecorder<'a>),
+    Encrypted(Box<MjrWriter<EncryptedWriter<BufWriter<File>>>>),
+}
+
+impl Track<'_> {
+    fn create(dir: &str, filename: &str, codec: Codec, data_key: Option<&DataKey>) -> Result<Self> {
+        let data_key = match data_key {
+            Some(data_key) => data_key,
+            None => return Ok(Self::Janus(JanusRecorder::create(dir, filename, codec)?)),
+        };
+
+        let path = Path::new(dir).join(format!("{}.mjr", filename));
+        let writer = EncryptedWriter::create(&path, data_key)?;
+
+        MjrWriter::new(writer, codec.as_str())
+            .map(|writer| Self::Encrypted(Box::new(writer)))
+            .context("Failed to create encrypted recorder")
+    }
+
+    fn save_frame(&mut self, packet: &[i8]) -> Result<()> {
+        match self {
+            Self::Janus(recorder) => recorder.save_frame(packet),
+            Self::Encrypted(writer) => {
+                let packet = unsafe {
+                    std::slice::from_raw_parts(packet.as_ptr() as *const u8, packet.len())
+                };
+
+                writer.save_frame(packet).context("Failed to save frame")
+            }
+        }
+    }
+
+    fn close(&mut self) -> Result<()> {
+        match self {
+            Self::Janus(recorder) => recorder.close(),
+            Self::Encrypted(writer) => writer
+                .get_mut()
+                .finish()
+                .context("Failed to close recorder"),
+        }
+    }
 }
 
+/// Creates recorder threads to be started and the creator of handles sending messages to them.
 pub fn recorder(
     config: Config,
     metrics: crate::conf::Metrics,
-) -> (Recorder, RecorderHandlesCreator) {
-    let (tx, rx) = crossbeam_channel::unbounded();
-    (
-        Recorder::new(rx, metrics.recorders_metrics_load_interval),
-        RecorderHandlesCreator::new(tx, config),
-    )
+    catalog: Arc<Catalog>,
+) -> (Vec<Recorder>, RecorderHandlesCreator) {
+    let threads = config.queue.threads;
+    let loads = Arc::new(
+        (0..threads)
+            .map(|_| ShardLoad::default())
+            .collect::<Vec<_>>(),
+    );
+    let mut recorders = Vec::with_capacity(threads);
+    let mut shards = Vec::with_capacity(threads);
+
+    for shard in 0..threads {
+        // Control messages are sent under the switchboard lock so they must never block
+        // on a stalled recorder. They share the queue with packets to keep the order though
+        // so the queue is unbounded and the capacity is kept by dropping packets instead.
+        let (tx, rx) = crossbeam_channel::unbounded();
+
+        recorders.push(Recorder::new(
+            rx,
+            &config,
+            metrics.recorders_metrics_load_interval,
+            catalog.clone(),
+            (shard, loads.clone()),
+        ));
+
+        // Every queued packet may hold a buffer.
+        shards.push((tx, PacketPool::new(config.queue.capacity)));
+    }
+
+    (recorders, RecorderHandlesCreator::new(shards, config))
 }
 
 #[derive(Debug)]
 pub struct RecorderHandle {
     sender: Sender<RecorderMsg>,
+    packet_pool: PacketPool,
     stream_id: StreamId,
     save_root_dir: String,
+    disk_space: DiskSpace,
+    queue: QueueConfig,
+    /// Set after dropping a video packet until the next keyframe
+    /// since video can't be decoded without the dropped packet anyway.
+    is_video_broken: AtomicBool,
+    is_data_recorded: bool,
 
     is_deletion_enabled: bool,
 }
@@ -260,18 +1225,63 @@ pub struct RecorderHandle {
 /// Recorder runs in separate thread.
 /// You're able to write buffers using `record_packet` method.
 impl RecorderHandle {
-    fn new(config: &Config, stream_id: StreamId, messages: Sender<RecorderMsg>) -> Self {
+    fn new(
+        config: &Config,
+        stream_id: StreamId,
+        (messages, packet_pool): (Sender<RecorderMsg>, PacketPool),
+        disk_space: DiskSpace,
+    ) -> Self {
         Self {
             stream_id,
             save_root_dir: config.directory.clone(),
+            disk_space,
+            queue: config.queue.clone(),
+            is_video_broken: AtomicBool::new(false),
+            is_data_recorded: config.record_data,
             is_deletion_enabled: config.delete_records,
             sender: messages,
+            packet_pool,
         }
     }
 
     pub fn record_packet(&self, buf: &[i8], is_video: bool) -> Result<()> {
+        // Recordings are stopped when the disk is full so there's no point to queue packets.
+        if self.disk_space.is_critical() {
+            return Ok(());
+        }
+
+        if is_video {
+            let packet =
+                unsafe { std::slice::from_raw_parts(buf.as_ptr() as *const u8, buf.len()) };
+
+            let is_dropped = self
+                .queue
+                .overflow
+                .is_video_dropped(self.sender.len(), self.queue.capacity);
+
+            if is_dropped {
+                self.is_video_broken.store(true, Ordering::Relaxed);
+            } else if vp8::is_keyframe(packet) {
+                self.is_video_broken.store(false, Ordering::Relaxed);
+            }
+
+            if self.is_video_broken.load(Ordering::Relaxed) {
+                Metrics::observe_dropped_packet(is_video);
+                return Ok(());
+            }
+        }
+
+        if self.is_queue_full() {
+            if is_video {
+                self.is_video_broken.store(true, Ordering::Relaxed);
+            }
+
+            Metrics::observe_dropped_packet(is_video);
+            return Ok(());
+        }
+
         let msg = RecorderMsg::Packet {
-            buf: buf.to_vec(),
+            buf: self.packet_pool.packet(buf),
             is_video,
             stream_id: self.stream_id,
         };
@@ -279,7 +1289,37 @@ impl RecorderHandle {
         self.sender.send(msg).context("Failed to send packet")
     }
 
-    pub fn start_recording(&self) -> Result<()> {
+    /// Writes the frame to the data track stamped with the recording time.
+    /// Does nothing if data recording is disabled.
+    pub fn record_data(&self, frame: &DataFrame<'_>) -> Result<()> {
+        if !self.is_data_recorded || self.disk_space.is_critical() {
+            return Ok(());
+        }
+
+        if self.is_queue_full() {
+            bail!("Recorder queue is full");
+        }
+
+        let msg = RecorderMsg::Data {
+            data: serde_json::to_vec(frame).context("Failed to serialize data frame")?,
+            stream_id: self.stream_id,
+        };
+
+        self.sender.send(msg).context("Failed to send data")
+    }
+
+    // The queue itself is unbounded so packets and data are dropped when it's full.
+    fn is_queue_full(&self) -> bool {
+        self.sender.len() >= self.queue.capacity
+    }
+
+    /// Starts recording of the stream published by `agent_id` through `handle_id`.
+    pub fn start_recording(
+        &self,
+        mode: RecordMode,
+        agent_id: &AgentId,
+        handle_id: SessionId,
+    ) -> Result<()> {
         info!("Start recording"; {"rtc_id": self.stream_id});
 
         let dir = self.get_records_dir().to_string_lossy().into_owned();
@@ -289,10 +1329,31 @@ impl RecorderHandle {
                 stream_id: self.stream_id,
                 dir,
                 start_time: Utc::now(),
+                mode,
+                agent_id: agent_id.to_owned(),
+                handle_id,
             })
             .context("Failed to start recording")
     }
 
+    pub fn pause_recording(&self) -> Result<()> {
+        self.sender
+            .send(RecorderMsg::Pause {
+                stream_id: self.stream_id,
+                time: Utc::now(),
+            })
+            .context("Failed to pause recording")
+    }
+
+    pub fn resume_recording(&self) -> Result<()> {
+        self.sender
+            .send(RecorderMsg::Resume {
+                stream_id: self.stream_id,
+                time: Utc::now(),
+            })
+            .context("Failed to resume recording")
+    }
+
     pub fn stop_recording(&self) -> Result<()> {
         self.sender
             .send(RecorderMsg::Stop {
@@ -301,6 +1362,19 @@ impl RecorderHandle {
             .context("Failed to stop recording")
     }
 
+    /// Writes the writer config to the timeline when it differs from the previous one.
+    /// Should be called right after starting the recording to write the initial state.
+    pub fn update_writer_config(&self, send_audio: bool, send_video: bool) -> Result<()> {
+        self.sender
+            .send(RecorderMsg::UpdateWriterConfig {
+                stream_id: self.stream_id,
+                time: Utc::now(),
+                send_audio,
+                send_video,
+            })
+            .context("Failed to update writer config")
+    }
+
     pub async fn wait_stop(&self) -> Result<()> {
         let (tx, rx) = async_oneshot::oneshot();
         self.sender
@@ -313,6 +1387,42 @@ impl RecorderHandle {
         Ok(())
     }
 
+    /// Closes the current segment and starts a new one without stopping the recording.
+    /// Returns the start time of the new segment in milliseconds so all the segments
+    /// started earlier are complete. `None` means that there's no open segment.
+    pub async fn rotate(&self) -> Result<Option<i64>> {
+        let (tx, rx) = async_oneshot::oneshot();
+
+        self.sender
+            .send(RecorderMsg::Rotate {
+                waiter: tx,
+                stream_id: self.stream_id,
+            })
+            .context("Failed to rotate recording")?;
+
+        // The sender gets dropped when the recording stops.
+        Ok(rx.await.unwrap_or(None))
+    }
+
+    /// Adds a marker to the timeline. Returns the time it's stamped with
+    /// by the recorder as a unix timestamp in milliseconds.
+    pub async fn mark(&self, label: String, payload: Option<JsonValue>) -> Result<i64> {
+        let (tx, rx) = async_oneshot::oneshot();
+
+        self.sender
+            .send(RecorderMsg::Mark {
+                waiter: tx,
+                stream_id: self.stream_id,
+                label,
+                payload,
+            })
+            .context("Failed to mark recording")?;
+
+        // The sender gets dropped if there's no recording.
+        rx.await
+            .map_err(|_| anyhow!("Failed to mark recording: the stream is not being recorded"))
+    }
+
     pub fn get_records_dir(&self) -> PathBuf {
         let mut path = PathBuf::new();
         path.push(&self.save_root_dir);
@@ -334,13 +1444,49 @@ impl RecorderHandle {
         }
     }
 
+    /// Deletes the record after the upload unless deletion is disabled in the config.
     pub fn delete_record(&self) -> Result<()> {
         if self.is_deletion_enabled {
-            fs::remove_dir_all(&self.get_records_dir()).context("Failed to delete record")
+            self.remove_record()
         } else {
             Ok(())
         }
     }
+
+    /// Deletes the record regardless of the config destroying its data key first.
+    pub fn remove_record(&self) -> Result<()> {
+        let dir = self.get_records_dir();
+        crypto::destroy_data_key(&dir)?;
+
+        match fs::remove_dir_all(&dir) {
+            Err(err) if err.kind() != io::ErrorKind::NotFound => {
+                Err(err).context("Failed to delete record")
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Deletes audio and video files of a segment leaving the rest of the record.
+    pub fn delete_segment(&self, prefix: &str) -> Result<()> {
+        if !self.is_deletion_enabled {
+            return Ok(());
+        }
+
+        for kind in &["audio", "video", "data"] {
+            let path = self
+                .get_records_dir()
+                .join(format!("{}.{}.mjr", prefix, kind));
+
+            match fs::remove_file(&path) {
+                Err(err) if err.kind() != io::ErrorKind::NotFound => {
+                    return Err(err).context("Failed to delete segment");
+                }
+                _ => (),
+            }
+        }
+
+        Ok(())
+    }
 }
 
 #[derive(Debug)]
@@ -378,3 +1524,33 @@ impl From<io::Error> for RecorderError {
         RecorderError::IoError(err)
     }
 }
+
+///////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use uuid::Uuid;
+
+    use super::*;
+
+    #[test]
+    fn assign_shards() {
+        let stream_id = Uuid::new_v4();
+        assert_eq!(shard(stream_id, 4), shard(stream_id, 4));
+        assert_eq!(shard(stream_id, 1), 0);
+
+        let shards = (0..100)
+            .map(|_| shard(Uuid::new_v4(), 4))
+            .collect::<std::collections::HashSet<_>>();
+
+        assert_eq!(shards.len(), 4);
+    }
+
+    #[test]
+    fn drop_video_first() {
+        let policy = OverflowPolicy::DropVideoFirst;
+        assert!(!policy.is_video_dropped(74, 100));
+        assert!(policy.is_video_dropped(75, 100));
+        assert!(!OverflowPolicy::DropNewest.is_video_dropped(99, 100));
+    }
+}
@@ -0,0 +1,229 @@
+use std::fs;
+use std::path::Path;
+use std::time::UNIX_EPOCH;
+
+use anyhow::{Context, Result};
+
+use crate::crypto::{self, DataKey};
+use crate::manifest;
+use crate::mjr;
+use crate::postprocess;
+use crate::timeline::{self, TimelineEvent};
+use crate::utils;
+
+///////////////////////////////////////////////////////////////////////////////
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Stats {
+    pub segments: usize,
+    pub frames: u64,
+    pub truncated_bytes: u64,
+}
+
+impl Stats {
+    fn add(&mut self, other: Stats) {
+        self.segments += other.segments;
+        self.frames += other.frames;
+        self.truncated_bytes += other.truncated_bytes;
+    }
+}
+
+/// Repairs segments left unfinished by a crash in all the records of the recordings directory.
+/// Must be called before any recording starts since every unfinished segment is considered
+/// abandoned. Failure to recover one record doesn't prevent recovering others.
+pub fn recover_all(recordings_dir: &Path, encryption: Option<&crypto::Config>) -> Result<Stats> {
+    let mut stats = Stats::default();
+
+    for (stream_id, path) in utils::record_dirs(recordings_dir)? {
+        if let Err(err) = remove_plain_leftovers(&path) {
+            err!("Failed to remove upload leftovers: {:#}", err; {"rtc_id": stream_id});
+        }
+
+        match recover(&path, encryption) {
+            Ok(record_stats) if record_stats.segments > 0 => {
+                warn!(
+                    "Recovered {} unfinished segments: {} frames, {} bytes truncated",
+                    record_stats.segments, record_stats.frames, record_stats.truncated_bytes;
+                    {"rtc_id": stream_id}
+                );
+
+                stats.add(record_stats);
+            }
+            Ok(_) => (),
+            Err(err) => err!("Failed to recover record: {:#}", err; {"rtc_id": stream_id}),
+        }
+    }
+
+    Ok(stats)
+}
+
+/// Truncates partial frames of the record's unfinished segments, fills their sizes and checksums
+/// and marks them as recovered in the manifest. Also closes the timeline with a stop event.
+/// Encrypted files get sealed so they can be read as truncated on purpose.
+pub fn recover(dir: &Path, encryption: Option<&crypto::Config>) -> Result<Stats> {
+    let mut manifest = manifest::read(dir)?;
+    let mut stats = Stats::default();
+    let mut last_stopped_at = None;
+
+    if manifest
+        .segments
+        .iter()
+        .all(|segment| segment.stopped_at.is_some())
+    {
+        return Ok(stats);
+    }
+
+    let data_key = match encryption {
+        Some(config) => crypto::read_data_key(dir, config)?,
+        None => None,
+    };
+
+    for segment in manifest.segments.iter_mut() {
+        if segment.stopped_at.is_some() {
+            continue;
+        }
+
+        let mut stopped_at = segment.started_at;
+
+        for file in segment.files.iter_mut() {
+            let path = dir.join(&file.filename);
+
+            let repair = repair(&path, data_key.as_ref())
+                .with_context(|| format!("Failed to repair {}", file.filename))?;
+
+            let modified_at = fs::metadata(&path)?
+                .modified()?
+                .duration_since(UNIX_EPOCH)
+                .map(|time| time.as_millis() as i64)
+                .unwrap_or_default();
+
+            let (size, sha256) = manifest::digest(&path)
+                .with_context(|| format!("Failed to digest {}", file.filename))?;
+
+            file.packets = repair.frames;
+            file.size = Some(size);
+            file.sha256 = Some(sha256);
+            stopped_at = stopped_at.max(modified_at);
+            stats.frames += repair.frames;
+            stats.truncated_bytes += repair.truncated;
+        }
+
+        segment.stopped_at = Some(stopped_at);
+        segment.recovered = true;
+        stats.segments += 1;
+        last_stopped_at = last_stopped_at.max(Some(stopped_at));
+    }
+
+    if let Some(time) = last_stopped_at {
+        manifest::write(dir, &manifest)?;
+
+        let is_stopped = matches!(
+            timeline::read(dir)?.last(),
+            None | Some(TimelineEvent::Stop { .. })
+        );
+
+        if !is_stopped {
+            timeline::append(dir, &TimelineEvent::Stop { time })?;
+        }
+    }
+
+    Ok(stats)
+}
+
+// Muxed outputs and decrypted files of an upload interrupted by the crash
+// which may contain plain recordings of an encrypted record.
+fn remove_plain_leftovers(dir: &Path) -> Result<()> {
+    utils::remove_dir(&dir.join(postprocess::OUTPUTS_DIR)).context("Failed to remove outputs")?;
+    crypto::remove_decrypted(dir)
+}
+
+fn repair(path: &Path, data_key: Option<&DataKey>) -> Result<mjr::Repair> {
+    if !crypto::is_encrypted(path)? {
+        return Ok(mjr::repair(path)?);
+    }
+
+    let data_key = data_key.context("File is encrypted but there's no data key")?;
+    let (chunks, truncated) = crypto::repair(path, data_key)?;
+
+    // The magic and the info header take a chunk each.
+    Ok(mjr::Repair {
+        frames: chunks.saturating_sub(2),
+        truncated,
+    })
+}
+
+///////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use crate::manifest::{FileEntry, Manifest, SegmentEntry};
+    use crate::switchboard::SessionId;
+    use crate::utils::TempDir;
+
+    use super::*;
+
+    #[test]
+    fn recover_unfinished_segment() -> Result<()> {
+        let dir = TempDir::new("recovery_test")?;
+
+        let header = br#"{"c":"opus","u":1000000}"#;
+        let mut mjr = b"MJR00002".to_vec();
+        mjr.extend_from_slice(&(header.len() as u16).to_be_bytes());
+        mjr.extend_from_slice(header);
+        mjr.extend_from_slice(b"MEET\0\0\0\0\0\x01\xff");
+        let size = mjr.len() as u64;
+        // Partial second frame.
+        mjr.extend_from_slice(b"MEET\0\0");
+        fs::write(dir.join("1000.audio.mjr"), &mjr)?;
+
+        let manifest = Manifest {
+            segments: vec![SegmentEntry {
+                started_at: 1000,
+                stopped_at: None,
+                agent_id: String::from("web.john.example.org"),
+                handle_id: SessionId::new(123),
+                files: vec![FileEntry {
+                    filename: String::from("1000.audio.mjr"),
+                    codec: String::from("opus"),
+                    packets: 0,
+                    size: None,
+                    sha256: None,
+                }],
+                recovered: false,
+                keyframe_wait: None,
+            }],
+            key_id: None,
+        };
+
+        manifest::write(&dir, &manifest)?;
+        timeline::append(&dir, &TimelineEvent::Start { time: 1000 })?;
+
+        let stats = recover(&dir, None)?;
+
+        assert_eq!(
+            stats,
+            Stats {
+                segments: 1,
+                frames: 1,
+                truncated_bytes: 6,
+            }
+        );
+
+        let segment = &manifest::read(&dir)?.segments[0];
+        assert!(segment.recovered);
+        assert!(segment.stopped_at.is_some());
+        assert_eq!(segment.files[0].size, Some(size));
+        assert_eq!(segment.files[0].packets, 1);
+
+        let is_stopped = matches!(
+            timeline::read(&dir)?.last(),
+            Some(TimelineEvent::Stop { .. })
+        );
+
+        assert!(is_stopped);
+
+        // Nothing to do the second time.
+        assert_eq!(recover(&dir, None)?, Stats::default());
+        Ok(())
+    }
+}
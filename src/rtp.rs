@@ -0,0 +1,62 @@
+const RTP_HEADER_SIZE: usize = 12;
+
+/// Returns RTP payload skipping CSRCs, header extension and padding.
+pub fn payload(packet: &[u8]) -> Option<&[u8]> {
+    let first_byte = *packet.first()?;
+    let csrc_count = (first_byte & 0x0f) as usize;
+    let mut offset = RTP_HEADER_SIZE + csrc_count * 4;
+
+    // Header extension.
+    if first_byte & 0x10 != 0 {
+        let length = packet.get(offset + 2..offset + 4)?;
+        let length = u16::from_be_bytes([length[0], length[1]]) as usize;
+        offset += 4 + length * 4;
+    }
+
+    let mut end = packet.len();
+
+    // Padding length is stored in the last byte.
+    if first_byte & 0x20 != 0 {
+        end = end.checked_sub(*packet.last()? as usize)?;
+    }
+
+    packet.get(offset..end)
+}
+
+pub fn marker(packet: &[u8]) -> bool {
+    matches!(packet.get(1), Some(byte) if byte & 0x80 != 0)
+}
+
+pub fn sequence_number(packet: &[u8]) -> Option<u16> {
+    let bytes = packet.get(2..4)?;
+    Some(u16::from_be_bytes([bytes[0], bytes[1]]))
+}
+
+pub fn timestamp(packet: &[u8]) -> Option<u32> {
+    let bytes = packet.get(4..8)?;
+    Some(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+}
+
+///////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_packet() {
+        let packet = [
+            0xb1, 0xe0, 0x01, 0x02, 0x00, 0x00, 0x10, 0x00, 0, 0, 0, 1, // Header with 1 CSRC.
+            0, 0, 0, 2, // CSRC.
+            0xbe, 0xde, 0x00, 0x01, 0x10, 0xff, 0x00, 0x00, // Header extension.
+            0xaa, 0xbb, // Payload.
+            0x00, 0x02, // Padding.
+        ];
+
+        assert_eq!(payload(&packet), Some(&[0xaa, 0xbb][..]));
+        assert!(marker(&packet));
+        assert_eq!(sequence_number(&packet), Some(0x0102));
+        assert_eq!(timestamp(&packet), Some(0x1000));
+        assert_eq!(payload(&packet[..14]), None);
+    }
+}
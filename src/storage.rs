@@ -0,0 +1,448 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::fs::File;
+use std::io;
+use std::path::{Component, Path, PathBuf};
+use std::time::Duration;
+
+use anyhow::{bail, format_err, Context, Result};
+use async_std::fs;
+use async_trait::async_trait;
+use md5::{Digest, Md5};
+use surf::http::Method;
+use surf::Url;
+
+use crate::conf::UploadBackendConfig;
+use crate::s3::S3Client;
+
+const KEY_TEMPLATE_PLACEHOLDERS: &[&str] = &["id", "bucket", "object"];
+const DUMP_KEY_TEMPLATE_PLACEHOLDERS: &[&str] = &["id", "bucket", "object", "filename"];
+
+const MAX_ATTEMPTS: u32 = 5;
+const RETRY_DELAY: Duration = Duration::from_millis(500);
+
+/// Object headers set on upload.
+#[derive(Debug, Default)]
+pub struct ObjectMetadata<'a> {
+    pub content_type: Option<&'a str>,
+    pub cache_control: Option<&'a str>,
+}
+
+/// Destination of recordings.
+#[async_trait]
+pub trait StorageBackend: Send + Sync {
+    /// Uploads the file as `key` object and returns URI of the stored object.
+    /// Backends which have no notion of buckets ignore `bucket` but it still may be used
+    /// in the key template.
+    async fn upload(
+        &self,
+        bucket: &str,
+        key: &str,
+        path: &Path,
+        metadata: &ObjectMetadata<'_>,
+    ) -> Result<String>;
+}
+
+///////////////////////////////////////////////////////////////////////////////
+
+/// `upload.backends.<name>` section.
+#[derive(Clone, Deserialize, Debug)]
+pub struct BackendConfig {
+    #[serde(flatten)]
+    pub storage: StorageConfig,
+    /// Key of the muxed recording.
+    #[serde(default = "BackendConfig::default_key_template")]
+    pub key_template: String,
+    /// Key of a .mjr dump.
+    #[serde(default = "BackendConfig::default_dump_key_template")]
+    pub dump_key_template: String,
+}
+
+#[derive(Clone, Deserialize, Debug)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum StorageConfig {
+    S3(UploadBackendConfig),
+    Local(LocalConfig),
+    Http(HttpConfig),
+}
+
+#[derive(Clone, Deserialize, Debug)]
+pub struct LocalConfig {
+    /// Objects are copied to `<directory>/<bucket>/<key>`.
+    pub directory: PathBuf,
+}
+
+#[derive(Clone, Deserialize)]
+pub struct HttpConfig {
+    /// Presigned prefix URL. Objects are PUT to `<url path>/<key>?<url query>`.
+    pub url: String,
+    #[serde(default)]
+    pub headers: HashMap<String, String>,
+}
+
+// Query of a presigned URL contains the signature.
+impl fmt::Debug for HttpConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let url = self.url.split('?').next().unwrap_or_default();
+
+        f.debug_struct("HttpConfig")
+            .field("url", &url)
+            .field("headers", &self.headers.keys())
+            .finish()
+    }
+}
+
+impl BackendConfig {
+    fn default_key_template() -> String {
+        String::from("{object}")
+    }
+
+    fn default_dump_key_template() -> String {
+        String::from("{id}_dump/{filename}")
+    }
+
+    /// S3 backend with the default key templates.
+    pub fn s3(config: UploadBackendConfig) -> Self {
+        Self {
+            storage: StorageConfig::S3(config),
+            key_template: Self::default_key_template(),
+            dump_key_template: Self::default_dump_key_template(),
+        }
+    }
+
+    pub fn check(&self) -> Result<()> {
+        check_template(&self.key_template, KEY_TEMPLATE_PLACEHOLDERS)?;
+        check_template(&self.dump_key_template, DUMP_KEY_TEMPLATE_PLACEHOLDERS)?;
+
+        match self.storage {
+            StorageConfig::S3(ref config) => Url::parse(&config.endpoint)
+                .map(|_| ())
+                .with_context(|| format!("Invalid S3 endpoint: {}", config.endpoint)),
+            StorageConfig::Local(ref config) => match config.directory.is_dir() {
+                true => Ok(()),
+                false => bail!("{} is not a directory", config.directory.display()),
+            },
+            StorageConfig::Http(ref config) => Url::parse(&config.url)
+                .map(|_| ())
+                .context("Invalid HTTP backend URL"),
+        }
+    }
+
+    pub fn build(&self) -> Result<Box<dyn StorageBackend>> {
+        let backend: Box<dyn StorageBackend> = match self.storage {
+            StorageConfig::S3(ref config) => Box::new(S3Client::new(config.to_owned())),
+            StorageConfig::Local(ref config) => Box::new(LocalBackend {
+                directory: config.directory.to_owned(),
+            }),
+            StorageConfig::Http(ref config) => Box::new(HttpBackend {
+                url: Url::parse(&config.url).context("Invalid HTTP backend URL")?,
+                headers: config.headers.to_owned(),
+                http: surf::Client::new(),
+            }),
+        };
+
+        Ok(backend)
+    }
+}
+
+/// Replaces `{name}` placeholders in the template with values.
+pub fn render_key(template: &str, values: &[(&str, &str)]) -> String {
+    values
+        .iter()
+        .fold(template.to_owned(), |key, (name, value)| {
+            key.replace(&format!("{{{}}}", name), value)
+        })
+}
+
+fn check_template(template: &str, placeholders: &[&str]) -> Result<()> {
+    let mut rest = template;
+
+    while let Some(start) = rest.find('{') {
+        let end = rest[start..]
+            .find('}')
+            .ok_or_else(|| format_err!("Unclosed placeholder in key template '{}'", template))?;
+
+        let name = &rest[start + 1..start + end];
+
+        if !placeholders.contains(&name) {
+            bail!(
+                "Unknown placeholder '{}' in key template '{}'",
+                name,
+                template
+            );
+        }
+
+        rest = &rest[start + end + 1..];
+    }
+
+    Ok(())
+}
+
+///////////////////////////////////////////////////////////////////////////////
+
+/// Copies objects to a local or mounted network directory.
+struct LocalBackend {
+    directory: PathBuf,
+}
+
+#[async_trait]
+impl StorageBackend for LocalBackend {
+    async fn upload(
+        &self,
+        bucket: &str,
+        key: &str,
+        path: &Path,
+        _metadata: &ObjectMetadata<'_>,
+    ) -> Result<String> {
+        let relative_path = Path::new(bucket).join(key);
+
+        // Objects must not escape the directory.
+        if !relative_path
+            .components()
+            .all(|component| matches!(component, Component::Normal(_)))
+        {
+            bail!("Invalid object path: {}", relative_path.display());
+        }
+
+        let destination = self.directory.join(relative_path);
+
+        if let Some(parent) = destination.parent() {
+            fs::create_dir_all(parent)
+                .await
+                .with_context(|| format!("Failed to create {}", parent.display()))?;
+        }
+
+        // Copy to a temporary file first so readers never see a partial object.
+        let tmp_destination = destination.with_extension("part");
+
+        let size = fs::copy(path, &tmp_destination)
+            .await
+            .with_context(|| format!("Failed to copy to {}", tmp_destination.display()))?;
+
+        let expected_size = fs::metadata(path)
+            .await
+            .with_context(|| format!("Failed to get size of {}", path.display()))?
+            .len();
+
+        if size != expected_size {
+            bail!("Copied {} bytes of {}", size, expected_size);
+        }
+
+        fs::rename(&tmp_destination, &destination)
+            .await
+            .with_context(|| format!("Failed to rename to {}", destination.display()))?;
+
+        Ok(format!("file://{}", destination.display()))
+    }
+}
+
+///////////////////////////////////////////////////////////////////////////////
+
+/// Uploads objects with HTTP PUT under a presigned prefix URL like Azure Blob SAS or similar.
+/// Integrity is checked by the server with `Content-MD5` header.
+struct HttpBackend {
+    url: Url,
+    headers: HashMap<String, String>,
+    http: surf::Client,
+}
+
+impl HttpBackend {
+    fn object_url(&self, key: &str) -> Result<Url> {
+        let mut url = self.url.clone();
+
+        url.path_segments_mut()
+            .map_err(|()| format_err!("HTTP backend URL can't be a base"))?
+            .pop_if_empty()
+            .extend(key.split('/'));
+
+        Ok(url)
+    }
+}
+
+#[async_trait]
+impl StorageBackend for HttpBackend {
+    async fn upload(
+        &self,
+        _bucket: &str,
+        key: &str,
+        path: &Path,
+        metadata: &ObjectMetadata<'_>,
+    ) -> Result<String> {
+        let url = self.object_url(key)?;
+
+        let digest = md5_digest(path)
+            .await
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+
+        let mut attempt = 1;
+
+        loop {
+            // The file is streamed rather than read into memory since it may be huge.
+            let body = surf::Body::from_file(path)
+                .await
+                .with_context(|| format!("Failed to open {}", path.display()))?;
+
+            let mut request = surf::Request::new(Method::Put, url.clone());
+            // Set before headers so the content type they specify overrides the guessed one.
+            request.set_body(body);
+
+            for (name, value) in &self.headers {
+                request.insert_header(name.as_str(), value.as_str());
+            }
+
+            if let Some(content_type) = metadata.content_type {
+                request.insert_header("content-type", content_type);
+            }
+
+            if let Some(cache_control) = metadata.cache_control {
+                request.insert_header("cache-control", cache_control);
+            }
+
+            request.insert_header("content-md5", digest.as_str());
+
+            let err = match self.http.send(request).await {
+                Ok(response) if response.status().is_success() => break,
+                Ok(response) if !is_retryable(response.status()) => {
+                    bail!("PUT {} failed with {}", url.path(), response.status())
+                }
+                Ok(response) => format_err!("PUT {} failed with {}", url.path(), response.status()),
+                Err(err) => format_err!("PUT {} failed: {}", url.path(), err),
+            };
+
+            if attempt >= MAX_ATTEMPTS {
+                return Err(err);
+            }
+
+            warn!("HTTP upload attempt {} failed, retrying: {}", attempt, err);
+            async_std::task::sleep(RETRY_DELAY * attempt).await;
+            attempt += 1;
+        }
+
+        // Drop the query with the signature.
+        let mut uri = url;
+        uri.set_query(None);
+        Ok(uri.to_string())
+    }
+}
+
+/// Returns base64 encoded MD5 of the file.
+async fn md5_digest(path: &Path) -> io::Result<String> {
+    let path = path.to_owned();
+
+    async_std::task::spawn_blocking(move || {
+        let mut hasher = Md5::new();
+        io::copy(&mut File::open(path)?, &mut hasher)?;
+        Ok(base64::encode(&hasher.finalize()))
+    })
+    .await
+}
+
+/// Whether a request failed with this status is worth retrying.
+pub fn is_retryable(status: surf::StatusCode) -> bool {
+    status.is_server_error() || status == surf::StatusCode::TooManyRequests
+}
+
+///////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use crate::utils::TempDir;
+
+    use super::*;
+
+    #[test]
+    fn check_templates() {
+        assert!(check_template("{bucket}/{id}/{object}", KEY_TEMPLATE_PLACEHOLDERS).is_ok());
+        assert!(check_template("plain", KEY_TEMPLATE_PLACEHOLDERS).is_ok());
+        assert!(check_template("{filename}", KEY_TEMPLATE_PLACEHOLDERS).is_err());
+        assert!(check_template("{id}_dump/{filename}", DUMP_KEY_TEMPLATE_PLACEHOLDERS).is_ok());
+        assert!(check_template("{id", DUMP_KEY_TEMPLATE_PLACEHOLDERS).is_err());
+    }
+
+    #[test]
+    fn render() {
+        let key = render_key(
+            "{id}_dump/{filename}",
+            &[("id", "123"), ("filename", "a.mjr")],
+        );
+        assert_eq!(key, "123_dump/a.mjr");
+    }
+
+    #[test]
+    fn parse_config() -> Result<()> {
+        let toml = r#"
+            [s3]
+            type = "s3"
+            endpoint = "https://storage.example.org"
+            region = "ru-central1"
+            key_template = "{bucket}/{object}"
+
+            [nfs]
+            type = "local"
+            directory = "/mnt/records"
+
+            [presigned]
+            type = "http"
+            url = "https://blob.example.org/records?sig=secret"
+            headers = { x-ms-blob-type = "BlockBlob" }
+        "#;
+
+        let mut parser = config::Config::default();
+        parser.merge(config::File::from_str(toml, config::FileFormat::Toml))?;
+        let backends = parser.try_into::<HashMap<String, BackendConfig>>()?;
+
+        assert!(
+            matches!(backends["s3"].storage, StorageConfig::S3(ref s3) if s3.region == "ru-central1")
+        );
+        assert_eq!(backends["s3"].key_template, "{bucket}/{object}");
+        assert!(matches!(backends["nfs"].storage, StorageConfig::Local(_)));
+        assert_eq!(backends["nfs"].dump_key_template, "{id}_dump/{filename}");
+        assert!(!format!("{:?}", backends["presigned"]).contains("secret"));
+        Ok(())
+    }
+
+    #[test]
+    fn upload_to_local_directory() -> Result<()> {
+        let dir = TempDir::new("storage_test")?;
+        let source = dir.join("source");
+        std::fs::write(&source, b"data")?;
+
+        let backend = LocalBackend {
+            directory: dir.join("storage"),
+        };
+
+        async_std::task::block_on(async {
+            let metadata = ObjectMetadata::default();
+            let uri = backend
+                .upload("bucket", "a/b.webm", &source, &metadata)
+                .await?;
+            assert_eq!(
+                uri,
+                format!("file://{}", dir.join("storage/bucket/a/b.webm").display())
+            );
+            assert_eq!(std::fs::read(dir.join("storage/bucket/a/b.webm"))?, b"data");
+
+            let escape = backend
+                .upload("bucket", "../b.webm", &source, &metadata)
+                .await;
+            assert!(escape.is_err());
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn build_http_url() -> Result<()> {
+        let backend = HttpBackend {
+            url: Url::parse("https://blob.example.org/records/?sig=secret")?,
+            headers: HashMap::new(),
+            http: surf::Client::new(),
+        };
+
+        let url = backend.object_url("bucket/full webm")?;
+        assert_eq!(
+            url.as_str(),
+            "https://blob.example.org/records/bucket/full%20webm?sig=secret"
+        );
+        Ok(())
+    }
+}
@@ -11,6 +11,8 @@ use once_cell::sync::Lazy;
 use uuid::Uuid;
 
 use crate::bidirectional_multimap::BidirectionalMultimap;
+use crate::forwarder::{ForwarderId, RtpForwarder};
+use crate::ingest::RtpIngest;
 use crate::janus_callbacks;
 use crate::janus_rtp::JanusRtpSwitchingContext;
 use crate::recorder::RecorderHandle;
@@ -219,6 +221,8 @@ pub struct Switchboard {
     publishers_subscribers: BidirectionalMultimap<SessionId, SessionId>,
     reader_configs: FnvHashMap<(StreamId, AgentId), ReaderConfig>,
     writer_configs: FnvHashMap<StreamId, WriterConfig>,
+    forwarders: FnvHashMap<StreamId, Vec<RtpForwarder>>,
+    ingests: FnvHashMap<SessionId, RtpIngest>,
 }
 
 impl Switchboard {
@@ -231,6 +235,8 @@ impl Switchboard {
             publishers_subscribers: BidirectionalMultimap::new(),
             reader_configs: FnvHashMap::default(),
             writer_configs: FnvHashMap::default(),
+            forwarders: FnvHashMap::default(),
+            ingests: FnvHashMap::default(),
         }
     }
 
@@ -268,6 +274,12 @@ impl Switchboard {
     }
 
     pub fn disconnect(&mut self, id: SessionId) -> Result<()> {
+        // There's no Janus handle behind an ingest to end so remove it right away.
+        if self.is_ingest(id) {
+            info!("Disconnecting RTP ingest"; {"handle_id": id});
+            return self.handle_disconnect(id);
+        }
+
         info!("Disconnecting session asynchronously"; {"handle_id": id});
 
         let session = self
@@ -302,6 +314,7 @@ impl Switchboard {
 
         self.sessions.remove(&id);
         self.states.remove(&id);
+        self.ingests.remove(&id);
         self.agents.remove_value(&id);
         self.publishers_subscribers.remove_value(&id);
         Ok(())
@@ -340,6 +353,12 @@ impl Switchboard {
             .map(|id| id.to_owned())
     }
 
+    pub fn publishers(&self) -> impl Iterator<Item = (StreamId, SessionId)> + '_ {
+        self.publishers
+            .iter()
+            .map(|(stream_id, publisher)| (*stream_id, *publisher))
+    }
+
     pub fn publisher_of(&self, stream_id: StreamId) -> Option<SessionId> {
         self.publishers.get(&stream_id).map(|p| p.to_owned())
     }
@@ -403,6 +422,49 @@ impl Switchboard {
         self.writer_configs.insert(stream_id, writer_config)
     }
 
+    pub fn forwarders(&self, stream_id: StreamId) -> &[RtpForwarder] {
+        self.forwarders
+            .get(&stream_id)
+            .map(Vec::as_slice)
+            .unwrap_or(&[])
+    }
+
+    pub fn add_forwarder(&mut self, stream_id: StreamId, forwarder: RtpForwarder) -> Result<()> {
+        if !self.publishers.contains_key(&stream_id) {
+            bail!("Stream {} does not exist", stream_id);
+        }
+
+        info!("Adding RTP forwarder {}", forwarder.id(); {"rtc_id": stream_id});
+
+        self.forwarders
+            .entry(stream_id)
+            .or_default()
+            .push(forwarder);
+
+        Ok(())
+    }
+
+    pub fn remove_forwarder(&mut self, stream_id: StreamId, id: ForwarderId) -> Result<()> {
+        let forwarders = self
+            .forwarders
+            .get_mut(&stream_id)
+            .ok_or_else(|| format_err!("No RTP forwarders for stream {}", stream_id))?;
+
+        let forwarders_count = forwarders.len();
+        forwarders.retain(|forwarder| forwarder.id() != id);
+
+        if forwarders.len() == forwarders_count {
+            bail!("RTP forwarder {} not found", id);
+        }
+
+        if forwarders.is_empty() {
+            self.forwarders.remove(&stream_id);
+        }
+
+        info!("RTP forwarder {} removed", id; {"rtc_id": stream_id});
+        Ok(())
+    }
+
     pub fn create_stream(
         &mut self,
         id: StreamId,
@@ -426,6 +488,28 @@ impl Switchboard {
         Ok(())
     }
 
+    pub fn create_ingest_stream(
+        &mut self,
+        id: StreamId,
+        ingest: RtpIngest,
+        agent_id: AgentId,
+    ) -> Result<()> {
+        let publisher = ingest.session_id();
+        let state = SessionState::new();
+
+        // Start the timeout countdown right away so the vacuum drops
+        // the ingest if the source never starts sending.
+        state.touch_last_rtp_packet_timestamp();
+
+        self.states.insert(publisher, state);
+        self.ingests.insert(publisher, ingest);
+        self.create_stream(id, publisher, agent_id)
+    }
+
+    pub fn is_ingest(&self, id: SessionId) -> bool {
+        self.ingests.contains_key(&id)
+    }
+
     pub fn join_stream(
         &mut self,
         id: StreamId,
@@ -456,6 +540,7 @@ impl Switchboard {
         if let Some(publisher) = maybe_publisher {
             self.stop_recording(publisher)?;
             self.publishers.remove(&id);
+            self.forwarders.remove(&id);
             self.publishers_subscribers.remove_key(&publisher);
             self.agents.remove_value(&publisher);
         }
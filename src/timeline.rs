@@ -0,0 +1,212 @@
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, ErrorKind, Write};
+use std::path::Path;
+
+use anyhow::{Context, Result};
+use serde_json::Value as JsonValue;
+
+///////////////////////////////////////////////////////////////////////////////
+
+const TIMELINE_FILENAME: &str = "timeline.jsonl";
+
+/// Recording events which aren't reflected in .mjr files themselves.
+/// Stored one JSON object per line in the records directory of the stream.
+/// `time` is a unix timestamp in milliseconds.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum TimelineEvent {
+    Start {
+        time: i64,
+    },
+    Stop {
+        time: i64,
+    },
+    Pause {
+        time: i64,
+    },
+    Resume {
+        time: i64,
+    },
+    WriterConfig {
+        time: i64,
+        send_audio: bool,
+        send_video: bool,
+    },
+    /// Bookmark added with `recording.mark`.
+    Mark {
+        time: i64,
+        label: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        payload: Option<JsonValue>,
+    },
+}
+
+pub fn append(dir: &Path, event: &TimelineEvent) -> Result<()> {
+    let mut line = serde_json::to_string(event).context("Failed to serialize timeline event")?;
+    line.push('\n');
+
+    OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(dir.join(TIMELINE_FILENAME))
+        .and_then(|mut file| file.write_all(line.as_bytes()))
+        .context("Failed to write timeline event")
+}
+
+/// Reads the timeline of the records directory.
+/// Missing timeline means no events were recorded.
+pub fn read(dir: &Path) -> Result<Vec<TimelineEvent>> {
+    let file = match File::open(dir.join(TIMELINE_FILENAME)) {
+        Ok(file) => file,
+        Err(err) if err.kind() == ErrorKind::NotFound => return Ok(vec![]),
+        Err(err) => return Err(err).context("Failed to open timeline"),
+    };
+
+    let mut events = vec![];
+
+    for line in BufReader::new(file).lines() {
+        let line = line.context("Failed to read timeline")?;
+
+        // The last line may be incomplete in case of a crash.
+        match serde_json::from_str(&line) {
+            Ok(event) => events.push(event),
+            Err(err) => warn!("Skipping malformed timeline event '{}': {}", line, err),
+        }
+    }
+
+    Ok(events)
+}
+
+/// Returns `(start, end)` timestamps of pauses.
+/// A pause lasts until resume or the end of the recording.
+pub fn pauses(events: &[TimelineEvent]) -> Vec<(i64, i64)> {
+    let mut pauses = vec![];
+    let mut pause_start = None;
+
+    for event in events {
+        match *event {
+            TimelineEvent::Pause { time } => {
+                pause_start.get_or_insert(time);
+            }
+            TimelineEvent::Resume { time }
+            | TimelineEvent::Stop { time }
+            | TimelineEvent::Start { time } => {
+                if let Some(start) = pause_start.take() {
+                    pauses.push((start, time));
+                }
+            }
+            TimelineEvent::WriterConfig { .. } | TimelineEvent::Mark { .. } => (),
+        }
+    }
+
+    pauses
+}
+
+/// Returns `(start, end)` timestamps of intervals when audio or video was muted
+/// by the writer config. Muting lasts until unmute or the end of the recording.
+pub fn muted(events: &[TimelineEvent], is_video: bool) -> Vec<(i64, i64)> {
+    let mut intervals = vec![];
+    let mut mute_start = None;
+
+    for event in events {
+        match *event {
+            TimelineEvent::WriterConfig {
+                time,
+                send_audio,
+                send_video,
+            } => {
+                let is_sending = if is_video { send_video } else { send_audio };
+
+                match (is_sending, mute_start) {
+                    (false, None) => mute_start = Some(time),
+                    (true, Some(start)) => {
+                        intervals.push((start, time));
+                        mute_start = None;
+                    }
+                    _ => (),
+                }
+            }
+            TimelineEvent::Stop { time } | TimelineEvent::Start { time } => {
+                if let Some(start) = mute_start.take() {
+                    intervals.push((start, time));
+                }
+            }
+            TimelineEvent::Pause { .. }
+            | TimelineEvent::Resume { .. }
+            | TimelineEvent::Mark { .. } => (),
+        }
+    }
+
+    intervals
+}
+
+/// Returns `(time, label, payload)` of markers in order of adding.
+pub fn marks(events: &[TimelineEvent]) -> impl Iterator<Item = (i64, &str, Option<&JsonValue>)> {
+    events.iter().filter_map(|event| match event {
+        TimelineEvent::Mark {
+            time,
+            label,
+            payload,
+        } => Some((*time, label.as_str(), payload.as_ref())),
+        _ => None,
+    })
+}
+
+///////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn collect_pauses() {
+        let events = [
+            TimelineEvent::Start { time: 1000 },
+            TimelineEvent::Pause { time: 2000 },
+            TimelineEvent::Pause { time: 2500 },
+            TimelineEvent::Resume { time: 3000 },
+            TimelineEvent::Resume { time: 3500 },
+            TimelineEvent::Pause { time: 4000 },
+            TimelineEvent::Stop { time: 5000 },
+        ];
+
+        assert_eq!(pauses(&events), vec![(2000, 3000), (4000, 5000)]);
+    }
+
+    #[test]
+    fn collect_muted_intervals() {
+        let writer_config = |time, send_audio, send_video| TimelineEvent::WriterConfig {
+            time,
+            send_audio,
+            send_video,
+        };
+
+        let events = [
+            TimelineEvent::Start { time: 1000 },
+            writer_config(1000, true, false),
+            writer_config(2000, false, false),
+            writer_config(3000, true, true),
+            writer_config(4000, false, true),
+            TimelineEvent::Stop { time: 5000 },
+        ];
+
+        assert_eq!(muted(&events, false), vec![(2000, 3000), (4000, 5000)]);
+        assert_eq!(muted(&events, true), vec![(1000, 3000)]);
+    }
+
+    #[test]
+    fn parse_events() -> Result<()> {
+        let event = serde_json::from_str::<TimelineEvent>(r#"{"event":"pause","time":123}"#)?;
+        assert_eq!(event, TimelineEvent::Pause { time: 123 });
+
+        let event = serde_json::from_str::<TimelineEvent>(
+            r#"{"event":"mark","time":456,"label":"Question 3","payload":{"slide":7}}"#,
+        )?;
+
+        let events = [TimelineEvent::Pause { time: 123 }, event];
+        let marks = marks(&events).collect::<Vec<_>>();
+        let payload = serde_json::json!({"slide": 7});
+        assert_eq!(marks, vec![(456, "Question 3", Some(&payload))]);
+        Ok(())
+    }
+}
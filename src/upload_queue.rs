@@ -0,0 +1,500 @@
+use std::collections::HashMap;
+use std::error::Error as StdError;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use anyhow::{format_err, Context, Error, Result};
+use async_std::channel::{self, Receiver, Sender};
+use chrono::Utc;
+use serde_json::Value as JsonValue;
+use uuid::Uuid;
+
+use crate::catalog::{Catalog, RecordingState};
+use crate::message_handler::run_upload;
+use crate::postprocess::Container;
+use crate::switchboard::StreamId;
+use crate::utils;
+
+// Hidden so it doesn't get confused with records directories of streams.
+const JOBS_DIRNAME: &str = ".upload_jobs";
+
+#[derive(Clone, Deserialize, Debug)]
+#[serde(default)]
+pub struct Config {
+    /// How many jobs run at the same time.
+    pub workers: usize,
+    /// Failed jobs are retried until the number of attempts is reached.
+    pub max_attempts: u32,
+    #[serde(with = "humantime_serde")]
+    pub retry_delay: Duration,
+    /// Finished jobs are kept for status polling that long.
+    #[serde(with = "humantime_serde")]
+    pub job_ttl: Duration,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            workers: 2,
+            max_attempts: 3,
+            retry_delay: Duration::from_secs(60),
+            job_ttl: Duration::from_secs(24 * 60 * 60),
+        }
+    }
+}
+
+/// Stream record to upload and its destination.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct UploadTask {
+    pub id: StreamId,
+    pub backends: Vec<String>,
+    pub bucket: String,
+    pub object: String,
+    /// Parts of the record to upload instead of the whole one.
+    #[serde(default)]
+    pub clips: Vec<Clip>,
+    /// Files to produce from the record. A single WebM of `object` if empty.
+    #[serde(default)]
+    pub outputs: Vec<OutputProfile>,
+    /// Upload complete segments only and keep the record for the next uploads.
+    #[serde(default)]
+    pub partial: bool,
+    /// Segments started since then are skipped. Unix timestamp in milliseconds.
+    #[serde(default)]
+    pub until: Option<i64>,
+}
+
+/// Time range of the record in milliseconds.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Clip {
+    /// Relative to the record start unless `absolute`.
+    pub start: u64,
+    pub end: u64,
+    /// Whether `start` and `end` are Unix timestamps in milliseconds.
+    #[serde(default)]
+    pub absolute: bool,
+    /// Object name of the clip. Defaults to the task's object with the clip number added.
+    pub object: Option<String>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct OutputProfile {
+    pub container: Container,
+    /// Name of the configured encoder to transcode with. Frames are copied if not specified.
+    pub encoder: Option<String>,
+    pub object: String,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum JobState {
+    Queued,
+    Running,
+    Done,
+    Failed,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct UploadJob {
+    pub id: Uuid,
+    pub task: UploadTask,
+    pub state: JobState,
+    pub attempts: u32,
+    /// Unix timestamp in milliseconds of the last state change.
+    pub updated_at: i64,
+    /// `stream.upload` result payload of a done job.
+    pub result: Option<JsonValue>,
+    /// The last error of a failed or retried job.
+    pub error: Option<String>,
+}
+
+impl UploadJob {
+    fn is_finished(&self) -> bool {
+        matches!(self.state, JobState::Done | JobState::Failed)
+    }
+
+    fn is_expired(&self, ttl: Duration) -> bool {
+        self.is_finished()
+            && Utc::now().timestamp_millis() - self.updated_at > ttl.as_millis() as i64
+    }
+
+    fn set_state(&mut self, state: JobState) {
+        self.state = state;
+        self.updated_at = Utc::now().timestamp_millis();
+    }
+}
+
+/// Upload jobs processed in background by a fixed number of workers.
+///
+/// Every job is stored as a JSON file so queued and interrupted jobs get resumed
+/// after a restart and finished ones stay available for status polling.
+/// Full uploads move records through the uploading state in the catalog.
+#[derive(Debug)]
+pub struct UploadQueue {
+    config: Config,
+    dir: PathBuf,
+    catalog: Arc<Catalog>,
+    jobs: Mutex<HashMap<Uuid, UploadJob>>,
+    sender: Sender<Uuid>,
+    receiver: Receiver<Uuid>,
+}
+
+impl UploadQueue {
+    pub fn new(config: Config, recordings_dir: &Path, catalog: Arc<Catalog>) -> Result<Self> {
+        let dir = recordings_dir.join(JOBS_DIRNAME);
+        fs::create_dir_all(&dir).context("Failed to create upload jobs dir")?;
+
+        let (sender, receiver) = channel::unbounded();
+
+        let queue = Self {
+            config,
+            dir,
+            catalog,
+            jobs: Mutex::new(HashMap::new()),
+            sender,
+            receiver,
+        };
+
+        queue.load()?;
+        Ok(queue)
+    }
+
+    /// Spawns workers. Must be called once the app is initialized.
+    pub fn start(&'static self) {
+        for _ in 0..self.config.workers.max(1) {
+            async_std::task::spawn(async move {
+                while let Ok(job_id) = self.receiver.recv().await {
+                    self.run(job_id).await;
+                }
+            });
+        }
+    }
+
+    /// Adds a job for the task unless there's an unfinished job for the same stream
+    /// in which case that job is returned.
+    pub fn enqueue(&self, task: UploadTask) -> Result<UploadJob, EnqueueError> {
+        let mut jobs = self.lock_jobs()?;
+        self.remove_expired(&mut jobs);
+
+        match Self::find_unfinished_job(&jobs, task.id) {
+            Some(job) if job.task == task => return Ok(job.to_owned()),
+            Some(job) => return Err(EnqueueError::Conflict(job.id)),
+            None => (),
+        }
+
+        let job = UploadJob {
+            id: Uuid::new_v4(),
+            task,
+            state: JobState::Queued,
+            attempts: 0,
+            updated_at: Utc::now().timestamp_millis(),
+            result: None,
+            error: None,
+        };
+
+        self.save(&job)?;
+        jobs.insert(job.id, job.clone());
+        self.schedule(job.id, None);
+        Ok(job)
+    }
+
+    pub fn get(&self, job_id: Uuid) -> Result<Option<UploadJob>> {
+        Ok(self.lock_jobs()?.get(&job_id).cloned())
+    }
+
+    /// Returns the queued or running job of the stream if there's one.
+    pub fn find_unfinished(&self, stream_id: StreamId) -> Result<Option<UploadJob>> {
+        let jobs = self.lock_jobs()?;
+        Ok(Self::find_unfinished_job(&jobs, stream_id).cloned())
+    }
+
+    fn find_unfinished_job(
+        jobs: &HashMap<Uuid, UploadJob>,
+        stream_id: StreamId,
+    ) -> Option<&UploadJob> {
+        jobs.values()
+            .find(|job| job.task.id == stream_id && !job.is_finished())
+    }
+
+    async fn run(&self, job_id: Uuid) {
+        let task = match self.update(job_id, |job| {
+            job.set_state(JobState::Running);
+            job.attempts += 1;
+        }) {
+            Some(job) => job.task,
+            None => return,
+        };
+
+        // Partial uploads leave the record to go on recording.
+        if !task.partial {
+            self.catalog.set_state(task.id, RecordingState::Uploading);
+        }
+
+        info!("Running upload job {}", job_id; {"rtc_id": task.id});
+        let result = run_upload(&task).await;
+        let max_attempts = self.config.max_attempts;
+
+        let job = self.update(job_id, |job| match result {
+            Ok(result) => {
+                job.set_state(JobState::Done);
+                job.result = Some(result);
+                job.error = None;
+            }
+            Err(err) => {
+                job.error = Some(format!("{:#}", err));
+
+                match job.attempts < max_attempts {
+                    true => job.set_state(JobState::Queued),
+                    false => job.set_state(JobState::Failed),
+                }
+            }
+        });
+
+        match job {
+            Some(job) if job.state == JobState::Done => {
+                info!("Upload job {} done", job_id; {"rtc_id": task.id});
+
+                if !task.partial {
+                    self.catalog.set_state(task.id, RecordingState::Uploaded);
+                }
+            }
+            Some(job) if job.state == JobState::Queued => {
+                warn!(
+                    "Upload job {} attempt {} failed, retrying: {}",
+                    job_id,
+                    job.attempts,
+                    job.error.unwrap_or_default();
+                    {"rtc_id": task.id}
+                );
+
+                self.schedule(job_id, Some(self.config.retry_delay));
+            }
+            Some(job) => {
+                let error = job.error.unwrap_or_default();
+                err!("Upload job {} failed: {}", job_id, error; {"rtc_id": task.id});
+
+                if !task.partial {
+                    self.catalog.set_failed(task.id, error);
+                }
+            }
+            None => (),
+        }
+    }
+
+    // Applies the change to the job and saves it. Returns the updated job.
+    fn update<F>(&self, job_id: Uuid, f: F) -> Option<UploadJob>
+    where
+        F: FnOnce(&mut UploadJob),
+    {
+        let mut jobs = match self.lock_jobs() {
+            Ok(jobs) => jobs,
+            Err(err) => {
+                err!("Failed to update upload job {}: {}", job_id, err);
+                return None;
+            }
+        };
+
+        let job = jobs.get_mut(&job_id)?;
+        f(job);
+
+        if let Err(err) = self.save(job) {
+            err!("{:#}", err);
+        }
+
+        Some(job.to_owned())
+    }
+
+    fn schedule(&self, job_id: Uuid, delay: Option<Duration>) {
+        let sender = self.sender.clone();
+
+        async_std::task::spawn(async move {
+            if let Some(delay) = delay {
+                async_std::task::sleep(delay).await;
+            }
+
+            if let Err(err) = sender.send(job_id).await {
+                err!("Failed to schedule upload job {}: {}", job_id, err);
+            }
+        });
+    }
+
+    fn load(&self) -> Result<()> {
+        let mut jobs = self.lock_jobs()?;
+
+        for entry in fs::read_dir(&self.dir).context("Failed to read upload jobs dir")? {
+            let path = entry.context("Failed to read upload jobs dir")?.path();
+
+            if path.extension().and_then(|ext| ext.to_str()) != Some("json") {
+                continue;
+            }
+
+            let job = fs::read(&path)
+                .map_err(anyhow::Error::from)
+                .and_then(|data| Ok(serde_json::from_slice::<UploadJob>(&data)?));
+
+            match job {
+                Ok(job) => {
+                    jobs.insert(job.id, job);
+                }
+                Err(err) => warn!("Skipping malformed upload job {}: {}", path.display(), err),
+            }
+        }
+
+        self.remove_expired(&mut jobs);
+
+        let mut unfinished = jobs
+            .values_mut()
+            .filter(|job| !job.is_finished())
+            .collect::<Vec<_>>();
+
+        unfinished.sort_by_key(|job| job.updated_at);
+
+        for job in unfinished {
+            // The job has been interrupted by the restart.
+            if job.state == JobState::Running {
+                job.set_state(JobState::Queued);
+                self.save(job)?;
+            }
+
+            info!("Resuming upload job {}", job.id; {"rtc_id": job.task.id});
+
+            self.sender
+                .try_send(job.id)
+                .map_err(|err| format_err!("Failed to enqueue upload job: {}", err))?;
+        }
+
+        Ok(())
+    }
+
+    fn remove_expired(&self, jobs: &mut HashMap<Uuid, UploadJob>) {
+        let ttl = self.config.job_ttl;
+
+        jobs.retain(|job_id, job| {
+            if !job.is_expired(ttl) {
+                return true;
+            }
+
+            if let Err(err) = fs::remove_file(self.job_path(*job_id)) {
+                warn!("Failed to remove upload job {}: {}", job_id, err);
+            }
+
+            false
+        });
+    }
+
+    fn save(&self, job: &UploadJob) -> Result<()> {
+        let data = serde_json::to_vec(job).context("Failed to serialize upload job")?;
+
+        utils::atomic_write(&self.job_path(job.id), &data)
+            .with_context(|| format!("Failed to save upload job {}", job.id))
+    }
+
+    fn job_path(&self, job_id: Uuid) -> PathBuf {
+        self.dir.join(format!("{}.json", job_id))
+    }
+
+    fn lock_jobs(&self) -> Result<std::sync::MutexGuard<'_, HashMap<Uuid, UploadJob>>> {
+        self.jobs
+            .lock()
+            .map_err(|_| format_err!("Upload jobs lock is poisoned"))
+    }
+}
+
+#[derive(Debug)]
+pub enum EnqueueError {
+    /// Another task of the stream is unfinished.
+    Conflict(Uuid),
+    InternalError(Error),
+}
+
+impl fmt::Display for EnqueueError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Conflict(job_id) => {
+                write!(f, "Upload job {} of the stream is unfinished", job_id)
+            }
+            Self::InternalError(source) => write!(f, "{}", source),
+        }
+    }
+}
+
+impl StdError for EnqueueError {
+    fn source(&self) -> Option<&(dyn StdError + 'static)> {
+        match self {
+            Self::Conflict(_) => None,
+            Self::InternalError(source) => Some(source.as_ref()),
+        }
+    }
+}
+
+impl From<Error> for EnqueueError {
+    fn from(err: Error) -> EnqueueError {
+        EnqueueError::InternalError(err)
+    }
+}
+
+///////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use crate::utils::TempDir;
+
+    use super::*;
+
+    fn task() -> UploadTask {
+        UploadTask {
+            id: Uuid::new_v4(),
+            backends: vec![String::from("yandex")],
+            bucket: String::from("bucket"),
+            object: String::from("object.webm"),
+            clips: vec![],
+            outputs: vec![],
+            partial: false,
+            until: None,
+        }
+    }
+
+    #[test]
+    fn persist_jobs() -> Result<()> {
+        let dir = TempDir::new("upload_queue_test")?;
+
+        let catalog = Arc::new(Catalog::new(&dir)?);
+        let queue = UploadQueue::new(Config::default(), &dir, catalog.clone())?;
+        let task = task();
+        let job = queue.enqueue(task.clone())?;
+
+        // Unfinished job for the same task gets reused but not for a different one.
+        assert_eq!(queue.enqueue(task.clone())?.id, job.id);
+
+        let other_task = UploadTask {
+            object: String::from("other.webm"),
+            ..task
+        };
+
+        assert!(matches!(
+            queue.enqueue(other_task),
+            Err(EnqueueError::Conflict(job_id)) if job_id == job.id
+        ));
+
+        queue.update(job.id, |job| job.set_state(JobState::Running));
+
+        let done_job = queue.enqueue(self::task())?;
+
+        queue.update(done_job.id, |job| {
+            job.set_state(JobState::Done);
+            job.updated_at = 0;
+        });
+
+        // After restart the interrupted job is queued again and the expired one is removed.
+        let queue = UploadQueue::new(Config::default(), &dir, catalog)?;
+        let job = queue.get(job.id)?.expect("Missing job");
+        assert_eq!(job.state, JobState::Queued);
+        assert!(queue.get(done_job.id)?.is_none());
+        assert!(!queue.job_path(done_job.id).exists());
+        assert_eq!(queue.receiver.try_recv().ok(), Some(job.id));
+        Ok(())
+    }
+}
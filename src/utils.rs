@@ -1,12 +1,17 @@
 #![allow(unused_macros)]
 
+use std::fs::{self, File};
+use std::io::{self, Write};
 use std::os::raw::{c_ulong, c_void};
+use std::path::{Path, PathBuf};
 
 use anyhow::{format_err, Context, Result};
 use janus::{JanssonDecodingFlags, JanssonEncodingFlags, JanssonValue};
 use serde::de::DeserializeOwned;
 use serde_json::Value;
 
+use crate::switchboard::StreamId;
+
 // Based on https://github.com/slog-rs/slog/blob/master/src/lib.rs#L750.
 macro_rules! log(
     // `2` means that `;` was already found
@@ -84,6 +89,83 @@ pub fn jansson_to_serde<T: DeserializeOwned>(json: &JanssonValue) -> Result<T> {
     serde_json::from_str(&json).context("Failed to parse JSON")
 }
 
+/// Replaces the file with `data` through a temporary file so that a crash or a power loss
+/// leaves either the old or the new file but not a partially written one.
+pub fn atomic_write(path: &Path, data: &[u8]) -> io::Result<()> {
+    let mut tmp_path = path.as_os_str().to_owned();
+    tmp_path.push(".tmp");
+
+    let mut file = File::create(&tmp_path)?;
+    file.write_all(data)?;
+    // Otherwise the rename may get to the disk before the data.
+    file.sync_all()?;
+    fs::rename(&tmp_path, path)
+}
+
+/// Returns record dirs of the recordings directory along with their stream IDs.
+/// Hidden dirs like upload jobs and anything else which isn't a record are skipped.
+pub fn record_dirs(recordings_dir: &Path) -> Result<impl Iterator<Item = (StreamId, PathBuf)>> {
+    let entries = fs::read_dir(recordings_dir).context("Failed to read recordings dir")?;
+
+    Ok(entries.filter_map(|entry| {
+        let path = match entry {
+            Ok(entry) => entry.path(),
+            Err(err) => {
+                err!("Failed to read recordings dir entry: {}", err);
+                return None;
+            }
+        };
+
+        let stream_id = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .and_then(|name| name.parse::<StreamId>().ok())?;
+
+        if path.is_dir() {
+            Some((stream_id, path))
+        } else {
+            None
+        }
+    }))
+}
+
+/// Removes the directory with all its contents if it exists.
+pub fn remove_dir(path: &Path) -> io::Result<()> {
+    match fs::remove_dir_all(path) {
+        Err(err) if err.kind() != io::ErrorKind::NotFound => Err(err),
+        _ => Ok(()),
+    }
+}
+
+/// Directory which gets removed with all its contents on drop so nothing is left there
+/// whichever way the work in it ends.
+pub struct ScratchDir(PathBuf);
+
+impl ScratchDir {
+    /// Leftovers of a previous run get removed first.
+    pub fn create(path: PathBuf) -> io::Result<Self> {
+        remove_dir(&path)?;
+        fs::create_dir(&path)?;
+        Ok(Self(path))
+    }
+}
+
+impl std::ops::Deref for ScratchDir {
+    type Target = Path;
+
+    fn deref(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl Drop for ScratchDir {
+    fn drop(&mut self) {
+        if let Err(err) = remove_dir(&self.0) {
+            err!("Failed to remove {}: {}", self.0.display(), err);
+        }
+    }
+}
+
 ////////////////////////////////////////////////////////////////////////////////
 
 #[allow(non_camel_case_types)]
@@ -92,3 +174,35 @@ pub struct janus_ice_handle {
     _session: *mut c_void,
     pub handle_id: c_ulong,
 }
+
+////////////////////////////////////////////////////////////////////////////////
+
+/// Temporary directory for tests which gets removed on drop even if the test panics.
+#[cfg(test)]
+pub struct TempDir(std::path::PathBuf);
+
+#[cfg(test)]
+impl TempDir {
+    pub fn new(prefix: &str) -> io::Result<Self> {
+        let name = format!("{}_{}", prefix, uuid::Uuid::new_v4());
+        let path = std::env::temp_dir().join(name);
+        fs::create_dir_all(&path)?;
+        Ok(Self(path))
+    }
+}
+
+#[cfg(test)]
+impl std::ops::Deref for TempDir {
+    type Target = Path;
+
+    fn deref(&self) -> &Path {
+        &self.0
+    }
+}
+
+#[cfg(test)]
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
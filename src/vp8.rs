@@ -0,0 +1,327 @@
+use std::collections::BTreeMap;
+
+use crate::rtp;
+
+/// Number of packets held back to put reordered ones in order.
+const REORDER_WINDOW: usize = 16;
+
+/// Checks whether the RTP packet carries the beginning of a VP8 keyframe.
+/// See RFC 7741 for payload descriptor and payload header layout.
+pub fn is_keyframe(packet: &[u8]) -> bool {
+    let header = rtp::payload(packet).and_then(|payload| {
+        if !is_partition_start(payload) {
+            return None;
+        }
+
+        payload.get(descriptor_len(payload)?).copied()
+    });
+
+    matches!(header, Some(header) if header & 0x01 == 0)
+}
+
+/// Returns `(width, height)` of a keyframe.
+pub fn frame_size(frame: &[u8]) -> Option<(u16, u16)> {
+    // Keyframes have a start code after the frame tag.
+    if frame.get(3..6)? != [0x9d, 0x01, 0x2a] {
+        return None;
+    }
+
+    let width = u16::from_le_bytes([frame[6], *frame.get(7)?]) & 0x3fff;
+    let height = u16::from_le_bytes([*frame.get(8)?, *frame.get(9)?]) & 0x3fff;
+    Some((width, height))
+}
+
+// Payload header is present only in the first packet of partition 0.
+fn is_partition_start(payload: &[u8]) -> bool {
+    matches!(payload.first(), Some(descriptor) if descriptor & 0x10 != 0 && descriptor & 0x07 == 0)
+}
+
+fn descriptor_len(payload: &[u8]) -> Option<usize> {
+    let descriptor = *payload.first()?;
+    let mut offset = 1;
+
+    if descriptor & 0x80 != 0 {
+        let extension = *payload.get(offset)?;
+        offset += 1;
+
+        // Picture ID is 7 or 15 bits long depending on M bit.
+        if extension & 0x80 != 0 {
+            offset += if *payload.get(offset)? & 0x80 != 0 {
+                2
+            } else {
+                1
+            };
+        }
+
+        // TL0PICIDX.
+        if extension & 0x40 != 0 {
+            offset += 1;
+        }
+
+        // TID/Y/KEYIDX.
+        if extension & 0x30 != 0 {
+            offset += 1;
+        }
+    }
+
+    if offset <= payload.len() {
+        Some(offset)
+    } else {
+        None
+    }
+}
+
+///////////////////////////////////////////////////////////////////////////////
+
+#[derive(Debug)]
+pub struct Frame {
+    timestamp: u32,
+    data: Vec<u8>,
+}
+
+impl Frame {
+    /// RTP timestamp of the frame.
+    pub fn timestamp(&self) -> u32 {
+        self.timestamp
+    }
+
+    pub fn into_data(self) -> Vec<u8> {
+        self.data
+    }
+
+    pub fn is_keyframe(&self) -> bool {
+        matches!(self.data.first(), Some(tag) if tag & 0x01 == 0)
+    }
+}
+
+/// Assembles VP8 frames from RTP packets.
+///
+/// Packets are stored in arrival order so a small window of them is held back
+/// to put them in sequence number order. Frames with lost packets are dropped
+/// along with the following ones up to the next keyframe since they can't be
+/// decoded without them. For the same reason the output starts with a keyframe.
+#[derive(Debug)]
+pub struct Depacketizer {
+    /// Packets held back by extended sequence numbers.
+    window: BTreeMap<u64, Vec<u8>>,
+    /// Sequence number of the last pushed packet and its extended one.
+    last_pushed: Option<(u16, u64)>,
+    last_seq: Option<u64>,
+    frame: Vec<u8>,
+    timestamp: Option<u32>,
+    is_valid: bool,
+    is_waiting_keyframe: bool,
+}
+
+impl Depacketizer {
+    pub fn new() -> Self {
+        Self {
+            window: BTreeMap::new(),
+            last_pushed: None,
+            last_seq: None,
+            frame: vec![],
+            timestamp: None,
+            is_valid: false,
+            is_waiting_keyframe: true,
+        }
+    }
+
+    /// Returns a frame when its last packet leaves the reordering window.
+    pub fn push(&mut self, packet: &[u8]) -> Option<Frame> {
+        let seq = rtp::sequence_number(packet)?;
+
+        let extended_seq = match self.last_pushed {
+            Some((last_seq, last_extended_seq)) => {
+                (last_extended_seq as i64 + seq.wrapping_sub(last_seq) as i16 as i64) as u64
+            }
+            // Leaves room for packets reordered before the first one.
+            None => u32::MAX as u64,
+        };
+
+        self.last_pushed = Some((seq, extended_seq));
+
+        // The packet is too late since its successors have already been assembled.
+        if matches!(self.last_seq, Some(last_seq) if extended_seq <= last_seq) {
+            return None;
+        }
+
+        self.window.insert(extended_seq, packet.to_vec());
+
+        if self.window.len() <= REORDER_WINDOW {
+            return None;
+        }
+
+        self.pop()
+    }
+
+    /// Assembles packets left in the window when there're no more ones.
+    /// Should be called until it returns `None`.
+    pub fn flush(&mut self) -> Option<Frame> {
+        while !self.window.is_empty() {
+            if let Some(frame) = self.pop() {
+                return Some(frame);
+            }
+        }
+
+        None
+    }
+
+    fn pop(&mut self) -> Option<Frame> {
+        let seq = *self.window.keys().next()?;
+        let packet = self.window.remove(&seq)?;
+        self.assemble(seq, &packet)
+    }
+
+    fn assemble(&mut self, seq: u64, packet: &[u8]) -> Option<Frame> {
+        let timestamp = rtp::timestamp(packet)?;
+        let payload = rtp::payload(packet)?;
+        let descriptor_len = descriptor_len(payload)?;
+        let is_start = is_partition_start(payload);
+        let is_continuous = self.last_seq.map(|last_seq| last_seq + 1) == Some(seq);
+        let mut is_lost = self.last_seq.is_some() && !is_continuous;
+        self.last_seq = Some(seq);
+
+        if is_start || self.timestamp != Some(timestamp) {
+            // Whatever has been collected before is incomplete.
+            is_lost |= self.is_valid || !is_start;
+            self.frame.clear();
+            self.timestamp = Some(timestamp);
+            self.is_valid = is_start;
+        }
+
+        if is_lost {
+            self.is_valid = false;
+            self.is_waiting_keyframe = true;
+        }
+
+        if !self.is_valid {
+            return None;
+        }
+
+        self.frame.extend_from_slice(&payload[descriptor_len..]);
+
+        if !rtp::marker(packet) {
+            return None;
+        }
+
+        self.is_valid = false;
+
+        let frame = Frame {
+            timestamp,
+            data: std::mem::take(&mut self.frame),
+        };
+
+        if self.is_waiting_keyframe {
+            if !frame.is_keyframe() {
+                return None;
+            }
+
+            self.is_waiting_keyframe = false;
+        }
+
+        Some(frame)
+    }
+}
+
+///////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RTP_HEADER: [u8; 12] = [0x80, 0x60, 0, 1, 0, 0, 0, 1, 0, 0, 0, 1];
+
+    fn packet(header: &[u8], payload: &[u8]) -> Vec<u8> {
+        let mut packet = header.to_vec();
+        packet.extend_from_slice(payload);
+        packet
+    }
+
+    fn rtp_packet(seq: u16, timestamp: u32, marker: bool, payload: &[u8]) -> Vec<u8> {
+        let mut header = RTP_HEADER;
+        header[1] |= if marker { 0x80 } else { 0 };
+        header[2..4].copy_from_slice(&seq.to_be_bytes());
+        header[4..8].copy_from_slice(&timestamp.to_be_bytes());
+        packet(&header, payload)
+    }
+
+    #[test]
+    fn detect_keyframe() {
+        // Minimal descriptor with S bit set.
+        assert!(is_keyframe(&packet(&RTP_HEADER, &[0x10, 0x00])));
+        assert!(!is_keyframe(&packet(&RTP_HEADER, &[0x10, 0x01])));
+
+        // Not a start of partition.
+        assert!(!is_keyframe(&packet(&RTP_HEADER, &[0x00, 0x00])));
+
+        // Extended descriptor with 15 bit picture id, TL0PICIDX and KEYIDX.
+        let payload = [0x90, 0xf0, 0x80, 0x01, 0x02, 0x03, 0x00];
+        assert!(is_keyframe(&packet(&RTP_HEADER, &payload)));
+
+        // Truncated descriptor.
+        assert!(!is_keyframe(&packet(&RTP_HEADER, &payload[..5])));
+    }
+
+    #[test]
+    fn skip_header_extension() {
+        let mut header = RTP_HEADER.to_vec();
+        header[0] |= 0x10;
+        header.extend_from_slice(&[0xbe, 0xde, 0x00, 0x01, 0x10, 0xff, 0x00, 0x00]);
+
+        assert!(is_keyframe(&packet(&header, &[0x10, 0x00])));
+        assert!(!is_keyframe(&packet(&header, &[0x10, 0x01])));
+    }
+
+    #[test]
+    fn depacketize() {
+        let keyframe_start = [0x10, 0x00, 0x00, 0x00, 0x9d, 0x01, 0x2a, 0x80, 0x02];
+
+        let packets = vec![
+            // Delta frame before the first keyframe is dropped.
+            rtp_packet(1, 0, true, &[0x10, 0x01]),
+            rtp_packet(2, 3000, false, &keyframe_start),
+            rtp_packet(3, 3000, true, &[0x00, 0xe0, 0x01]),
+            // Reordered packet.
+            rtp_packet(5, 6000, true, &[0x00, 0x05]),
+            rtp_packet(4, 6000, false, &[0x10, 0x01, 0x04]),
+            // The frame with a lost packet and the following delta frame are dropped.
+            rtp_packet(6, 9000, false, &[0x10, 0x01]),
+            rtp_packet(8, 9000, true, &[0x00, 0x02]),
+            rtp_packet(9, 12000, true, &[0x10, 0x01, 0x03]),
+            rtp_packet(10, 15000, true, &[0x10, 0x00, 0x0a]),
+            rtp_packet(11, 18000, true, &[0x10, 0x01, 0x0b]),
+        ];
+
+        let mut depacketizer = Depacketizer::new();
+        let mut frames = vec![];
+
+        for packet in &packets {
+            frames.extend(depacketizer.push(packet));
+        }
+
+        // Packets are held back until the window is full.
+        assert!(frames.is_empty());
+        frames.extend(std::iter::from_fn(|| depacketizer.flush()));
+
+        let frames = frames
+            .into_iter()
+            .map(|frame| (frame.timestamp(), frame.is_keyframe(), frame.into_data()))
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            frames,
+            vec![
+                (
+                    3000,
+                    true,
+                    vec![0x00, 0x00, 0x00, 0x9d, 0x01, 0x2a, 0x80, 0x02, 0xe0, 0x01]
+                ),
+                (6000, false, vec![0x01, 0x04, 0x05]),
+                (15000, true, vec![0x00, 0x0a]),
+                (18000, false, vec![0x01, 0x0b]),
+            ]
+        );
+
+        assert_eq!(frame_size(&frames[0].2), Some((640, 480)));
+    }
+}
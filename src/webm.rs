@@ -0,0 +1,471 @@
+use std::io::{self, Seek, SeekFrom, Write};
+
+///////////////////////////////////////////////////////////////////////////////
+
+const EBML: u32 = 0x1A45_DFA3;
+const EBML_VERSION: u32 = 0x4286;
+const EBML_READ_VERSION: u32 = 0x42F7;
+const EBML_MAX_ID_LENGTH: u32 = 0x42F2;
+const EBML_MAX_SIZE_LENGTH: u32 = 0x42F3;
+const DOC_TYPE: u32 = 0x4282;
+const DOC_TYPE_VERSION: u32 = 0x4287;
+const DOC_TYPE_READ_VERSION: u32 = 0x4285;
+const SEGMENT: u32 = 0x1853_8067;
+const SEEK_HEAD: u32 = 0x114D_9B74;
+const SEEK: u32 = 0x4DBB;
+const SEEK_ID: u32 = 0x53AB;
+const SEEK_POSITION: u32 = 0x53AC;
+const VOID: u32 = 0xEC;
+const INFO: u32 = 0x1549_A966;
+const TIMECODE_SCALE: u32 = 0x2A_D7B1;
+const MUXING_APP: u32 = 0x4D80;
+const WRITING_APP: u32 = 0x5741;
+const DURATION: u32 = 0x4489;
+const TRACKS: u32 = 0x1654_AE6B;
+const TRACK_ENTRY: u32 = 0xAE;
+const TRACK_NUMBER: u32 = 0xD7;
+const TRACK_UID: u32 = 0x73C5;
+const TRACK_TYPE: u32 = 0x83;
+const CODEC_ID: u32 = 0x86;
+const CODEC_PRIVATE: u32 = 0x63A2;
+const SEEK_PRE_ROLL: u32 = 0x56BB;
+const VIDEO: u32 = 0xE0;
+const PIXEL_WIDTH: u32 = 0xB0;
+const PIXEL_HEIGHT: u32 = 0xBA;
+const AUDIO: u32 = 0xE1;
+const SAMPLING_FREQUENCY: u32 = 0xB5;
+const CHANNELS: u32 = 0x9F;
+const CLUSTER: u32 = 0x1F43_B675;
+const TIMECODE: u32 = 0xE7;
+const SIMPLE_BLOCK: u32 = 0xA3;
+const CUES: u32 = 0x1C53_BB6B;
+const CUE_POINT: u32 = 0xBB;
+const CUE_TIME: u32 = 0xB3;
+const CUE_TRACK_POSITIONS: u32 = 0xB7;
+const CUE_TRACK: u32 = 0xF7;
+const CUE_CLUSTER_POSITION: u32 = 0xF1;
+
+const VIDEO_TRACK_TYPE: u64 = 1;
+const AUDIO_TRACK_TYPE: u64 = 2;
+
+// Timecodes are in milliseconds.
+const NANOSECONDS_PER_TIMECODE: u64 = 1_000_000;
+// Opus decoder needs 80 ms to converge after seeking.
+const OPUS_SEEK_PRE_ROLL: u64 = 80_000_000;
+const OPUS_SAMPLE_RATE: u32 = 48000;
+const OPUS_CHANNELS: u8 = 2;
+
+// Keeps relative block timecodes within i16 and seeking reasonably granular.
+const MAX_CLUSTER_DURATION: u64 = 5000;
+
+// 8 byte size vint with all value bits set which means unknown size.
+const UNKNOWN_SIZE: [u8; 8] = [0x01, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Track {
+    Video,
+    Audio,
+}
+
+impl Track {
+    fn number(self) -> u64 {
+        match self {
+            Self::Video => 1,
+            Self::Audio => 2,
+        }
+    }
+}
+
+/// Writes VP8 video and Opus audio frames to a WebM file.
+///
+/// Frames are expected in timecode order. Segment size and duration
+/// aren't known in advance so they get patched in `finish`. Cues for seeking
+/// get written at the end of the segment and referenced by the seek head
+/// which space is reserved for at the beginning.
+pub struct WebmWriter<W: Write + Seek> {
+    writer: W,
+    segment_size_offset: u64,
+    seek_head_offset: u64,
+    duration_offset: u64,
+    has_video: bool,
+    cluster: Vec<u8>,
+    cluster_timecode: Option<u64>,
+    /// Track to seek to the beginning of the current cluster by.
+    cluster_cue: Option<Track>,
+    /// `(timecode, track, cluster position)`.
+    cues: Vec<(u64, Track, u64)>,
+    last_timecode: u64,
+}
+
+impl<W: Write + Seek> WebmWriter<W> {
+    /// `video_size` is `(width, height)` of the video track if there is one.
+    pub fn new(mut writer: W, video_size: Option<(u16, u16)>, has_audio: bool) -> io::Result<Self> {
+        let mut header = vec![];
+        let mut ebml = vec![];
+        uint_element(&mut ebml, EBML_VERSION, 1);
+        uint_element(&mut ebml, EBML_READ_VERSION, 1);
+        uint_element(&mut ebml, EBML_MAX_ID_LENGTH, 4);
+        uint_element(&mut ebml, EBML_MAX_SIZE_LENGTH, 8);
+        element(&mut ebml, DOC_TYPE, b"webm");
+        uint_element(&mut ebml, DOC_TYPE_VERSION, 4);
+        uint_element(&mut ebml, DOC_TYPE_READ_VERSION, 2);
+        element(&mut header, EBML, &ebml);
+
+        write_id(&mut header, SEGMENT);
+        let segment_size_offset = header.len() as u64;
+        header.extend_from_slice(&UNKNOWN_SIZE);
+
+        // Patched with the actual seek head in `finish` which has the same size.
+        let seek_head_offset = header.len() as u64;
+        let seek_head_len = seek_head(0).len();
+        element(&mut header, VOID, &vec![0; seek_head_len - 2]);
+
+        let mut info = vec![];
+        uint_element(&mut info, TIMECODE_SCALE, NANOSECONDS_PER_TIMECODE);
+        element(&mut info, MUXING_APP, env!("CARGO_PKG_NAME").as_bytes());
+        element(&mut info, WRITING_APP, env!("CARGO_PKG_NAME").as_bytes());
+        // Duration is the last element so its offset is easy to calculate.
+        float_element(&mut info, DURATION, 0.0);
+        let duration_offset_in_info = info.len() - 8;
+        write_id(&mut header, INFO);
+        write_size(&mut header, info.len() as u64);
+        let duration_offset = (header.len() + duration_offset_in_info) as u64;
+        header.extend_from_slice(&info);
+
+        let mut tracks = vec![];
+
+        if let Some((width, height)) = video_size {
+            let mut entry = vec![];
+            uint_element(&mut entry, TRACK_NUMBER, Track::Video.number());
+            uint_element(&mut entry, TRACK_UID, Track::Video.number());
+            uint_element(&mut entry, TRACK_TYPE, VIDEO_TRACK_TYPE);
+            element(&mut entry, CODEC_ID, b"V_VP8");
+            let mut video = vec![];
+            uint_element(&mut video, PIXEL_WIDTH, width as u64);
+            uint_element(&mut video, PIXEL_HEIGHT, height as u64);
+            element(&mut entry, VIDEO, &video);
+            element(&mut tracks, TRACK_ENTRY, &entry);
+        }
+
+        if has_audio {
+            let mut entry = vec![];
+            uint_element(&mut entry, TRACK_NUMBER, Track::Audio.number());
+            uint_element(&mut entry, TRACK_UID, Track::Audio.number());
+            uint_element(&mut entry, TRACK_TYPE, AUDIO_TRACK_TYPE);
+            element(&mut entry, CODEC_ID, b"A_OPUS");
+            element(&mut entry, CODEC_PRIVATE, &opus_head());
+            uint_element(&mut entry, SEEK_PRE_ROLL, OPUS_SEEK_PRE_ROLL);
+            let mut audio = vec![];
+            float_element(&mut audio, SAMPLING_FREQUENCY, OPUS_SAMPLE_RATE as f64);
+            uint_element(&mut audio, CHANNELS, OPUS_CHANNELS as u64);
+            element(&mut entry, AUDIO, &audio);
+            element(&mut tracks, TRACK_ENTRY, &entry);
+        }
+
+        element(&mut header, TRACKS, &tracks);
+        writer.write_all(&header)?;
+
+        let start = writer.stream_position()? - header.len() as u64;
+
+        Ok(Self {
+            writer,
+            segment_size_offset: start + segment_size_offset,
+            seek_head_offset: start + seek_head_offset,
+            duration_offset: start + duration_offset,
+            has_video: video_size.is_some(),
+            cluster: vec![],
+            cluster_timecode: None,
+            cluster_cue: None,
+            cues: vec![],
+            last_timecode: 0,
+        })
+    }
+
+    /// Writes a frame with absolute `timecode` in milliseconds.
+    /// Timecodes going backwards are clamped to keep the file valid.
+    pub fn write_frame(
+        &mut self,
+        track: Track,
+        timecode: u64,
+        data: &[u8],
+        is_keyframe: bool,
+    ) -> io::Result<()> {
+        let timecode = timecode.max(self.last_timecode);
+
+        let cluster_timecode = match self.cluster_timecode {
+            Some(cluster_timecode)
+                if timecode - cluster_timecode < MAX_CLUSTER_DURATION
+                    && !(track == Track::Video && is_keyframe) =>
+            {
+                cluster_timecode
+            }
+            _ => {
+                self.flush_cluster()?;
+                uint_element(&mut self.cluster, TIMECODE, timecode);
+                self.cluster_timecode = Some(timecode);
+
+                // Seeking to a video frame other than keyframe breaks decoding.
+                self.cluster_cue = match track {
+                    Track::Video if is_keyframe => Some(track),
+                    Track::Audio if !self.has_video => Some(track),
+                    _ => None,
+                };
+
+                timecode
+            }
+        };
+
+        let mut block = vec![];
+        write_size(&mut block, track.number());
+        block.extend_from_slice(&((timecode - cluster_timecode) as i16).to_be_bytes());
+        block.push(if is_keyframe { 0x80 } else { 0x00 });
+        block.extend_from_slice(data);
+        element(&mut self.cluster, SIMPLE_BLOCK, &block);
+
+        self.last_timecode = timecode;
+        Ok(())
+    }
+
+    /// Timecode of the last written frame in milliseconds.
+    pub fn last_timecode(&self) -> u64 {
+        self.last_timecode
+    }
+
+    pub fn finish(mut self) -> io::Result<W> {
+        self.flush_cluster()?;
+
+        if !self.cues.is_empty() {
+            let mut cues = vec![];
+
+            for (timecode, track, position) in &self.cues {
+                let mut positions = vec![];
+                uint_element(&mut positions, CUE_TRACK, track.number());
+                uint_element(&mut positions, CUE_CLUSTER_POSITION, *position);
+                let mut point = vec![];
+                uint_element(&mut point, CUE_TIME, *timecode);
+                element(&mut point, CUE_TRACK_POSITIONS, &positions);
+                element(&mut cues, CUE_POINT, &point);
+            }
+
+            let mut buf = vec![];
+            element(&mut buf, CUES, &cues);
+            let cues_position = self.segment_position()?;
+            self.writer.write_all(&buf)?;
+            self.writer.seek(SeekFrom::Start(self.seek_head_offset))?;
+            self.writer.write_all(&seek_head(cues_position))?;
+            self.writer.seek(SeekFrom::End(0))?;
+        }
+
+        let end = self.writer.stream_position()?;
+        let segment_size = end - self.segment_size_offset - UNKNOWN_SIZE.len() as u64;
+        let mut size = [0x01, 0, 0, 0, 0, 0, 0, 0];
+        size[1..].copy_from_slice(&segment_size.to_be_bytes()[1..]);
+
+        self.writer
+            .seek(SeekFrom::Start(self.segment_size_offset))?;
+        self.writer.write_all(&size)?;
+        self.writer.seek(SeekFrom::Start(self.duration_offset))?;
+        self.writer
+            .write_all(&(self.last_timecode as f64).to_be_bytes())?;
+        self.writer.seek(SeekFrom::Start(end))?;
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+
+    fn flush_cluster(&mut self) -> io::Result<()> {
+        if self.cluster.is_empty() {
+            return Ok(());
+        }
+
+        if let (Some(track), Some(timecode)) = (self.cluster_cue, self.cluster_timecode) {
+            let position = self.segment_position()?;
+            self.cues.push((timecode, track, position));
+        }
+
+        let mut header = vec![];
+        write_id(&mut header, CLUSTER);
+        write_size(&mut header, self.cluster.len() as u64);
+        self.writer.write_all(&header)?;
+        self.writer.write_all(&self.cluster)?;
+        self.cluster.clear();
+        Ok(())
+    }
+
+    // Positions of top level elements are relative to the beginning of the segment data.
+    fn segment_position(&mut self) -> io::Result<u64> {
+        let position = self.writer.stream_position()?;
+        Ok(position - self.segment_size_offset - UNKNOWN_SIZE.len() as u64)
+    }
+}
+
+// Seek position is always 8 bytes long to keep the size the same as of the placeholder.
+fn seek_head(cues_position: u64) -> Vec<u8> {
+    let mut seek = vec![];
+    element(&mut seek, SEEK_ID, &CUES.to_be_bytes());
+    element(&mut seek, SEEK_POSITION, &cues_position.to_be_bytes());
+    let mut seek_head = vec![];
+    element(&mut seek_head, SEEK, &seek);
+    let mut buf = vec![];
+    element(&mut buf, SEEK_HEAD, &seek_head);
+    buf
+}
+
+/// Opus identification header which is the same for WebM and Ogg.
+pub fn opus_head() -> Vec<u8> {
+    let mut head = b"OpusHead".to_vec();
+    head.push(1);
+    head.push(OPUS_CHANNELS);
+    // Pre-skip.
+    head.extend_from_slice(&0u16.to_le_bytes());
+    head.extend_from_slice(&OPUS_SAMPLE_RATE.to_le_bytes());
+    // Output gain.
+    head.extend_from_slice(&0i16.to_le_bytes());
+    // Channel mapping family.
+    head.push(0);
+    head
+}
+
+///////////////////////////////////////////////////////////////////////////////
+
+fn write_id(buf: &mut Vec<u8>, id: u32) {
+    let bytes = id.to_be_bytes();
+    let leading_zeros = (id.leading_zeros() / 8) as usize;
+    buf.extend_from_slice(&bytes[leading_zeros..]);
+}
+
+fn write_size(buf: &mut Vec<u8>, size: u64) {
+    // Value with all bits set is reserved for unknown size.
+    let mut length = 1;
+
+    while length < 8 && size >= (1 << (7 * length)) - 1 {
+        length += 1;
+    }
+
+    let marked = size | (1 << (7 * length));
+    buf.extend_from_slice(&marked.to_be_bytes()[8 - length..]);
+}
+
+fn element(buf: &mut Vec<u8>, id: u32, payload: &[u8]) {
+    write_id(buf, id);
+    write_size(buf, payload.len() as u64);
+    buf.extend_from_slice(payload);
+}
+
+fn uint_element(buf: &mut Vec<u8>, id: u32, value: u64) {
+    let bytes = value.to_be_bytes();
+    let leading_zeros = ((value.leading_zeros() / 8) as usize).min(7);
+    element(buf, id, &bytes[leading_zeros..]);
+}
+
+fn float_element(buf: &mut Vec<u8>, id: u32, value: f64) {
+    element(buf, id, &value.to_be_bytes());
+}
+
+///////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+
+    #[test]
+    fn encode_vints() {
+        let mut buf = vec![];
+        write_size(&mut buf, 1);
+        write_size(&mut buf, 127);
+        write_size(&mut buf, 300);
+        assert_eq!(buf, vec![0x81, 0x40, 0x7f, 0x41, 0x2c]);
+
+        let mut buf = vec![];
+        uint_element(&mut buf, TIMECODE_SCALE, NANOSECONDS_PER_TIMECODE);
+        assert_eq!(buf, vec![0x2a, 0xd7, 0xb1, 0x83, 0x0f, 0x42, 0x40]);
+
+        let mut buf = vec![];
+        uint_element(&mut buf, TIMECODE, 0);
+        assert_eq!(buf, vec![0xe7, 0x81, 0x00]);
+    }
+
+    #[test]
+    fn write_file() -> io::Result<()> {
+        let mut writer = WebmWriter::new(Cursor::new(vec![]), Some((640, 480)), true)?;
+        writer.write_frame(Track::Video, 0, &[0x10, 0x02], true)?;
+        writer.write_frame(Track::Audio, 10, &[0xfc], true)?;
+        writer.write_frame(Track::Video, 6000, &[0x11], false)?;
+        writer.write_frame(Track::Video, 7000, &[0x10, 0x03], true)?;
+        let buf = writer.finish()?.into_inner();
+
+        assert_eq!(&buf[..4], &[0x1a, 0x45, 0xdf, 0xa3]);
+
+        // Segment size covers everything after its header.
+        let segment_offset = buf
+            .windows(4)
+            .position(|window| window == [0x18, 0x53, 0x80, 0x67])
+            .expect("Missing segment");
+
+        let mut size = [0; 8];
+        size[1..].copy_from_slice(&buf[segment_offset + 5..segment_offset + 12]);
+        assert_eq!(buf[segment_offset + 4], 0x01);
+        assert_eq!(
+            u64::from_be_bytes(size),
+            (buf.len() - segment_offset - 12) as u64
+        );
+
+        // Duration is patched with the last timecode.
+        let duration_offset = buf
+            .windows(3)
+            .position(|window| window == [0x44, 0x89, 0x88])
+            .expect("Missing duration");
+
+        let mut duration = [0; 8];
+        duration.copy_from_slice(&buf[duration_offset + 3..duration_offset + 11]);
+        assert_eq!(f64::from_be_bytes(duration), 7000.0);
+
+        // The frame exceeding cluster duration and the keyframe start new clusters.
+        let clusters = buf
+            .windows(4)
+            .enumerate()
+            .filter(|(_, window)| *window == [0x1f, 0x43, 0xb6, 0x75])
+            .map(|(offset, _)| (offset - segment_offset - 12) as u64)
+            .collect::<Vec<_>>();
+
+        assert_eq!(clusters.len(), 3);
+
+        // Clusters starting with keyframes only get cues which the seek head refers to.
+        // The seek head contains cues ID too.
+        let cues_offset = buf
+            .windows(4)
+            .rposition(|window| window == [0x1c, 0x53, 0xbb, 0x6b])
+            .expect("Missing cues");
+
+        let mut cue_points = vec![];
+
+        for (timecode, position) in &[(0, clusters[0]), (7000, clusters[2])] {
+            let mut positions = vec![];
+            uint_element(&mut positions, CUE_TRACK, 1);
+            uint_element(&mut positions, CUE_CLUSTER_POSITION, *position);
+            let mut point = vec![];
+            uint_element(&mut point, CUE_TIME, *timecode);
+            element(&mut point, CUE_TRACK_POSITIONS, &positions);
+            element(&mut cue_points, CUE_POINT, &point);
+        }
+
+        let mut cues = vec![];
+        element(&mut cues, CUES, &cue_points);
+        assert_eq!(&buf[cues_offset..], &cues[..]);
+
+        let seek_position_offset = buf
+            .windows(3)
+            .position(|window| window == [0x53, 0xac, 0x88])
+            .expect("Missing seek position");
+
+        let mut position = [0; 8];
+        position.copy_from_slice(&buf[seek_position_offset + 3..seek_position_offset + 11]);
+        assert_eq!(
+            u64::from_be_bytes(position),
+            (cues_offset - segment_offset - 12) as u64
+        );
+
+        Ok(())
+    }
+}